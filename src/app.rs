@@ -22,13 +22,13 @@ use crate::enemy::EnemyPlugin;
 use crate::map::{MapPlugin, SCREEN};
 use crate::multiplayer::UdpClientPlugin;
 use crate::multiplayer::UdpServerPlugin;
+use crate::multiplayer::transport::NetTransport;
 use crate::util::DevModePlugin;
 
 use crate::game_ui::UIPlugin;
 
 use crate::physics::rope_force::{
-    RopeGeometry, apply_rope_geometry, compute_rope_geometry, init_ropes, rope_force_to_system,
-    rope_tension_system,
+    RopeGeometry, apply_rope_geometry, compute_rope_geometry, init_ropes,
 };
 use crate::player::load_players::spawn_players;
 
@@ -53,8 +53,25 @@ pub enum GameMode {
     LocalWithNpc(usize), // main player p1 with ai player 2.
     AiWithAi,            // main player p1 with ai player 2.
     NetCoop(usize),
+    // watching a net game, following either climber.
+    Spectate,
     Simulated,
 }
+
+impl GameMode {
+    // a slot with no mode means net coop, like the old --p1/--p2 flags.
+    pub fn from_launch(config: &LaunchConfig) -> Option<Self> {
+        let slot = config.slot.unwrap_or(0);
+        match config.mode {
+            Some(LaunchMode::Net) => Some(GameMode::NetCoop(slot)),
+            Some(LaunchMode::Local) => Some(GameMode::LocalCoop),
+            Some(LaunchMode::Npc) => Some(GameMode::LocalWithNpc(slot)),
+            Some(LaunchMode::Ai) => Some(GameMode::AiWithAi),
+            Some(LaunchMode::Spectate) => Some(GameMode::Spectate),
+            None => config.slot.map(GameMode::NetCoop),
+        }
+    }
+}
 #[derive(Resource, Deref, DerefMut)]
 struct botTimer {
     time: Timer,
@@ -202,18 +219,12 @@ impl Plugin for InGameSystems {
     fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
-            (
-                rope_tension_system,
-                rope_force_to_system,
-                compute_rope_geometry,
-                apply_rope_geometry,
-            )
-                .run_if(in_state(MyAppState::InGame)),
+            (compute_rope_geometry, apply_rope_geometry).run_if(in_state(MyAppState::InGame)),
         );
     }
 }
 
-pub fn run(player_number: Option<usize>) {
+pub fn run(config: LaunchConfig) {
     let mut app = App::new();
 
     #[cfg(all(feature = "client", debug_assertions))]
@@ -233,19 +244,19 @@ pub fn run(player_number: Option<usize>) {
         app.add_event::<RLAction>();
         app.add_event::<RLAction2>();
 
-        // sets the gamemode via command line flags
-        // if let Some(player_number) = player_number {
-        //     app.insert_resource(GameMode::NetCoop(player_number));
-        // }
-        // else {
-        //     app.insert_resource(GameMode::LocalCoop);
-        // }
+        // a mode from the command line / config file skips the main menu.
+        if let Some(mode) = GameMode::from_launch(&config) {
+            if matches!(mode, GameMode::LocalWithNpc(_) | GameMode::AiWithAi) {
+                app.insert_resource(BotActive(true));
+            }
+            app.insert_resource(mode);
+        }
 
         app.add_plugins(UdpClientPlugin {
-            // server_addr: "127.0.0.1:5000".to_string(), // localhost
-            // server_addr: "home.tailaaef65.ts.net:5000".to_string(), // hostname magic dns.
-            // server_addr: "100.110.71.63:5000".to_string(), // tailscaled.
-            server_addr: "3.22.185.76:5000".to_string(),
+            server_addr: config.server_socket_addr(),
+            room: config.room,
+            link: config.link,
+            transport: NetTransport::Udp,
         });
 
         let asset_server = app.world().get_resource::<AssetServer>().unwrap().clone();
@@ -257,38 +268,74 @@ pub fn run(player_number: Option<usize>) {
             main_menu: asset_server.load("mainMenu.png"),
         };
         app.insert_resource(game_assets);
+
+        if app.world().contains_resource::<GameMode>() {
+            app.insert_state(MyAppState::InGame);
+        } else {
+            app.insert_state(MyAppState::MainMenu);
+        }
+        app.add_systems(OnEnter(MyAppState::InGame), init_ropes.after(spawn_players));
+
+        add_game(&mut app);
     }
 
+    // the server process only owns the socket. every match runs in a room
+    // app of its own, built by room_app.
     #[cfg(feature = "server")]
     {
         app.add_plugins(MinimalPlugins);
-        app.add_plugins(bevy::state::app::StatesPlugin);
-        app.add_plugins(bevy::input::InputPlugin);
+        // ctrl-c exits through AppExit, so clients hear about it and the
+        // network runtime shuts down cleanly.
+        app.add_plugins(bevy::app::TerminalCtrlCHandlerPlugin);
+        app.add_plugins(UdpServerPlugin {
+            bind_addr: config.bind_socket_addr(),
+            new_room: room_app,
+            link: config.link,
+            transport: NetTransport::Udp,
+            ai_partner: config.ai_partner,
+        });
+    }
 
-        app.insert_resource(GameMode::Simulated);
-        app.add_plugins(UdpServerPlugin);
+    app.insert_resource(config);
+    app.run();
+}
 
-        let game_assets = GameAssets {
-            fish: dummy(),
-            background: dummy(),
-            tile_fg: dummy(),
-            entity: dummy(),
-            main_menu: dummy(),
-        };
-        app.insert_resource(game_assets);
-    }
+// one server room: a headless copy of the game simulating both climbers.
+#[cfg(feature = "server")]
+pub fn room_app() -> App {
+    headless_app(GameMode::Simulated)
+}
 
-    #[cfg(feature = "server")]
-    {
-        app.insert_state(MyAppState::InGame);
-        app.add_systems(Startup, init_ropes.after(spawn_players));
-    }
-    #[cfg(feature = "client")]
-    {
-        app.insert_state(MyAppState::MainMenu);
-        app.add_systems(OnEnter(MyAppState::InGame), init_ropes.after(spawn_players));
-    }
+// the game without a window or assets, for server rooms and for the clients
+// in multiplayer::harness.
+#[cfg(feature = "server")]
+pub fn headless_app(mode: GameMode) -> App {
+    let mut app = App::new();
+
+    app.add_plugins(MinimalPlugins);
+    app.add_plugins(bevy::state::app::StatesPlugin);
+    app.add_plugins(bevy::input::InputPlugin);
 
+    app.insert_resource(mode);
+
+    let game_assets = GameAssets {
+        fish: dummy(),
+        background: dummy(),
+        tile_fg: dummy(),
+        entity: dummy(),
+        main_menu: dummy(),
+    };
+    app.insert_resource(game_assets);
+
+    app.insert_state(MyAppState::InGame);
+    app.add_systems(OnEnter(MyAppState::InGame), init_ropes.after(spawn_players));
+
+    add_game(&mut app);
+    app
+}
+
+// everything the client and a server room share.
+fn add_game(app: &mut App) {
     app.enable_state_scoped_entities::<MyAppState>();
 
     app.insert_resource(Time::<Fixed>::from_hz(60.0))
@@ -316,6 +363,4 @@ pub fn run(player_number: Option<usize>) {
         .add_systems(Update, update_camera.run_if(in_state(MyAppState::InGame)))
         .insert_resource(RopeGeometry::default())
         .add_plugins(InGameSystems);
-
-    app.run();
 }
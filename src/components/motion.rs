@@ -38,7 +38,7 @@ pub struct ControlForce (pub Vec2);
 #[derive(Component, Clone, Copy, Debug)]
 pub struct RopeForce (pub Vec2);
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 pub struct JumpController {
     pub is_jumping: bool,
     pub jump_time_elapsed: f32,
@@ -63,7 +63,7 @@ impl Default for JumpController {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 pub struct GroundState {
     pub is_grounded: bool,
     pub coyote_timer: Timer,
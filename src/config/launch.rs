@@ -0,0 +1,308 @@
+// Launch configuration from the command line and an optional config file.
+
+use bevy::prelude::*;
+use serde::Deserialize;
+use serde::de::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use crate::multiplayer::conditioner::LinkConditions;
+use crate::multiplayer::protocol::RoomRequest;
+
+// picked up from the working directory when no --config is given.
+pub const DEFAULT_CONFIG_FILE: &str = "katsuo.json";
+
+pub const USAGE: &str = "\
+usage: bevy-katsuo [options]
+
+  --config <file>    read settings from a json config file (default: ./katsuo.json if present)
+  --bind <addr>      server: address to listen on (default 0.0.0.0)
+  --port <port>      server: port to listen on, client: port used when --server has none (default 5000)
+  --server <host[:port]>
+                     client: server to connect to
+  --room <id|new|any>
+                     client: join room <id>, open a new room, or take any free slot (default any)
+  --slot <1|2>       preferred player slot
+  --p1, --p2         same as --slot 1 / --slot 2
+  --mode <mode>      skip the main menu: net, local, npc, ai or spectate
+                     (a slot without a mode starts net coop)
+  --ai-partner       server: an AI climber fills any player slot no client holds
+  -h, --help         print this message
+
+command line flags override the config file. network conditions for testing
+can only be set there, e.g.
+
+  { \"link\": { \"latency_ms\": 80, \"jitter_ms\": 20, \"loss\": 0.05,
+              \"duplicate\": 0.01, \"reorder\": 0.02,
+              \"outage_every_ms\": 10000, \"outage_ms\": 1500 } }";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchMode {
+    Net,
+    Local,
+    Npc,
+    Ai,
+    Spectate,
+}
+
+impl std::str::FromStr for LaunchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "net" => Ok(Self::Net),
+            "local" => Ok(Self::Local),
+            "npc" => Ok(Self::Npc),
+            "ai" => Ok(Self::Ai),
+            "spectate" => Ok(Self::Spectate),
+            _ => Err(format!(
+                "unknown mode `{s}` (expected net, local, npc, ai or spectate)"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchConfig {
+    pub bind_addr: String,
+    pub port: u16,
+    pub server_addr: String,
+    #[serde(deserialize_with = "room_from_json")]
+    pub room: RoomRequest,
+    // 0-based internally; the cli and config file count from 1.
+    #[serde(deserialize_with = "slot_from_one")]
+    pub slot: Option<usize>,
+    pub mode: Option<LaunchMode>,
+    // link conditioner applied to everything this side sends.
+    pub link: LinkConditions,
+    // server only, see multiplayer::ai_partner.
+    pub ai_partner: bool,
+}
+
+impl Default for LaunchConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "0.0.0.0".to_string(),
+            port: 5000,
+            server_addr: "3.22.185.76".to_string(),
+            room: RoomRequest::Any,
+            slot: None,
+            mode: None,
+            link: LinkConditions::default(),
+            ai_partner: false,
+        }
+    }
+}
+
+fn slot_from_one<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<usize>, D::Error> {
+    let slot = Option::<usize>::deserialize(d)?;
+    slot.map(|s| parse_slot(&s.to_string()).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+// a room id, "new" or "any".
+fn room_from_json<'de, D: serde::Deserializer<'de>>(d: D) -> Result<RoomRequest, D::Error> {
+    match serde_json::Value::deserialize(d)? {
+        serde_json::Value::Number(n) => n
+            .as_u64()
+            .and_then(|id| u32::try_from(id).ok())
+            .map(RoomRequest::Join)
+            .ok_or_else(|| D::Error::custom(format!("invalid room id {n}"))),
+        serde_json::Value::String(s) => parse_room(&s).map_err(D::Error::custom),
+        _ => Err(D::Error::custom("expected a room id, \"new\" or \"any\"")),
+    }
+}
+
+fn parse_room(s: &str) -> Result<RoomRequest, String> {
+    match s {
+        "any" => Ok(RoomRequest::Any),
+        "new" => Ok(RoomRequest::Create),
+        _ => s
+            .parse()
+            .map(RoomRequest::Join)
+            .map_err(|_| format!("invalid room `{s}` (expected a room id, new or any)")),
+    }
+}
+
+fn parse_slot(s: &str) -> Result<usize, String> {
+    match s {
+        "1" => Ok(0),
+        "2" => Ok(1),
+        _ => Err(format!("invalid slot `{s}` (expected 1 or 2)")),
+    }
+}
+
+// what main should do with the command line.
+#[derive(Debug)]
+pub enum LaunchArgs {
+    Run(LaunchConfig),
+    Help,
+}
+
+impl LaunchConfig {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        serde_json::from_str(&text).map_err(|e| format!("failed to parse {}: {e}", path.display()))
+    }
+
+    // args without the binary name.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<LaunchArgs, String> {
+        let args: Vec<String> = args.into_iter().collect();
+
+        // the config file is the base layer, so find it before anything else.
+        let config_flag = args.iter().position(|a| a == "--config");
+        let mut config = match config_flag {
+            Some(i) => {
+                let path = args.get(i + 1).ok_or("--config needs a value")?;
+                Self::from_file(Path::new(path))?
+            }
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(LaunchArgs::Help),
+                "--config" => {
+                    value()?;
+                }
+                "--bind" => config.bind_addr = value()?,
+                "--port" => {
+                    let port = value()?;
+                    config.port = port.parse().map_err(|_| format!("invalid port `{port}`"))?;
+                }
+                "--server" => config.server_addr = value()?,
+                "--room" => config.room = parse_room(&value()?)?,
+                "--slot" => config.slot = Some(parse_slot(&value()?)?),
+                "--p1" => config.slot = Some(0),
+                "--p2" => config.slot = Some(1),
+                "--mode" => config.mode = Some(value()?.parse()?),
+                "--ai-partner" => config.ai_partner = true,
+                _ => return Err(format!("unknown argument `{arg}`")),
+            }
+        }
+
+        Ok(LaunchArgs::Run(config))
+    }
+
+    pub fn bind_socket_addr(&self) -> String {
+        format!("{}:{}", self.bind_addr, self.port)
+    }
+
+    // server_addr with the configured port filled in when it has none.
+    pub fn server_socket_addr(&self) -> String {
+        let addr = self.server_addr.as_str();
+        if let Ok(ip) = addr.parse::<IpAddr>() {
+            return SocketAddr::new(ip, self.port).to_string();
+        }
+        let has_port = addr
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        if has_port {
+            addr.to_string()
+        } else {
+            format!("{addr}:{}", self.port)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<LaunchConfig, String> {
+        match LaunchConfig::from_args(args.iter().map(|a| a.to_string()))? {
+            LaunchArgs::Run(config) => Ok(config),
+            LaunchArgs::Help => Err("help".to_string()),
+        }
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let config = parse(&[
+            "--bind",
+            "127.0.0.1",
+            "--port",
+            "6000",
+            "--server",
+            "localhost",
+            "--p2",
+            "--mode",
+            "net",
+        ])
+        .unwrap();
+        assert_eq!(config.bind_socket_addr(), "127.0.0.1:6000");
+        assert_eq!(config.server_socket_addr(), "localhost:6000");
+        assert_eq!(config.slot, Some(1));
+        assert_eq!(config.mode, Some(LaunchMode::Net));
+        assert_eq!(config.room, RoomRequest::Any);
+        assert!(!config.ai_partner);
+
+        assert_eq!(
+            parse(&["--room", "12"]).unwrap().room,
+            RoomRequest::Join(12)
+        );
+        assert_eq!(parse(&["--room", "new"]).unwrap().room, RoomRequest::Create);
+        assert_eq!(
+            parse(&["--mode", "spectate"]).unwrap().mode,
+            Some(LaunchMode::Spectate)
+        );
+        assert!(parse(&["--ai-partner"]).unwrap().ai_partner);
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert!(parse(&["--port"]).is_err());
+        assert!(parse(&["--port", "lots"]).is_err());
+        assert!(parse(&["--slot", "3"]).is_err());
+        assert!(parse(&["--mode", "solo"]).is_err());
+        assert!(parse(&["--room", "lobby"]).is_err());
+        assert!(parse(&["--whatever"]).is_err());
+    }
+
+    #[test]
+    fn server_port_is_only_added_when_missing() {
+        let mut config = LaunchConfig::default();
+        for (addr, expected) in [
+            ("10.0.0.1:7000", "10.0.0.1:7000"),
+            ("10.0.0.1", "10.0.0.1:5000"),
+            ("[::1]:7000", "[::1]:7000"),
+            ("::1", "[::1]:5000"),
+            ("example.com", "example.com:5000"),
+        ] {
+            config.server_addr = addr.to_string();
+            assert_eq!(config.server_socket_addr(), expected);
+        }
+    }
+
+    #[test]
+    fn config_file_uses_one_based_slots() {
+        let config: LaunchConfig =
+            serde_json::from_str(r#"{ "port": 5001, "slot": 2, "mode": "ai" }"#).unwrap();
+        assert_eq!(config.port, 5001);
+        assert_eq!(config.slot, Some(1));
+        assert_eq!(config.mode, Some(LaunchMode::Ai));
+        assert_eq!(config.bind_addr, "0.0.0.0");
+
+        let config: LaunchConfig = serde_json::from_str(r#"{ "room": 3 }"#).unwrap();
+        assert_eq!(config.room, RoomRequest::Join(3));
+        let config: LaunchConfig = serde_json::from_str(r#"{ "room": "new" }"#).unwrap();
+        assert_eq!(config.room, RoomRequest::Create);
+
+        assert!(serde_json::from_str::<LaunchConfig>(r#"{ "slot": 0 }"#).is_err());
+        assert!(serde_json::from_str::<LaunchConfig>(r#"{ "prot": 1 }"#).is_err());
+
+        let config: LaunchConfig =
+            serde_json::from_str(r#"{ "link": { "latency_ms": 80, "loss": 0.1 } }"#).unwrap();
+        assert_eq!(config.link.latency_ms, 80);
+        assert_eq!(config.link.loss, 0.1);
+        assert_eq!(config.link.jitter_ms, 0);
+    }
+}
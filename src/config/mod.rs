@@ -3,10 +3,12 @@
 // Author: Tingxu Chen <tic128@pitt.edu>
 // Description: <Configuration module>
 
+pub mod launch;
 pub mod player;
 pub mod physics;
 pub mod states;
 
+pub use launch::*;
 pub use player::*;
 pub use physics::*;
 
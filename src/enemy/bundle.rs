@@ -13,6 +13,8 @@ pub struct EnemyCollider {
 pub struct EnemyMovement {
     pub speed: f32,
     pub down: bool,
+    // how far it moved last tick, swept for collisions.
+    pub last_move: Vec2,
 }
 
 #[derive(Bundle)]
@@ -31,7 +33,7 @@ impl EnemyBundle {
             collider: EnemyCollider {
                 aabb: Aabb2d::new(Vec2::ZERO, Vec2::new(16.0, 16.0)),
             },
-            movement: EnemyMovement{ speed: 150.0 , down: true },
+            movement: EnemyMovement{ speed: 150.0 , down: true, last_move: Vec2::ZERO },
             transform: Transform::from_xyz(x, y, 0.0),
             sprite: Sprite {
                 color: Color::srgb(1.0, 0.0, 0.0),
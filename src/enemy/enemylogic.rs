@@ -11,15 +11,16 @@ pub fn spawn_enemy_system(mut commands: Commands) {
 
 pub fn update_enemy_system(
     time: Res<Time>,
-    mut query: Query<(&mut Transform, &EnemyMovement), With<Enemy>>,
+    mut query: Query<(&mut Transform, &mut EnemyMovement), With<Enemy>>,
 ) {
-    for (mut transform, movement) in query.iter_mut() {
+    for (mut transform, mut movement) in query.iter_mut() {
         let change = movement.speed * time.delta_secs();
         // Move enemy based on direction and speed
-        if movement.down {
-            transform.translation.y -= change;
+        movement.last_move = if movement.down {
+            Vec2::new(0.0, -change)
         } else {
-            transform.translation.y += change;
-        }
+            Vec2::new(0.0, change)
+        };
+        transform.translation += movement.last_move.extend(0.0);
     }
 }
@@ -6,6 +6,7 @@ pub mod enemylogic;
 use enemylogic::{spawn_enemy_system, update_enemy_system};
 
 use crate::config::MyAppState;
+use crate::physics::broadphase::update_broadphase_system;
 use crate::physics::collision::{
     enemy_platform_collision_system, enemy_player_collision_system,
     on_enemy_platform_collision_system, on_enemy_player_collision_system,
@@ -25,6 +26,7 @@ impl Plugin for EnemyPlugin {
                 enemy_platform_collision_system,
             )
                 .chain()
+                .after(update_broadphase_system)
                 .run_if(in_state(MyAppState::InGame)),
         );
     }
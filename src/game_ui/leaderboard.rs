@@ -60,6 +60,10 @@ pub fn update_leaderboard(
         GameMode::NetCoop(local_player_number) => {
             "Player"
         }
+        // watched, not played.
+        GameMode::Spectate => {
+            return;
+        }
         GameMode::Simulated => {
             "Simulated"
         }
@@ -1,9 +1,11 @@
 use crate::config::MyAppState;
 use bevy::prelude::*;
 pub mod leaderboard;
+pub mod netgraph;
 pub mod ui;
 
 use leaderboard::*;
+use netgraph::*;
 use ui::*;
 
 pub struct UIPlugin;
@@ -12,6 +14,7 @@ impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(TotalCoin { amount: 0 })
             .insert_resource(MaxHeight { amount: 0 })
+            .init_resource::<NetgraphVisible>()
             .add_systems(Startup, load_ui_camera)
             .add_systems(OnEnter(MyAppState::InGame), load_ui_game)
             .add_systems(OnEnter(MyAppState::MainMenu), load_main_menu)
@@ -25,10 +28,16 @@ impl Plugin for UIPlugin {
             )
             .add_systems(Update, update_height.run_if(in_state(MyAppState::InGame)))
             .add_systems(Update, update_ui.run_if(in_state(MyAppState::InGame)))
+            .add_systems(Update, (toggle_netgraph, update_netgraph).chain())
             .add_systems(OnExit(MyAppState::MainMenu), despawn_ui)
             .add_systems(OnExit(MyAppState::InGame), despawn_ui)
             .add_systems(OnExit(MyAppState::EndCredit), despawn_ui)
-            .add_systems(Update, game_death.run_if(in_state(MyAppState::InGame)))
+            .add_systems(
+                Update,
+                game_death
+                    .run_if(in_state(MyAppState::InGame))
+                    .run_if(decides_game_over),
+            )
             .add_systems(OnEnter(MyAppState::EndCredit), update_leaderboard)
             .add_systems(OnEnter(MyAppState::EndCredit), load_ui_leaderboard)
             .add_systems(
@@ -0,0 +1,125 @@
+// Netgraph overlay, toggled with F3.
+//
+// the numbers from multiplayer::stats::NetStats in the top right corner, over
+// a bar per snapshot (height is its size, red for one that never arrived).
+// only there while connected to a server. state changes despawn every Node, so
+// the overlay is spawned again whenever it is missing.
+use bevy::color::palettes::css::{BLACK, GREEN, RED};
+use bevy::prelude::*;
+use bevy::render::view::RenderLayers;
+
+use crate::multiplayer::protocol::MAX_PACKET_SIZE;
+use crate::multiplayer::stats::{GRAPH_SAMPLES, NetStats};
+
+const BAR_WIDTH: f32 = 2.0;
+const GRAPH_HEIGHT: f32 = 40.0;
+
+#[derive(Resource, Debug, Default)]
+pub struct NetgraphVisible(pub bool);
+
+#[derive(Component)]
+pub struct Netgraph;
+
+#[derive(Component)]
+pub struct NetgraphText;
+
+// position in the graph, 0 is the oldest sample.
+#[derive(Component)]
+pub struct NetgraphBar(usize);
+
+pub fn toggle_netgraph(keyboard: Res<ButtonInput<KeyCode>>, mut visible: ResMut<NetgraphVisible>) {
+    if keyboard.just_pressed(KeyCode::F3) {
+        visible.0 = !visible.0;
+    }
+}
+
+pub fn update_netgraph(
+    mut commands: Commands,
+    visible: Res<NetgraphVisible>,
+    stats: Option<Res<NetStats>>,
+    overlay: Query<Entity, With<Netgraph>>,
+    mut text: Query<&mut Text, With<NetgraphText>>,
+    mut bars: Query<(&NetgraphBar, &mut Node, &mut BackgroundColor)>,
+) {
+    let stats = match stats {
+        Some(stats) if visible.0 => stats,
+        _ => {
+            for entity in overlay.iter() {
+                commands.entity(entity).despawn();
+            }
+            return;
+        }
+    };
+    if overlay.is_empty() {
+        spawn_netgraph(&mut commands);
+        return;
+    }
+
+    for mut text in text.iter_mut() {
+        text.0 = stats.to_string();
+    }
+
+    // newest sample on the right.
+    let empty = GRAPH_SAMPLES - stats.graph.len();
+    for (bar, mut node, mut color) in bars.iter_mut() {
+        let sample = bar.0.checked_sub(empty).map(|i| stats.graph[i]);
+        let (height, bar_color) = match sample {
+            None => (0.0, GREEN),
+            Some(0) => (GRAPH_HEIGHT, RED),
+            Some(bytes) => (
+                (bytes as f32 / MAX_PACKET_SIZE as f32).min(1.0) * GRAPH_HEIGHT,
+                GREEN,
+            ),
+        };
+        node.height = Val::Px(height);
+        color.0 = bar_color.into();
+    }
+}
+
+fn spawn_netgraph(commands: &mut Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.),
+                right: Val::Px(8.),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.),
+                ..default()
+            },
+            Netgraph,
+            RenderLayers::layer(1),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont::from_font_size(14.),
+                TextColor(BLACK.into()),
+                NetgraphText,
+                RenderLayers::layer(1),
+            ));
+            parent
+                .spawn((
+                    Node {
+                        height: Val::Px(GRAPH_HEIGHT),
+                        align_items: AlignItems::FlexEnd,
+                        ..default()
+                    },
+                    RenderLayers::layer(1),
+                ))
+                .with_children(|graph| {
+                    for i in 0..GRAPH_SAMPLES {
+                        graph.spawn((
+                            Node {
+                                width: Val::Px(BAR_WIDTH),
+                                height: Val::Px(0.),
+                                ..default()
+                            },
+                            BackgroundColor(GREEN.into()),
+                            NetgraphBar(i),
+                            RenderLayers::layer(1),
+                        ));
+                    }
+                });
+        });
+}
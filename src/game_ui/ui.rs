@@ -174,6 +174,9 @@ pub fn main_menu_input(
         #[cfg(feature = "client")]
         ev_toggle.write(ToggleBotEvent);
         Some(GameMode::AiWithAi)
+    } else if keyboard_input.just_pressed(KeyCode::Digit5) {
+        info!("Pressed 5 → Spectating a net game");
+        Some(GameMode::Spectate)
     } else if keyboard_input.just_pressed(KeyCode::KeyS) {
         info!("Pressed S → Starting game local coop");
         Some(GameMode::LocalCoop)
@@ -190,6 +193,15 @@ pub fn main_menu_input(
     }
 }
 
+// in a net game (and in a server room) the room's match lifecycle decides
+// when the round is over, see multiplayer/lifecycle.rs.
+pub fn decides_game_over(mode: Option<Res<GameMode>>) -> bool {
+    !matches!(
+        mode.as_deref(),
+        Some(GameMode::NetCoop(_) | GameMode::Spectate | GameMode::Simulated)
+    )
+}
+
 pub fn game_death(
     mut ev_r: EventReader<MaxHeightReached>,
     mut next_state: ResMut<NextState<MyAppState>>,
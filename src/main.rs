@@ -13,23 +13,20 @@ mod policy;
 mod stateMachine;
 mod util;
 
+use config::{LaunchArgs, LaunchConfig, USAGE};
 use std::env;
 fn main() {
-    let mut player_number = None;
-
-    for arg in env::args().skip(1) {
-        // skip binary name
-        match arg.as_str() {
-            "--p1" => {
-                player_number = Some(0);
-            }
-            "--p2" => {
-                player_number = Some(1);
-            }
-            _ => {
-                eprintln!("Unknown argument: {arg}");
-            }
+    // skip binary name
+    let config = match LaunchConfig::from_args(env::args().skip(1)) {
+        Ok(LaunchArgs::Run(config)) => config,
+        Ok(LaunchArgs::Help) => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
         }
-    }
-    app::run(player_number);
+    };
+    app::run(config);
 }
@@ -19,6 +19,8 @@ pub struct EasedPlatform {
     pub speed: f32,
     pub forward: bool,
     pub easing: CubicEasing,
+    // how far it moved on the last fixed tick, for carrying riders.
+    pub displacement: Vec2,
 }
 
 #[derive(Clone, Copy, Debug)]
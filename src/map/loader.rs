@@ -30,12 +30,23 @@ pub struct Spike;
 #[derive(Component, Default)]
 pub struct Platform;
 
+// a platform that is only solid from above: players jump up through it and
+// drop down through it by holding down.
+#[derive(Component, Default)]
+pub struct OneWay;
+
 #[derive(Component, Default)]
 pub struct MovingPlatform;
 
 #[derive(Component, Default)]
 pub struct Coin;
 
+// stable id for a map entity, shared by server and clients.
+// it is the entity's position in the sorted list of map json keys,
+// so every process that loads the same map agrees on it.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MapEntityId(pub u16);
+
 #[macro_export]
 macro_rules! new_game_object {
     ($id:expr, $sprite:expr, $transform:expr, $vis:expr) => {{
@@ -60,8 +71,12 @@ fn game_objects(
 ) -> Vec<GameObject> {
     let mut bundles = Vec::new();
 
+    // sort the keys so MapEntityId is the same on every machine.
+    let mut entities: Vec<_> = map_data.entities.iter().collect();
+    entities.sort_by(|a, b| a.0.cmp(b.0));
+
     // iterate the json map data for entities.
-    for (id, entity) in &map_data.entities {
+    for (map_index, (id, entity)) in entities.into_iter().enumerate() {
         #[cfg(feature = "client")]
         let index = atlas.indices[id];
 
@@ -79,7 +94,7 @@ fn game_objects(
             EntityKind::Platform => {
                 let collider =
                     collider_from_boundary(entity.collision.as_ref(), &entity.boundary, map_height);
-                if entity.attributes.moving.is_some() {
+                let mut bundle = if entity.attributes.moving.is_some() {
                     let eased_platform =
                         create_eased(entity.attributes.moving.as_ref().unwrap(), map_height);
                     new_game_object!(id, sprite, transform, Visibility::default())
@@ -91,7 +106,12 @@ fn game_objects(
                     new_game_object!(id, sprite, transform, Visibility::default())
                         .with_collider(collider)
                         .with_marker::<Platform>()
+                };
+                // oneWay: false platforms are solid on every side.
+                if entity.attributes.one_way.unwrap_or(true) {
+                    bundle = bundle.with_marker::<OneWay>();
                 }
+                bundle
             }
             EntityKind::Spike => {
                 let collider =
@@ -123,7 +143,7 @@ fn game_objects(
         //     println!("{}", bundle.eased_platform.as_ref().unwrap().start);
         // }
 
-        bundles.push(bundle);
+        bundles.push(bundle.with_component(MapEntityId(map_index as u16)));
     }
 
     bundles
@@ -58,7 +58,8 @@ pub struct EntityData {
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct EntityAttrs {
-    one_way: Option<bool>,
+    // platforms only; missing means one-way.
+    pub one_way: Option<bool>,
     pub moving: Option<Moving>,
     #[serde(rename = "bounceStrength")]
     pub bounce_strength: Option<f32>,
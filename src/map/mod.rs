@@ -4,11 +4,16 @@ mod loader;
 mod mapdata;
 mod util;
 use crate::config::MyAppState;
+use crate::physics::broadphase::update_broadphase_system;
 
 mod platformfunction;
 
 pub use game_object_builder::Collider;
-pub use loader::{Coin, MapDimensions, MapTextureHandles, Platform, Spike, TrampolineBounce};
+pub use game_object_builder::EasedPlatform;
+pub use loader::{
+    Coin, MapDimensions, MapEntityId, MapTextureHandles, OneWay, Platform, Spike,
+    TrampolineBounce,
+};
 pub use mapdata::MapFile;
 
 use loader::{load_background_layers, load_game_objects, load_map_data, load_render_resources};
@@ -40,8 +45,10 @@ impl Plugin for MapPlugin {
         );
 
         app.add_systems(
-            Update,
-            linear_move_with_easing.run_if(in_state(MyAppState::InGame)),
+            FixedUpdate,
+            linear_move_with_easing
+                .before(update_broadphase_system)
+                .run_if(in_state(MyAppState::InGame)),
         );
     }
 }
@@ -28,13 +28,24 @@ pub fn cubic_bezier_y(t: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
 }
 
 
-/// Animate t linearly over time, but apply cubic-bezier easing to movement
+impl EasedPlatform {
+    /// Position along the path at the current t
+    pub fn position(&self) -> Vec2 {
+        self.start.lerp(self.end, self.easing.ease(self.t))
+    }
+}
+
+/// Animate t linearly over time, but apply cubic-bezier easing to movement.
+/// Runs on the fixed tick so players standing on the platform move with it
 pub fn linear_move_with_easing(
-    time: Res<Time>,
+    time: Res<Time<Fixed>>,
     mut q: Query<(&mut Transform, &mut EasedPlatform)>,
 ) {
     for (mut transform, mut platform) in &mut q {
         let dt = time.delta_secs() / platform.speed;
+        // measured along the path, so a snapshot snapping the transform
+        // doesn't count as movement
+        let previous = platform.position();
 
         // Linear t progression with ping-pong
         if platform.forward {
@@ -51,11 +62,9 @@ pub fn linear_move_with_easing(
             }
         }
 
-        // Apply easing curve to t
-        let eased_t = platform.easing.ease(platform.t);
-
-        // Interpolate linearly between start and end using eased_t
-        let pos = platform.start.lerp(platform.end, eased_t);
+        // Apply easing curve to t and interpolate between start and end
+        let pos = platform.position();
+        platform.displacement = pos - previous;
         transform.translation = pos.extend(0.0);
     }
 }
\ No newline at end of file
@@ -60,6 +60,7 @@ pub fn create_eased(moving: &Moving, map_height: u32) -> EasedPlatform {
             x2: 0.58,
             y2: 1.0,
         },
+        displacement: Vec2::ZERO,
     }
 }
 
@@ -0,0 +1,174 @@
+// AI partners for empty player slots.
+//
+// with --ai-partner the server has the trained q-learning policy (policy::qtable)
+// drive every player slot no client holds, greedily and without learning, so a
+// single client can play a round. the room counts those slots as taken.
+//
+// a slot's input comes from either the AI or the client's jitter buffer, never
+// both. the AI lets go the tick a client claims the slot: the climber stays
+// where it is, rope and momentum included, and the client carries on from
+// there. it takes over again when the slot is freed.
+use bevy::prelude::*;
+use std::sync::Arc;
+
+use super::input_buffer::AppliedInput;
+use super::lifecycle::{PLAYERS_PER_ROOM, RoomOccupancy};
+use super::protocol::{INPUT_JUMP, INPUT_LEFT, INPUT_RIGHT};
+use crate::components::motion::Velocity;
+use crate::observer::state::ObservationState;
+use crate::player::Player;
+use crate::policy::qtable::{Action, QTable, X_N, Y_N};
+use crate::policy::{QTABLE_P2, QTABLE_PATH};
+
+// ticks a move is held before the policy gets asked again, as in training
+// (policy::action).
+const COMMIT_TICKS: u8 = 9;
+
+// the trained table for each slot, loaded once and shared by every room.
+#[derive(Clone)]
+pub struct AiPolicy(Arc<[QTable; PLAYERS_PER_ROOM]>);
+
+impl AiPolicy {
+    pub fn load() -> Self {
+        let tables = [QTABLE_PATH, QTABLE_P2].map(|path| {
+            QTable::load_from_csv(path).unwrap_or_else(|e| {
+                println!("[Server] Failed to read {path} ({e}), AI partners play untrained");
+                QTable::new()
+            })
+        });
+        Self(Arc::new(tables))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Driver {
+    // ticks left on the current move.
+    commit: u8,
+    mask: u8,
+    // this tick's input, None while a client holds the slot.
+    input: Option<AppliedInput>,
+}
+
+impl Driver {
+    // idle cuts a move short, anything else waits for it to run out.
+    fn decide(&mut self, greedy: Action) -> AppliedInput {
+        let prev = self.mask;
+        if greedy == Action::I {
+            self.commit = 0;
+            self.mask = 0;
+        } else if self.commit == 0 {
+            self.commit = COMMIT_TICKS;
+            self.mask = action_mask(greedy);
+        } else {
+            self.commit -= 1;
+        }
+        AppliedInput {
+            mask: self.mask,
+            jump_just_released: prev & INPUT_JUMP != 0 && self.mask & INPUT_JUMP == 0,
+        }
+    }
+}
+
+fn action_mask(action: Action) -> u8 {
+    match action {
+        Action::I => 0,
+        Action::L => INPUT_LEFT,
+        Action::R => INPUT_RIGHT,
+        Action::J => INPUT_JUMP,
+        Action::LJ => INPUT_LEFT | INPUT_JUMP,
+        Action::RJ => INPUT_RIGHT | INPUT_JUMP,
+    }
+}
+
+// the observer's discrete state for a climber, clamped onto the table.
+fn table_state(transform: &Transform, velocity: &Velocity) -> [usize; 4] {
+    let observation = ObservationState {
+        positions: transform.translation.truncate(),
+        velocities: velocity.0,
+        map_region: None,
+    }
+    .as_vector();
+    [
+        observation[0].clamp(0, X_N as i32 - 1) as usize,
+        observation[1].clamp(0, Y_N as i32 - 1) as usize,
+        (observation[2] + 1) as usize,
+        (observation[3] + 1) as usize,
+    ]
+}
+
+// only in rooms of a server started with --ai-partner.
+#[derive(Resource)]
+pub struct AiPartner {
+    policy: AiPolicy,
+    drivers: [Driver; PLAYERS_PER_ROOM],
+}
+
+impl AiPartner {
+    pub fn new(policy: AiPolicy) -> Self {
+        Self {
+            policy,
+            drivers: Default::default(),
+        }
+    }
+
+    // what the AI does with `slot` this tick, None when a client holds it.
+    pub fn input(&self, slot: usize) -> Option<AppliedInput> {
+        self.drivers.get(slot)?.input
+    }
+}
+
+// runs before process_remote_inputs_system, which applies the result.
+pub fn drive_ai_partners_system(
+    occupancy: Res<RoomOccupancy>,
+    players: Query<(&Player, &Transform, &Velocity)>,
+    mut ai: ResMut<AiPartner>,
+) {
+    let AiPartner { policy, drivers } = ai.as_mut();
+    for (slot, driver) in drivers.iter_mut().enumerate() {
+        if occupancy.claimed(slot) {
+            *driver = Driver::default();
+            continue;
+        }
+        // nobody to drive on the leaderboard.
+        let Some((_, transform, velocity)) = players.iter().find(|(p, ..)| p.slot() == slot)
+        else {
+            driver.input = None;
+            continue;
+        };
+        let [x, y, vx, vy] = table_state(transform, velocity);
+        let greedy = policy.0[slot].best_a(x, y, vx, vy);
+        driver.input = Some(driver.decide(greedy));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_are_held_for_the_commit_unless_the_policy_idles() {
+        let mut driver = Driver::default();
+        assert_eq!(driver.decide(Action::RJ).mask, INPUT_RIGHT | INPUT_JUMP);
+        // the policy changing its mind doesn't matter until the move is over.
+        for _ in 0..COMMIT_TICKS {
+            assert_eq!(driver.decide(Action::L).mask, INPUT_RIGHT | INPUT_JUMP);
+        }
+        assert_eq!(driver.decide(Action::L).mask, INPUT_LEFT);
+
+        let mut driver = Driver::default();
+        driver.decide(Action::J);
+        let input = driver.decide(Action::I);
+        assert_eq!(input.mask, 0);
+        assert!(input.jump_just_released);
+        assert_eq!(driver.commit, 0);
+    }
+
+    #[test]
+    fn states_off_the_table_are_clamped_onto_it() {
+        let velocity = Velocity(Vec2::new(-50.0, 0.0));
+        let below = Transform::from_xyz(-200.0, -500.0, 0.0);
+        assert_eq!(table_state(&below, &velocity), [0, 0, 0, 1]);
+        let far = Transform::from_xyz(64.0 * 100.0, 64.0 * 100.0, 0.0);
+        assert_eq!(table_state(&far, &velocity), [X_N - 1, Y_N - 1, 0, 1]);
+    }
+}
@@ -1,377 +1,1004 @@
-use async_channel::{Receiver, Sender};
-use async_io::Timer;
-use bevy::prelude::*;
-use bevy::tasks::IoTaskPool;
-use std::net::UdpSocket;
-use std::time::Duration;
-
-use crate::{app::GameMode, player::Player};
-
-// -----------------------------------------------------------
-//                 CLIENT SOCKET (UDP)
-// -----------------------------------------------------------
-#[derive(Resource)]
-pub struct UdpClientSocket {
-    pub socket: UdpSocket,
-    pub server_addr: std::net::SocketAddr,
-}
-
-// -----------------------------------------------------------
-//              INPUT HISTORY (for rollback)
-// -----------------------------------------------------------
-#[derive(Resource, Default)]
-pub struct InputHistory {
-    pub entries: Vec<InputEntry>,
-}
-
-pub struct InputEntry {
-    pub tick: u32,
-    pub mask: u8,
-}
-
-const MAX_HISTORY: usize = 200;
-
-// -----------------------------------------------------------
-//             CLIENT PREDICTION STATE
-// -----------------------------------------------------------
-#[derive(Resource, Default)]
-pub struct ClientPredictionState {
-    pub last_server_tick: u32,
-    pub authoritative_pos: Vec2,
-    pub predicted_pos: Vec2,
-
-    pub input_history: Vec<(u32, u8)>,
-}
-
-// -----------------------------------------------------------
-//                  SNAPSHOT UPDATE
-// -----------------------------------------------------------
-#[derive(Debug)]
-pub struct SnapshotUpdate {
-    pub tick: u32,
-    pub positions: Vec<(f32, f32)>,
-}
-
-// -----------------------------------------------------------
-//              INPUT COMMAND (TX INTO ECS)
-// -----------------------------------------------------------
-#[derive(Debug, Clone, Copy)]
-pub struct InputCommand {
-    pub seq: u32,
-    pub mask: u8,
-}
-
-// -----------------------------------------------------------
-//                CHANNELS FOR CLIENT
-// -----------------------------------------------------------
-#[derive(Resource)]
-pub struct ClientNetChannels {
-    pub rx_snapshots: Receiver<SnapshotUpdate>,
-    pub tx_inputs: Sender<InputCommand>,
-}
-
-// -----------------------------------------------------------
-//          SEND INPUT (CALLED EVERY FRAME ON CLIENT)
-// -----------------------------------------------------------
-pub fn send_input_state_system(
-    mut seq: Local<u32>,
-    keyboard: Res<ButtonInput<KeyCode>>,
-
-    channels: Option<Res<ClientNetChannels>>,
-    mut prediction_state: Option<ResMut<ClientPredictionState>>,
-    mut history: ResMut<InputHistory>,
-
-    client: Option<Res<UdpClientSocket>>,
-) {
-    let channels = match channels {
-        Some(c) => c,
-        None => return,
-    };
-    let client = match client {
-        Some(c) => c,
-        None => return,
-    };
-
-    // -------- Construct input bitmask --------
-    let mut mask = 0u8;
-    if keyboard.pressed(KeyCode::KeyW) {
-        mask |= 1 << 0;
-    }
-    if keyboard.pressed(KeyCode::KeyA) {
-        mask |= 1 << 1;
-    }
-    if keyboard.pressed(KeyCode::KeyS) {
-        mask |= 1 << 2;
-    }
-    if keyboard.pressed(KeyCode::KeyD) {
-        mask |= 1 << 3;
-    }
-
-    *seq += 1;
-
-    // -------- Store in InputHistory --------
-    history.entries.push(InputEntry { tick: *seq, mask });
-    if history.entries.len() > MAX_HISTORY {
-        history.entries.remove(0);
-    }
-
-    // -------- UDP packet --------
-    let mut buf = Vec::with_capacity(5);
-    buf.extend_from_slice(&seq.to_be_bytes());
-    buf.push(mask);
-
-    if let Err(e) = client.socket.send_to(&buf, client.server_addr) {
-        eprintln!("[Client] Failed to send input state: {}", e);
-    }
-
-    // -------- Prediction local storage --------
-    if let Some(mut pred) = prediction_state.as_mut() {
-        pred.input_history.push((*seq, mask));
-        if pred.input_history.len() > MAX_HISTORY {
-            pred.input_history.remove(0);
-        }
-    }
-
-    // -------- Send to ECS input channel --------
-    let cmd = InputCommand { seq: *seq, mask };
-    if let Err(e) = channels.tx_inputs.try_send(cmd) {
-        eprintln!("[Client] Failed to enqueue input: {}", e);
-    }
-}
-
-// -----------------------------------------------------------
-//               CLIENT HANDSHAKE + NETWORK SETUP
-// -----------------------------------------------------------
-#[derive(Resource)]
-pub struct ServerAddress(pub String);
-
-pub fn client_handshake(
-    mut commands: Commands,
-    server_addr: Res<ServerAddress>,
-    gamemode: Res<GameMode>,
-) {
-    let server_addr: std::net::SocketAddr = server_addr.0.parse().expect("Invalid server address");
-
-    let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to bind UDP client");
-    socket
-        .set_read_timeout(Some(Duration::from_secs(2)))
-        .expect("Failed to set read timeout");
-
-    let (tx_snapshots, rx_snapshots) = async_channel::unbounded::<SnapshotUpdate>();
-    let (tx_inputs, rx_inputs) = async_channel::unbounded::<InputCommand>();
-
-    let msg = match *gamemode {
-        GameMode::NetCoop(id) if id == 0 => b"MAIN",
-        GameMode::NetCoop(id) if id == 1 => b"PLAY",
-        _ => b"ERRR",
-    };
-
-    socket.send_to(msg, server_addr).ok();
-
-    // -------- SYN-ACK handshake --------
-    let mut buf = [0u8; 1024];
-    match socket.recv_from(&mut buf) {
-        Ok((len, addr)) => {
-            if &buf[..len] == b"ACK" {
-                println!("[Client] Handshake OK with {}", addr);
-
-                commands.insert_resource(ClientPredictionState::default());
-                commands.insert_resource(InputHistory::default());
-
-                // -------- SPAWN SNAPSHOT RECEIVER TASK --------
-                let sock_clone = socket.try_clone().unwrap();
-                let tx_snapshots_clone = tx_snapshots.clone();
-
-                IoTaskPool::get()
-                    .spawn(async move {
-                        let mut buf = [0u8; 1500];
-
-                        loop {
-                            match sock_clone.recv_from(&mut buf) {
-                                Ok((len, _)) => {
-                                    let data = &buf[..len];
-                                    if data.len() < 6 {
-                                        continue;
-                                    }
-
-                                    let tick = u32::from_be_bytes(data[0..4].try_into().unwrap());
-                                    let count =
-                                        u16::from_be_bytes(data[4..6].try_into().unwrap()) as usize;
-
-                                    let mut offset = 6;
-                                    let mut positions = Vec::with_capacity(count);
-
-                                    for _ in 0..count {
-                                        let x = f32::from_be_bytes(
-                                            data[offset..offset + 4].try_into().unwrap(),
-                                        );
-                                        let y = f32::from_be_bytes(
-                                            data[offset + 4..offset + 8].try_into().unwrap(),
-                                        );
-                                        offset += 8;
-                                        positions.push((x, y));
-                                    }
-
-                                    tx_snapshots_clone
-                                        .try_send(SnapshotUpdate { tick, positions })
-                                        .ok();
-                                }
-                                Err(_) => {}
-                            }
-                        }
-                    })
-                    .detach();
-
-                // -------- INPUT SENDER TASK --------
-                let sock_clone = socket.try_clone().unwrap();
-                let addr_clone = server_addr;
-
-                IoTaskPool::get()
-                    .spawn(async move {
-                        while let Ok(input) = rx_inputs.recv().await {
-                            let mut buf = Vec::with_capacity(5);
-                            buf.extend_from_slice(&input.seq.to_be_bytes());
-                            buf.push(input.mask);
-
-                            let sock = sock_clone.try_clone().unwrap();
-                            IoTaskPool::get()
-                                .spawn(async move {
-                                    Timer::after(Duration::from_millis(1)).await;
-                                    sock.send_to(&buf, addr_clone).ok();
-                                })
-                                .detach();
-                        }
-                    })
-                    .detach();
-
-                // -------- INSERT RESOURCES --------
-                commands.insert_resource(UdpClientSocket {
-                    socket,
-                    server_addr,
-                });
-                commands.insert_resource(ClientNetChannels {
-                    rx_snapshots,
-                    tx_inputs,
-                });
-            }
-        }
-        Err(e) => {
-            eprintln!("[Client] Handshake failed: {}", e);
-        }
-    }
-}
-
-// -----------------------------------------------------------
-//                SNAPSHOT APPLICATION + PREDICTION
-// -----------------------------------------------------------
-pub fn apply_snapshot_system(
-    channels: Res<ClientNetChannels>,
-    mut players: Query<(&mut Transform, &Player)>,
-    mut prediction: ResMut<ClientPredictionState>,
-    history: Res<InputHistory>,
-) {
-    while let Ok(snapshot) = channels.rx_snapshots.try_recv() {
-        let tick = snapshot.tick;
-
-        // Ignore stale snapshots
-        if tick <= prediction.last_server_tick {
-            continue;
-        }
-        prediction.last_server_tick = tick;
-
-        // -----------------------------------------------------
-        // 1. APPLY AUTHORITATIVE POSITION FOR LOCAL PLAYER
-        // -----------------------------------------------------
-        let mut local_id = None;
-
-        for (_, player) in players.iter() {
-            if let Player::Local(id) = player {
-                local_id = Some(*id);
-                break;
-            }
-        }
-
-        let local_id = local_id.expect("Local player missing?!");
-
-        let (auth_x, auth_y) = snapshot.positions[local_id];
-        let authoritative = Vec2::new(auth_x, auth_y);
-
-        prediction.authoritative_pos = authoritative;
-        prediction.predicted_pos = authoritative;
-
-        // -----------------------------------------------------
-        // 2. ROLLBACK & REPLAY INPUTS FOR LOCAL PLAYER
-        // -----------------------------------------------------
-        for entry in history.entries.iter().filter(|e| e.tick > tick) {
-            simulate_input(&mut prediction.predicted_pos, entry.mask);
-        }
-
-        // -----------------------------------------------------
-        // 3. APPLY POSITIONS TO ALL PLAYERS
-        // -----------------------------------------------------
-        for (mut transform, player) in players.iter_mut() {
-            match player {
-                // ----------------------
-                // LOCAL PREDICTED PLAYER
-                // ----------------------
-                Player::Local(id) => {
-                    let current = transform.translation.truncate();
-                    let target = prediction.predicted_pos;
-
-                    // smooth correction avoid snapping
-                    let blend = 0.12;
-                    let new = current + (target - current) * blend;
-
-                    transform.translation.x = new.x;
-                    transform.translation.y = new.y;
-                }
-
-                // -----------------------
-                // REMOTE NETWORK PLAYERS
-                // -----------------------
-                Player::Net(id) => {
-                    if let Some((x, y)) = snapshot.positions.get(*id) {
-                        let target = Vec2::new(*x, *y);
-                        let current = transform.translation.truncate();
-
-                        // Optional interpolation (looks smooth!)
-                        let blend = 0.30;
-                        let new = current + (target - current) * blend;
-
-                        transform.translation.x = new.x;
-                        transform.translation.y = new.y;
-                    }
-                }
-
-                // -----------------------
-                // NPCs ignore for now
-                // -----------------------
-                Player::Npc(_) => {}
-            }
-        }
-    }
-}
-
-// -----------------------------------------------------------
-//            SIMULATE INPUT (placeholder prediction)
-// -----------------------------------------------------------
-fn simulate_input(pos: &mut Vec2, mask: u8) {
-    let dt = 1.0 / 60.0;
-
-    let mut vel = Vec2::ZERO;
-
-    if mask & (1 << 0) != 0 {
-        vel.y += 450.0;
-    }
-    if mask & (1 << 1) != 0 {
-        vel.x -= 300.0;
-    }
-    if mask & (1 << 2) != 0 {
-        vel.y -= 300.0;
-    }
-    if mask & (1 << 3) != 0 {
-        vel.x += 300.0;
-    }
-
-    *pos += vel * dt;
-}
+use async_channel::Receiver;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::clock::ClockSync;
+use super::conditioner::{ConditionedSocket, LinkConditions};
+use super::delta::{self, SNAPSHOT_HISTORY};
+use super::interpolation::{InterpolationClock, SnapshotBuffer};
+use super::lifecycle::COUNTDOWN;
+use super::prediction::PredictionWorld;
+use super::protocol::{
+    self, MatchPhase, Message, PlayerState, RejectReason, ReliableEvent, RoomRequest,
+    SPECTATOR_SLOT, WorldSnapshot,
+};
+use super::reliable::ReliableChannel;
+use super::runtime::{NetRuntime, NetTask};
+use super::snapshot::{apply_platform, apply_player_motion, apply_rope};
+use super::stats::{NetStats, SnapshotReceipt};
+use super::transport::NetTransport;
+use crate::components::motion::{GroundState, JumpController, Momentum, Velocity};
+use crate::components::rope::Rope;
+use crate::config::MyAppState;
+use crate::game_ui::ui::{MaxHeight, TotalCoin};
+use crate::map::{Coin, Collider, EasedPlatform, MapEntityId, OneWay, Spike, TrampolineBounce};
+use crate::physics::MaxHeightReached;
+use crate::physics::broadphase::Broadphase;
+use crate::{app::GameMode, player::Player};
+
+// -----------------------------------------------------------
+//                 CLIENT SOCKET (UDP)
+// -----------------------------------------------------------
+#[derive(Resource)]
+pub struct UdpClientSocket {
+    pub socket: ConditionedSocket,
+    pub server_addr: std::net::SocketAddr,
+    // the receiver task, stopped along with the connection.
+    pub receiver: NetTask,
+}
+
+// -----------------------------------------------------------
+//              INPUT HISTORY (for rollback)
+// -----------------------------------------------------------
+#[derive(Resource, Default)]
+pub struct InputHistory {
+    pub entries: Vec<InputEntry>,
+}
+
+// `seq` is the server tick the frame is for, like in Message::Input.
+pub struct InputEntry {
+    pub seq: u32,
+    pub mask: u8,
+}
+
+const MAX_HISTORY: usize = 200;
+// input frames repeated in every packet, so up to this many packets in a row
+// can be lost without the server missing a frame.
+pub const INPUT_REDUNDANCY: usize = 8;
+
+// -----------------------------------------------------------
+//             CLIENT PREDICTION STATE
+// -----------------------------------------------------------
+#[derive(Resource, Default)]
+pub struct ClientPredictionState {
+    pub last_server_tick: u32,
+    pub authoritative_pos: Vec2,
+    pub predicted_pos: Vec2,
+    // full server state of the local player from the newest snapshot.
+    pub authoritative: Option<PlayerState>,
+    // newest snapshot plus the input masks to replay on top of it.
+    pub rollback: Option<PendingRollback>,
+
+    pub input_history: Vec<(u32, u8)>,
+}
+
+pub struct PendingRollback {
+    pub snapshot: WorldSnapshot,
+    pub inputs: Vec<u8>,
+}
+
+// -----------------------------------------------------------
+//                CHANNELS FOR CLIENT
+// -----------------------------------------------------------
+#[derive(Resource)]
+pub struct ClientNetChannels {
+    pub rx_snapshots: Receiver<WorldSnapshot>,
+    // reliable events from the server, in order and exactly once.
+    pub rx_reliable: Receiver<ReliableEvent>,
+    // every snapshot datagram that arrived, for NetStats.
+    pub rx_receipts: Receiver<SnapshotReceipt>,
+    // newest snapshot tick the receiver task has rebuilt, acked with every input.
+    pub acked_tick: Arc<AtomicU32>,
+}
+
+// -----------------------------------------------------------
+//          SEND INPUT (CALLED EVERY FRAME ON CLIENT)
+// -----------------------------------------------------------
+pub fn send_input_state_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    clock: Option<Res<ClockSync>>,
+
+    channels: Option<Res<ClientNetChannels>>,
+    mut prediction_state: Option<ResMut<ClientPredictionState>>,
+    mut history: ResMut<InputHistory>,
+
+    client: Option<Res<UdpClientSocket>>,
+    connection: Option<Res<ClientConnection>>,
+) {
+    let channels = match channels {
+        Some(c) => c,
+        None => return,
+    };
+    let client = match client {
+        Some(c) => c,
+        None => return,
+    };
+
+    // -------- Construct input bitmask --------
+    let mut mask = 0u8;
+    if keyboard.pressed(KeyCode::KeyW) {
+        mask |= protocol::INPUT_JUMP;
+    }
+    if keyboard.pressed(KeyCode::KeyA) {
+        mask |= protocol::INPUT_LEFT;
+    }
+    if keyboard.pressed(KeyCode::KeyS) {
+        mask |= protocol::INPUT_DOWN;
+    }
+    if keyboard.pressed(KeyCode::KeyD) {
+        mask |= protocol::INPUT_RIGHT;
+    }
+    // the server ignores input outside a round, so predict none either.
+    let playing = connection
+        .as_ref()
+        .is_some_and(|c| c.match_phase == Some(MatchPhase::Playing));
+    if !playing {
+        mask = 0;
+    }
+    let token = connection.map_or(0, |c| c.token);
+    let ack = channels.acked_tick.load(Ordering::Relaxed);
+
+    // every frame is for the server tick we are simulating. until the first
+    // pong there is none, so the packet only carries the ack.
+    let Some(seq) = clock.and_then(|c| c.sim_tick) else {
+        let buf = protocol::encode(&Message::Input {
+            token,
+            seq: 0,
+            masks: Vec::new(),
+            ack,
+        });
+        if let Err(e) = client.socket.send_to(&buf, client.server_addr) {
+            eprintln!("[Client] Failed to send input state: {}", e);
+        }
+        return;
+    };
+
+    // -------- Store in InputHistory --------
+    // the masks in a packet are consecutive ticks; after the clock jumped the
+    // old frames are for ticks we never ran (or will run again).
+    if history
+        .entries
+        .last()
+        .is_some_and(|e| e.seq.wrapping_add(1) != seq)
+    {
+        history.entries.clear();
+    }
+    history.entries.push(InputEntry { seq, mask });
+    if history.entries.len() > MAX_HISTORY {
+        history.entries.remove(0);
+    }
+
+    // -------- UDP packet --------
+    // newest frame first, the server dedupes and orders them.
+    let masks = history
+        .entries
+        .iter()
+        .rev()
+        .take(INPUT_REDUNDANCY)
+        .map(|e| e.mask)
+        .collect();
+    let buf = protocol::encode(&Message::Input {
+        token,
+        seq,
+        masks,
+        ack,
+    });
+
+    if let Err(e) = client.socket.send_to(&buf, client.server_addr) {
+        eprintln!("[Client] Failed to send input state: {}", e);
+    }
+
+    // -------- Prediction local storage --------
+    if let Some(mut pred) = prediction_state.as_mut() {
+        pred.input_history.push((seq, mask));
+        if pred.input_history.len() > MAX_HISTORY {
+            pred.input_history.remove(0);
+        }
+    }
+}
+
+// -----------------------------------------------------------
+//               CLIENT HANDSHAKE + NETWORK SETUP
+// -----------------------------------------------------------
+#[derive(Resource)]
+pub struct ServerAddress(pub String);
+
+#[derive(Resource)]
+pub struct RequestedRoom(pub RoomRequest);
+
+// keep-alive period while connected (inputs alone stop when the game pauses).
+// every ping is timed for NetStats::rtt.
+const PING_INTERVAL: Duration = Duration::from_millis(500);
+// pings still waiting for their pong. older ones count as lost.
+const PINGS_IN_FLIGHT: usize = 8;
+// nothing from the server for this long and we start reconnecting.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+// handshake retry delay, doubled per attempt up to the max.
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(250);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(8);
+// what the receiver task can get ahead of the ECS. snapshots and receipts
+// make room by dropping the oldest, events and reliable events wait.
+const SNAPSHOT_QUEUE: usize = 64;
+const EVENT_QUEUE: usize = 64;
+
+// control traffic the receiver task hands to client_connection_system.
+#[derive(Debug)]
+pub enum NetEvent {
+    Welcome {
+        slot: u8,
+        session: u32,
+        room: u32,
+        token: u64,
+    },
+    Rejected(RejectReason),
+    PartnerLeft {
+        slot: u8,
+    },
+    // answer to our ping `id` with the server's tick, stamped when it arrived.
+    Pong {
+        id: u32,
+        tick: u32,
+        at: Instant,
+    },
+    ServerClosed,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConnectionPhase {
+    Connecting { attempt: u32, next_attempt: Instant },
+    Connected { last_ping: Instant },
+    // the server will never take us (wrong version / slot), stop retrying.
+    Failed,
+}
+
+#[derive(Resource)]
+pub struct ClientConnection {
+    pub slot: u8,
+    // 0 until the first Welcome.
+    pub session: u32,
+    // the session's secret from Welcome (0 until then). goes with every input
+    // and proves who we are when reconnecting.
+    pub token: u64,
+    // what the player asked for, and the room the server put us in once it has.
+    pub requested_room: RoomRequest,
+    pub room: Option<u32>,
+    pub phase: ConnectionPhase,
+    // updated by the receiver task for every packet from the server.
+    pub last_heard: Arc<Mutex<Instant>>,
+    pub rx_events: Receiver<NetEvent>,
+    // shared with the receiver task, which handles acks as they come in.
+    pub reliable: Arc<Mutex<ReliableChannel>>,
+    // the room's match phase, once the server has told us.
+    pub match_phase: Option<MatchPhase>,
+    // id and send time of the pings not answered yet, oldest first.
+    pub pings: VecDeque<(u32, Instant)>,
+    pub next_ping: u32,
+}
+
+impl ClientConnection {
+    fn start_connecting(&mut self) {
+        self.phase = ConnectionPhase::Connecting {
+            attempt: 0,
+            next_attempt: Instant::now(),
+        };
+    }
+}
+
+pub fn client_connected(connection: Option<Res<ClientConnection>>) -> bool {
+    matches!(
+        connection.map(|c| c.phase),
+        Some(ConnectionPhase::Connected { .. })
+    )
+}
+
+fn reconnect_backoff(attempt: u32) -> Duration {
+    RECONNECT_BACKOFF_MIN
+        .saturating_mul(1 << attempt.min(16))
+        .min(RECONNECT_BACKOFF_MAX)
+}
+
+// opens the socket and starts the network tasks. the handshake itself is
+// driven by client_connection_system so it can retry.
+pub fn client_handshake(
+    mut commands: Commands,
+    server_addr: Res<ServerAddress>,
+    requested_room: Res<RequestedRoom>,
+    link: Res<LinkConditions>,
+    transport: Res<NetTransport>,
+    runtime: Res<NetRuntime>,
+    gamemode: Res<GameMode>,
+    connection: Option<Res<ClientConnection>>,
+    channels: Option<Res<ClientNetChannels>>,
+) {
+    // back from the leaderboard in the same slot: keep the session and ask
+    // the room for another round.
+    if let (Some(connection), Some(channels)) = (connection, channels) {
+        println!("[Client] Asking room {:?} for a restart", connection.room);
+        connection
+            .reliable
+            .lock()
+            .unwrap()
+            .send(ReliableEvent::RestartRequested);
+        // snapshots from the end of the last round.
+        while channels.rx_snapshots.try_recv().is_ok() {}
+        return;
+    }
+
+    // hostnames are allowed, so resolve rather than parse.
+    let Some(server_addr) = transport.resolve(&server_addr.0) else {
+        eprintln!(
+            "[Client] Could not resolve server address {}",
+            server_addr.0
+        );
+        return;
+    };
+
+    let socket = transport
+        .bind("0.0.0.0:0", &runtime)
+        .expect("Failed to bind UDP client");
+    let socket = ConditionedSocket::new(socket, *link, &runtime);
+
+    let (tx_snapshots, rx_snapshots) = async_channel::bounded::<WorldSnapshot>(SNAPSHOT_QUEUE);
+    let (tx_events, rx_events) = async_channel::bounded::<NetEvent>(EVENT_QUEUE);
+    let (tx_reliable, rx_reliable) = async_channel::bounded::<ReliableEvent>(EVENT_QUEUE);
+    let (tx_receipts, rx_receipts) = async_channel::bounded::<SnapshotReceipt>(SNAPSHOT_QUEUE);
+
+    let slot = match *gamemode {
+        GameMode::NetCoop(id) => id as u8,
+        _ => SPECTATOR_SLOT,
+    };
+
+    commands.insert_resource(ClientPredictionState::default());
+    commands.insert_resource(InputHistory::default());
+    commands.insert_resource(PredictionWorld::default());
+    commands.insert_resource(NetStats::default());
+    commands.insert_resource(ClockSync::default());
+
+    // -------- SPAWN SNAPSHOT RECEIVER TASK --------
+    let sock_clone = socket.clone();
+    let tx_snapshots_clone = tx_snapshots.clone();
+    let acked_tick = Arc::new(AtomicU32::new(0));
+    let acked_tick_clone = acked_tick.clone();
+    let last_heard = Arc::new(Mutex::new(Instant::now()));
+    let last_heard_clone = last_heard.clone();
+    let reliable = Arc::new(Mutex::new(ReliableChannel::default()));
+    let reliable_clone = reliable.clone();
+
+    let receiver = runtime.spawn(async move {
+        let mut buf = [0u8; protocol::MAX_PACKET_SIZE];
+        // rebuilt snapshots the server may use as delta baselines.
+        let mut baselines: VecDeque<WorldSnapshot> = VecDeque::with_capacity(SNAPSHOT_HISTORY);
+
+        loop {
+            let Ok((len, from)) = sock_clone.recv_from(&mut buf).await else {
+                continue;
+            };
+            if from != server_addr {
+                continue;
+            }
+
+            let msg = match protocol::decode(&buf[..len]) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    eprintln!("[Client] Dropping malformed packet: {}", e);
+                    continue;
+                }
+            };
+            *last_heard_clone.lock().unwrap() = Instant::now();
+
+            let snapshot = match msg {
+                Message::Snapshot(snapshot) => {
+                    let receipt = SnapshotReceipt {
+                        tick: snapshot.tick,
+                        bytes: len,
+                    };
+                    tx_receipts.force_send(receipt).ok();
+                    snapshot
+                }
+                Message::DeltaSnapshot(d) => {
+                    // it arrived even if it can't be rebuilt.
+                    let receipt = SnapshotReceipt {
+                        tick: d.tick,
+                        bytes: len,
+                    };
+                    tx_receipts.force_send(receipt).ok();
+                    let rebuilt = baselines
+                        .iter()
+                        .find(|s| s.tick == d.baseline)
+                        .and_then(|baseline| delta::apply(baseline, &d));
+                    match rebuilt {
+                        Some(snapshot) => snapshot,
+                        None => {
+                            // baseline already dropped, the server
+                            // falls back to a full snapshot soon.
+                            eprintln!(
+                                "[Client] No baseline {} for delta {}",
+                                d.baseline, d.tick
+                            );
+                            continue;
+                        }
+                    }
+                }
+                Message::Welcome {
+                    slot,
+                    session,
+                    room,
+                    token,
+                } => {
+                    // new session on the server side, old baselines mean nothing
+                    // and both ends number reliable messages from zero again.
+                    baselines.clear();
+                    acked_tick_clone.store(0, Ordering::Relaxed);
+                    reliable_clone.lock().unwrap().restart();
+                    tx_events
+                        .send(NetEvent::Welcome {
+                            slot,
+                            session,
+                            room,
+                            token,
+                        })
+                        .await
+                        .ok();
+                    continue;
+                }
+                Message::Reject { reason } => {
+                    tx_events.send(NetEvent::Rejected(reason)).await.ok();
+                    continue;
+                }
+                Message::PartnerLeft { slot } => {
+                    tx_events.send(NetEvent::PartnerLeft { slot }).await.ok();
+                    continue;
+                }
+                Message::Disconnect => {
+                    tx_events.send(NetEvent::ServerClosed).await.ok();
+                    continue;
+                }
+                Message::Reliable { seq, event } => {
+                    let (ready, next) = {
+                        let mut reliable = reliable_clone.lock().unwrap();
+                        (reliable.on_receive(seq, event), reliable.ack())
+                    };
+                    // ack duplicates too, the first ack may have been lost.
+                    send_to_server(&sock_clone, server_addr, &Message::ReliableAck { next });
+                    for event in ready {
+                        tx_reliable.send(event).await.ok();
+                    }
+                    continue;
+                }
+                Message::ReliableAck { next } => {
+                    reliable_clone.lock().unwrap().on_ack(next);
+                    continue;
+                }
+                Message::Pong { id, tick } => {
+                    let pong = NetEvent::Pong {
+                        id,
+                        tick,
+                        at: Instant::now(),
+                    };
+                    tx_events.send(pong).await.ok();
+                    continue;
+                }
+                _ => continue,
+            };
+
+            acked_tick_clone.fetch_max(snapshot.tick, Ordering::Relaxed);
+            if baselines.len() == SNAPSHOT_HISTORY {
+                baselines.pop_front();
+            }
+            baselines.push_back(snapshot.clone());
+            tx_snapshots_clone.force_send(snapshot).ok();
+        }
+    });
+
+    // -------- INSERT RESOURCES --------
+    commands.insert_resource(UdpClientSocket {
+        socket,
+        server_addr,
+        receiver,
+    });
+    commands.insert_resource(ClientNetChannels {
+        rx_snapshots,
+        rx_reliable,
+        rx_receipts,
+        acked_tick,
+    });
+
+    let mut connection = ClientConnection {
+        slot,
+        session: 0,
+        token: 0,
+        requested_room: requested_room.0,
+        room: None,
+        phase: ConnectionPhase::Failed,
+        last_heard,
+        rx_events,
+        reliable,
+        match_phase: None,
+        pings: VecDeque::with_capacity(PINGS_IN_FLIGHT),
+        next_ping: 0,
+    };
+    connection.start_connecting();
+    commands.insert_resource(connection);
+}
+
+// -----------------------------------------------------------
+//          CONNECTION UPKEEP (HELLO RETRIES, PINGS)
+// -----------------------------------------------------------
+pub fn client_connection_system(
+    mut connection: ResMut<ClientConnection>,
+    client: Res<UdpClientSocket>,
+    mut prediction: ResMut<ClientPredictionState>,
+    mut stats: ResMut<NetStats>,
+    mut clock: ResMut<ClockSync>,
+    fixed: Res<Time<Fixed>>,
+    mut buffers: Query<&mut SnapshotBuffer>,
+) {
+    while let Ok(event) = connection.rx_events.try_recv() {
+        match event {
+            NetEvent::Welcome {
+                slot,
+                session,
+                room,
+                token,
+            } => {
+                if connection.session != 0 && connection.session == session {
+                    println!(
+                        "[Client] Reconnected to {} (slot {}, room {})",
+                        client.server_addr, slot, room
+                    );
+                } else {
+                    println!(
+                        "[Client] Handshake OK with {} (slot {}, room {})",
+                        client.server_addr, slot, room
+                    );
+                }
+                connection.session = session;
+                connection.token = token;
+                connection.room = Some(room);
+                connection.phase = ConnectionPhase::Connected {
+                    last_ping: Instant::now(),
+                };
+                connection.pings.clear();
+                // a restarted server counts ticks from zero again.
+                prediction.last_server_tick = 0;
+                stats.restart();
+                for mut buffer in buffers.iter_mut() {
+                    buffer.clear();
+                }
+            }
+            NetEvent::Rejected(reason) => {
+                eprintln!(
+                    "[Client] Handshake rejected by {}: {:?}",
+                    client.server_addr, reason
+                );
+                match reason {
+                    // the old holder of our slot may still time out, keep trying.
+                    RejectReason::SlotTaken => {}
+                    // our room closed while we were away (or the server
+                    // restarted). unless we asked for that room by id, start over.
+                    RejectReason::NoSuchRoom
+                        if !matches!(connection.requested_room, RoomRequest::Join(_)) =>
+                    {
+                        connection.room = None;
+                        connection.session = 0;
+                        connection.token = 0;
+                    }
+                    _ => connection.phase = ConnectionPhase::Failed,
+                }
+            }
+            NetEvent::PartnerLeft { slot } => {
+                println!("[Client] P{} left the game", slot + 1);
+            }
+            NetEvent::Pong { id, tick, at } => {
+                // pings older than this one were lost, or their pongs were.
+                if let Some(i) = connection.pings.iter().position(|(ping, _)| *ping == id) {
+                    let (_, sent) = connection.pings.drain(..=i).last().unwrap();
+                    let rtt = at.saturating_duration_since(sent);
+                    stats.on_pong(rtt);
+                    clock.on_pong(tick, rtt, fixed.timestep());
+                }
+            }
+            NetEvent::ServerClosed => {
+                println!("[Client] Server closed the connection, reconnecting");
+                connection.start_connecting();
+            }
+        }
+    }
+
+    let now = Instant::now();
+    match connection.phase {
+        ConnectionPhase::Connecting {
+            attempt,
+            next_attempt,
+        } if now >= next_attempt => {
+            if attempt > 0 {
+                println!("[Client] Retrying handshake (attempt {})", attempt + 1);
+            }
+            let hello = Message::Hello {
+                slot: connection.slot,
+                token: connection.token,
+                room: connection
+                    .room
+                    .map_or(connection.requested_room, RoomRequest::Join),
+            };
+            send_to_server(&client.socket, client.server_addr, &hello);
+            connection.phase = ConnectionPhase::Connecting {
+                attempt: attempt + 1,
+                next_attempt: now + reconnect_backoff(attempt),
+            };
+        }
+        ConnectionPhase::Connected { last_ping } => {
+            let last_heard = *connection.last_heard.lock().unwrap();
+            if now.duration_since(last_heard) > SERVER_TIMEOUT {
+                eprintln!(
+                    "[Client] Lost connection to {}, reconnecting",
+                    client.server_addr
+                );
+                connection.start_connecting();
+            } else if now.duration_since(last_ping) >= PING_INTERVAL {
+                let id = connection.next_ping;
+                connection.next_ping = id.wrapping_add(1);
+                if connection.pings.len() == PINGS_IN_FLIGHT {
+                    connection.pings.pop_front();
+                }
+                connection.pings.push_back((id, now));
+                send_to_server(&client.socket, client.server_addr, &Message::Ping { id });
+                connection.phase = ConnectionPhase::Connected { last_ping: now };
+            }
+
+            let due = connection.reliable.lock().unwrap().due(now);
+            for (seq, event) in due {
+                send_to_server(
+                    &client.socket,
+                    client.server_addr,
+                    &Message::Reliable { seq, event },
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+fn send_to_server(socket: &ConditionedSocket, server_addr: std::net::SocketAddr, msg: &Message) {
+    if let Err(e) = socket.send_to(&protocol::encode(msg), server_addr) {
+        eprintln!("[Client] send error: {}", e);
+    }
+}
+
+// the connection outlives the leaderboard so the room can be restarted, but
+// not a switch to another mode or slot.
+pub fn leaving_net_game(
+    connection: Option<Res<ClientConnection>>,
+    mode: Option<Res<GameMode>>,
+) -> bool {
+    match (connection, mode.as_deref()) {
+        (Some(connection), Some(GameMode::NetCoop(slot))) => connection.slot as usize != *slot,
+        (Some(connection), Some(GameMode::Spectate)) => connection.slot != SPECTATOR_SLOT,
+        (Some(_), _) => true,
+        (None, _) => false,
+    }
+}
+
+// tell the server we are leaving so our slot is freed right away.
+pub fn client_disconnect(mut commands: Commands, client: Option<Res<UdpClientSocket>>) {
+    let Some(client) = client else {
+        return;
+    };
+    send_to_server(&client.socket, client.server_addr, &Message::Disconnect);
+
+    commands.remove_resource::<UdpClientSocket>();
+    commands.remove_resource::<ClientNetChannels>();
+    commands.remove_resource::<ClientConnection>();
+    commands.remove_resource::<PredictionWorld>();
+    commands.remove_resource::<NetStats>();
+    commands.remove_resource::<ClockSync>();
+}
+
+// -----------------------------------------------------------
+//                SNAPSHOT APPLICATION + PREDICTION
+// -----------------------------------------------------------
+pub fn apply_snapshot_system(
+    mut commands: Commands,
+    channels: Res<ClientNetChannels>,
+    mut players: Query<(Entity, &Player, Option<&mut SnapshotBuffer>)>,
+    mut platforms: Query<(&MapEntityId, &mut Transform, &mut EasedPlatform), Without<Player>>,
+    coins: Query<(Entity, &MapEntityId), With<Coin>>,
+    mut ropes: Query<&mut Rope>,
+    slots: Query<&Player>,
+    mut total_coin: ResMut<TotalCoin>,
+    mut prediction: ResMut<ClientPredictionState>,
+    history: Res<InputHistory>,
+    mut clock: ResMut<InterpolationClock>,
+    real: Res<Time<Real>>,
+    fixed: Res<Time<Fixed>>,
+) {
+    while let Ok(snapshot) = channels.rx_snapshots.try_recv() {
+        let tick = snapshot.tick;
+
+        // Ignore stale snapshots
+        if tick <= prediction.last_server_tick {
+            continue;
+        }
+        prediction.last_server_tick = tick;
+
+        clock.observe(
+            tick as f64 * fixed.timestep().as_secs_f64(),
+            real.elapsed_secs_f64(),
+        );
+
+        // -----------------------------------------------------
+        // 1. APPLY AUTHORITATIVE STATE FOR LOCAL PLAYER
+        // -----------------------------------------------------
+        let mut local_id = None;
+
+        for (_, player, _) in players.iter() {
+            if let Player::Local(id) = player {
+                local_id = Some(*id);
+                break;
+            }
+        }
+
+        // spectators have no local player, everyone is remote.
+        let mut local_missing = false;
+        if let Some(local_id) = local_id {
+            match snapshot
+                .players
+                .iter()
+                .find(|p| p.slot as usize == local_id)
+                .copied()
+            {
+                Some(authoritative) => {
+                    prediction.authoritative = Some(authoritative);
+                    prediction.authoritative_pos = authoritative.position;
+                    prediction.predicted_pos = authoritative.position;
+                }
+                // not in this one (a takeover, a restart). the rest of the
+                // world still applies, only our prediction waits for a
+                // snapshot that has us.
+                None => local_missing = true,
+            }
+        }
+
+        // -----------------------------------------------------
+        // 2. QUEUE ROLLBACK & REPLAY FOR LOCAL PLAYER
+        // -----------------------------------------------------
+        // replayed through the physics in predict_local_player_system: every
+        // frame the server hadn't applied yet when it took the snapshot.
+        let applied = prediction.authoritative.map_or(u32::MAX, |a| a.last_input);
+        let replay: Vec<u8> = history
+            .entries
+            .iter()
+            .filter(|e| e.seq > applied)
+            .map(|e| e.mask)
+            .collect();
+
+        // -----------------------------------------------------
+        // 3. APPLY STATE TO ALL PLAYERS
+        // -----------------------------------------------------
+        for (entity, player, buffer) in players.iter_mut() {
+            match player {
+                // ----------------------
+                // LOCAL PREDICTED PLAYER
+                // ----------------------
+                // corrected in predict_local_player_system.
+                Player::Local(_) => {}
+
+                // -----------------------
+                // REMOTE NETWORK PLAYERS
+                // -----------------------
+                // drawn from the buffer by interpolate_remote_players_system.
+                Player::Net(id) => {
+                    if let Some(state) = snapshot.players.iter().find(|p| p.slot as usize == *id) {
+                        match buffer {
+                            Some(mut buffer) => buffer.push(tick, *state),
+                            None => {
+                                let mut buffer = SnapshotBuffer::default();
+                                buffer.push(tick, *state);
+                                commands.entity(entity).insert(buffer);
+                            }
+                        }
+                    }
+                }
+
+                // -----------------------
+                // NPCs ignore for now
+                // -----------------------
+                Player::Npc(_) => {}
+            }
+        }
+
+        // -----------------------------------------------------
+        // 4. APPLY WORLD STATE (ropes, platforms, coins)
+        // -----------------------------------------------------
+        for mut rope in ropes.iter_mut() {
+            let (Ok(head), Ok(tail)) = (
+                slots.get(rope.attached_entity_head),
+                slots.get(rope.attached_entity_tail),
+            ) else {
+                continue;
+            };
+            if let Some(state) = snapshot
+                .ropes
+                .iter()
+                .find(|r| r.head as usize == head.slot() && r.tail as usize == tail.slot())
+            {
+                apply_rope(state, &mut rope);
+            }
+        }
+
+        for (id, mut transform, mut platform) in platforms.iter_mut() {
+            if let Some(state) = snapshot.platforms.iter().find(|p| p.id == id.0) {
+                apply_platform(state, &mut transform, &mut platform);
+            }
+        }
+
+        for (entity, id) in coins.iter() {
+            if snapshot.despawned_coins.contains(&id.0) {
+                commands.entity(entity).despawn();
+            }
+        }
+        total_coin.amount = snapshot.coins_collected;
+
+        // only the newest snapshot is worth rolling back to.
+        if !local_missing {
+            prediction.rollback = Some(PendingRollback {
+                snapshot,
+                inputs: replay,
+            });
+        }
+    }
+}
+
+// -----------------------------------------------------------
+//                RELIABLE EVENTS FROM THE SERVER
+// -----------------------------------------------------------
+// the server's word on pickups, deaths and the match phase. runs in every
+// state, a restart can come in while we are on the leaderboard.
+pub fn apply_reliable_events_system(
+    mut commands: Commands,
+    channels: Res<ClientNetChannels>,
+    mut connection: ResMut<ClientConnection>,
+    state: Res<State<MyAppState>>,
+    mut next_state: ResMut<NextState<MyAppState>>,
+    coins: Query<(Entity, &MapEntityId), With<Coin>>,
+    mut total_coin: ResMut<TotalCoin>,
+    mut max_height: ResMut<MaxHeight>,
+    mut game_over: EventWriter<MaxHeightReached>,
+) {
+    let in_game = *state.get() == MyAppState::InGame;
+    while let Ok(event) = channels.rx_reliable.try_recv() {
+        match event {
+            ReliableEvent::CoinCollected { id, total } if in_game => {
+                for (entity, _) in coins.iter().filter(|(_, coin)| coin.0 == id) {
+                    commands.entity(entity).despawn();
+                }
+                total_coin.amount = total;
+            }
+            ReliableEvent::GameOver { height } if in_game => {
+                game_over.write(MaxHeightReached { height });
+            }
+            ReliableEvent::PhaseChanged(phase) => {
+                match phase {
+                    MatchPhase::Countdown => {
+                        println!("[Client] Round starts in {}s", COUNTDOWN.as_secs())
+                    }
+                    _ => println!("[Client] Match {:?}", phase),
+                }
+                connection.match_phase = Some(phase);
+
+                match phase {
+                    MatchPhase::Finished if in_game => next_state.set(MyAppState::EndCredit),
+                    // the room started the next round without us asking.
+                    MatchPhase::Waiting | MatchPhase::Countdown | MatchPhase::Playing
+                        if *state.get() == MyAppState::EndCredit =>
+                    {
+                        total_coin.amount = 0;
+                        max_height.amount = 0;
+                        next_state.set(MyAppState::InGame);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// -----------------------------------------------------------
+//          LOCAL PREDICTION (rollback + physics replay)
+// -----------------------------------------------------------
+// corrections further than this are snapped instead of smoothed.
+const PREDICTION_SNAP_DISTANCE: f32 = 48.0;
+// fraction of the remaining position error removed per correction.
+const PREDICTION_CORRECTION_BLEND: f32 = 0.3;
+
+pub fn predict_local_player_system(
+    time: Res<Time<Fixed>>,
+    mut prediction: ResMut<ClientPredictionState>,
+    mut stats: Option<ResMut<NetStats>>,
+    mut world: ResMut<PredictionWorld>,
+    broadphase: Res<Broadphase>,
+    colliders: Query<
+        (
+            Entity,
+            &Transform,
+            &Collider,
+            Has<OneWay>,
+            Has<Spike>,
+            Option<&TrampolineBounce>,
+            Option<&EasedPlatform>,
+        ),
+        Without<Player>,
+    >,
+    mut players: Query<(
+        &Player,
+        &mut Transform,
+        &mut Velocity,
+        &mut Momentum,
+        &mut GroundState,
+        &mut JumpController,
+    )>,
+) {
+    let Some(rollback) = prediction.rollback.take() else {
+        return;
+    };
+    let Some((player, mut transform, mut velocity, mut momentum, mut ground, mut jump)) = players
+        .iter_mut()
+        .find(|(player, ..)| matches!(player, Player::Local(_)))
+    else {
+        return;
+    };
+    let slot = player.slot();
+
+    world.reset(&rollback.snapshot);
+    let mut nearby: Vec<Entity> = world
+        .reach(rollback.inputs.len(), time.timestep())
+        .iter()
+        .flat_map(|area| broadphase.query(area))
+        .collect();
+    nearby.sort_unstable();
+    nearby.dedup();
+    world.sync_colliders(
+        nearby.into_iter().filter_map(|entity| colliders.get(entity).ok()),
+        rollback.inputs.len(),
+    );
+
+    // the jump key counts as held going in if the server says we are mid-jump.
+    let mut prev_mask = match prediction.authoritative {
+        Some(state) if state.is_jumping => protocol::INPUT_JUMP,
+        _ => 0,
+    };
+    for mask in rollback.inputs {
+        world.step(slot, mask, prev_mask, time.timestep());
+        prev_mask = mask;
+    }
+
+    let Some(predicted) = world.player_state(slot) else {
+        return;
+    };
+    prediction.predicted_pos = predicted.position;
+
+    let current = transform.translation.truncate();
+    let error = predicted.position - current;
+    if let Some(stats) = stats.as_mut() {
+        stats.on_correction(error.length());
+    }
+    let new = if error.length() > PREDICTION_SNAP_DISTANCE {
+        predicted.position
+    } else {
+        current + error * PREDICTION_CORRECTION_BLEND
+    };
+    transform.translation.x = new.x;
+    transform.translation.y = new.y;
+
+    apply_player_motion(
+        &predicted,
+        &mut velocity,
+        &mut momentum,
+        &mut ground,
+        &mut jump,
+    );
+}
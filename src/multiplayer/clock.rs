@@ -0,0 +1,154 @@
+// Client clock sync.
+//
+// the client keeps a sim tick in the server's numbering and runs it a little
+// ahead of the server: far enough that the input for a tick is already sitting
+// in the server's jitter buffer when the server gets there.
+//
+// every Pong carries the room's newest tick. by the time it gets here the
+// server has moved on by about half the round trip, which gives an estimate of
+// the server tick against our own fixed tick count. the sim tick follows that
+// estimate plus the lead by running virtual time a few percent faster or
+// slower, and jumps when it is too far off (first pong, restarted server,
+// back from the leaderboard).
+use bevy::prelude::*;
+use std::time::Duration;
+
+use super::input_buffer::JITTER_DEPTH;
+
+// ticks ahead of the server on top of the one-way trip and the jitter buffer.
+const LEAD_MARGIN: f64 = 1.0;
+// further off than this many ticks and we jump instead of easing over.
+const SNAP_TICKS: f64 = 8.0;
+// weight of a new pong in the smoothed offset and lead.
+const SMOOTHING: f64 = 0.1;
+// speed change per tick of error, and the most we ever change it by.
+const SPEED_GAIN: f64 = 0.02;
+const MAX_SPEED_ADJUST: f64 = 0.05;
+
+#[derive(Resource, Debug, Default)]
+pub struct ClockSync {
+    // fixed ticks run since connecting.
+    pub local_tick: u64,
+    // the server tick we are simulating, None until the first pong.
+    pub sim_tick: Option<u32>,
+    // how far ahead of the server sim_tick should run, in ticks.
+    pub lead: f64,
+    // estimated server tick minus local_tick, smoothed.
+    offset: Option<f64>,
+}
+
+impl ClockSync {
+    // `tick` is the server's newest tick when it answered a ping that took
+    // `rtt` to come back.
+    pub fn on_pong(&mut self, tick: u32, rtt: Duration, timestep: Duration) {
+        let one_way = rtt.as_secs_f64() / timestep.as_secs_f64() / 2.0;
+        let offset = tick as f64 + one_way - self.local_tick as f64;
+        let lead = one_way + JITTER_DEPTH as f64 + LEAD_MARGIN;
+        match self.offset {
+            Some(old) if (offset - old).abs() < SNAP_TICKS => {
+                self.offset = Some(old + (offset - old) * SMOOTHING);
+                self.lead += (lead - self.lead) * SMOOTHING;
+            }
+            _ => {
+                self.offset = Some(offset);
+                self.lead = lead;
+            }
+        }
+    }
+
+    // where we think the server is right now.
+    pub fn server_tick(&self) -> Option<f64> {
+        Some(self.local_tick as f64 + self.offset?)
+    }
+
+    // ticks sim_tick is behind where it should be, negative when ahead.
+    pub fn error(&self) -> Option<f64> {
+        Some(self.server_tick()? + self.lead - self.sim_tick? as f64)
+    }
+
+    // one fixed tick.
+    pub fn advance(&mut self) {
+        self.local_tick += 1;
+        self.sim_tick = self.sim_tick.map(|t| t.wrapping_add(1));
+        let Some(server) = self.server_tick() else {
+            return;
+        };
+        if self.error().is_none_or(|e| e.abs() > SNAP_TICKS) {
+            self.sim_tick = Some((server + self.lead).round().max(0.0) as u32);
+        }
+    }
+
+    // relative speed for virtual time that closes the gap.
+    pub fn speed(&self) -> f64 {
+        match self.error() {
+            Some(e) => 1.0 + (e * SPEED_GAIN).clamp(-MAX_SPEED_ADJUST, MAX_SPEED_ADJUST),
+            None => 1.0,
+        }
+    }
+}
+
+pub fn advance_clock_system(mut clock: ResMut<ClockSync>) {
+    clock.advance();
+}
+
+// back to normal speed once there is no server to follow.
+pub fn adjust_clock_speed_system(clock: Option<Res<ClockSync>>, mut time: ResMut<Time<Virtual>>) {
+    let speed = clock.map_or(1.0, |c| c.speed());
+    if time.relative_speed_f64() != speed {
+        time.set_relative_speed_f64(speed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_micros(16_667);
+
+    fn synced(server_tick: u32, rtt_ticks: u32) -> ClockSync {
+        let mut clock = ClockSync::default();
+        clock.on_pong(server_tick, TICK * rtt_ticks, TICK);
+        clock.advance();
+        clock
+    }
+
+    #[test]
+    fn the_first_pong_puts_the_sim_ahead_of_the_server() {
+        let clock = synced(1000, 10);
+        // half the round trip, plus the jitter buffer and the margin.
+        let lead = 5.0 + JITTER_DEPTH as f64 + LEAD_MARGIN;
+        let server = clock.server_tick().unwrap();
+        assert!((server - 1006.0).abs() < 0.01, "{server}");
+        assert_eq!(clock.sim_tick, Some((1006.0 + lead).round() as u32));
+        assert!(clock.error().unwrap().abs() < 0.5);
+        assert!((clock.speed() - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn a_sim_that_falls_behind_speeds_up_and_one_ahead_slows_down() {
+        // the server turns out to be 3 ticks further along than we thought.
+        let mut clock = synced(1000, 10);
+        for _ in 0..40 {
+            clock.on_pong(1003 + clock.local_tick as u32, TICK * 10, TICK);
+        }
+        assert!(clock.error().unwrap() > 2.5);
+        assert_eq!(clock.speed(), 1.0 + MAX_SPEED_ADJUST);
+
+        // or 3 ticks behind.
+        let mut clock = synced(1000, 10);
+        for _ in 0..40 {
+            clock.on_pong(997 + clock.local_tick as u32, TICK * 10, TICK);
+        }
+        assert!(clock.error().unwrap() < -2.5);
+        assert_eq!(clock.speed(), 1.0 - MAX_SPEED_ADJUST);
+    }
+
+    #[test]
+    fn a_restarted_server_snaps_the_sim_tick() {
+        let mut clock = synced(5000, 4);
+        clock.on_pong(10, TICK * 4, TICK);
+        clock.advance();
+        let sim = clock.sim_tick.unwrap();
+        assert!((10..=20).contains(&sim), "{sim}");
+    }
+}
@@ -0,0 +1,257 @@
+// Link conditioner for testing lag compensation.
+//
+// every datagram a side sends goes through ConditionedSocket, which can delay,
+// jitter, drop, duplicate and reorder it before it hits the transport. each
+// side only conditions what it sends, so set it on the server for
+// server -> client trouble and on the client for client -> server.
+//
+// the outage settings take the link down completely for a stretch at a time,
+// for the README's short (<200ms) and long (>1s) loss cases.
+use bevy::prelude::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+use super::runtime::NetRuntime;
+use super::stats::TrafficTable;
+use super::transport::Transport;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConditions {
+    // one-way delay added to every packet.
+    pub latency_ms: u32,
+    // each packet's delay is moved by up to this much either way.
+    pub jitter_ms: u32,
+    // chances, 0.0 to 1.0.
+    pub loss: f32,
+    pub duplicate: f32,
+    // a reordered packet is held back long enough for later ones to overtake it.
+    pub reorder: f32,
+    // the link goes down for the last `outage_ms` of every `outage_every_ms`.
+    pub outage_every_ms: u32,
+    pub outage_ms: u32,
+    // fixed seed for repeatable runs.
+    pub seed: Option<u64>,
+}
+
+impl LinkConditions {
+    pub fn is_enabled(&self) -> bool {
+        self.latency_ms > 0
+            || self.jitter_ms > 0
+            || self.loss > 0.0
+            || self.duplicate > 0.0
+            || self.reorder > 0.0
+            || (self.outage_every_ms > 0 && self.outage_ms > 0)
+    }
+}
+
+// smallest hold back for a reordered packet when there is no latency to
+// speak of.
+const MIN_REORDER_DELAY: Duration = Duration::from_millis(10);
+
+pub struct LinkConditioner {
+    conditions: LinkConditions,
+    rng: Mutex<SmallRng>,
+    started: Instant,
+}
+
+impl LinkConditioner {
+    pub fn new(conditions: LinkConditions) -> Self {
+        let rng = match conditions.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_rng(&mut rand::rng()),
+        };
+        Self {
+            conditions,
+            rng: Mutex::new(rng),
+            started: Instant::now(),
+        }
+    }
+
+    fn in_outage(&self, now: Instant) -> bool {
+        let c = &self.conditions;
+        if c.outage_every_ms == 0 || c.outage_ms == 0 {
+            return false;
+        }
+        let since = now.saturating_duration_since(self.started).as_millis();
+        let every = c.outage_every_ms as u128;
+        since % every >= every.saturating_sub(c.outage_ms as u128)
+    }
+
+    // delays to send copies of one packet after. empty when it is lost.
+    pub fn plan(&self, now: Instant) -> Vec<Duration> {
+        let c = &self.conditions;
+        if self.in_outage(now) {
+            return Vec::new();
+        }
+
+        let mut rng = self.rng.lock().unwrap();
+        if rng.random::<f32>() < c.loss {
+            return Vec::new();
+        }
+
+        let copies = if rng.random::<f32>() < c.duplicate {
+            2
+        } else {
+            1
+        };
+        (0..copies)
+            .map(|_| {
+                let jitter = c.jitter_ms as i64;
+                let ms = (c.latency_ms as i64 + rng.random_range(-jitter..=jitter)).max(0);
+                let mut delay = Duration::from_millis(ms as u64);
+                if rng.random::<f32>() < c.reorder {
+                    delay += Duration::from_millis((c.latency_ms + c.jitter_ms) as u64)
+                        .max(MIN_REORDER_DELAY);
+                }
+                delay
+            })
+            .collect()
+    }
+}
+
+// a Transport whose sends go through a LinkConditioner. cheap to clone, every
+// clone shares the same transport, the same conditioner and the same traffic
+// counts.
+#[derive(Clone)]
+pub struct ConditionedSocket {
+    socket: Arc<dyn Transport>,
+    link: Option<Arc<LinkConditioner>>,
+    traffic: TrafficTable,
+    // where delayed packets wait.
+    runtime: Handle,
+}
+
+impl ConditionedSocket {
+    pub fn new(socket: Arc<dyn Transport>, conditions: LinkConditions, runtime: &NetRuntime) -> Self {
+        let link = conditions.is_enabled().then(|| {
+            println!("[Net] Link conditioner on: {:?}", conditions);
+            Arc::new(LinkConditioner::new(conditions))
+        });
+        Self {
+            socket,
+            link,
+            traffic: TrafficTable::default(),
+            runtime: runtime.handle().clone(),
+        }
+    }
+
+    // what went through this socket, per peer. sends are counted when they
+    // are handed over, before the conditioner gets to drop or delay them.
+    pub fn traffic(&self) -> &TrafficTable {
+        &self.traffic
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (len, from) = self.socket.recv_from(buf).await?;
+        self.traffic.record_received(from, len);
+        Ok((len, from))
+    }
+
+    // without a conditioner this is a plain send_to. with one, delayed packets
+    // are sent from the network runtime and their errors are only logged.
+    // whatever is still held back when it shuts down is lost.
+    pub fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<()> {
+        self.traffic.record_sent(addr, data.len());
+        let Some(link) = &self.link else {
+            return self.socket.send_to(data, addr);
+        };
+
+        for delay in link.plan(Instant::now()) {
+            if delay.is_zero() {
+                self.socket.send_to(data, addr)?;
+                continue;
+            }
+            let socket = self.socket.clone();
+            let data = data.to_vec();
+            self.runtime.spawn(async move {
+                tokio::time::sleep(delay).await;
+                if let Err(e) = socket.send_to(&data, addr) {
+                    eprintln!("[Net] delayed send to {} failed: {}", addr, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditioner(conditions: LinkConditions) -> LinkConditioner {
+        LinkConditioner::new(LinkConditions {
+            seed: Some(7),
+            ..conditions
+        })
+    }
+
+    #[test]
+    fn default_conditions_are_disabled() {
+        assert!(!LinkConditions::default().is_enabled());
+        let plan = conditioner(LinkConditions::default()).plan(Instant::now());
+        assert_eq!(plan, vec![Duration::ZERO]);
+    }
+
+    #[test]
+    fn delays_stay_within_jitter() {
+        let link = conditioner(LinkConditions {
+            latency_ms: 80,
+            jitter_ms: 20,
+            ..Default::default()
+        });
+        for _ in 0..1000 {
+            let plan = link.plan(Instant::now());
+            assert_eq!(plan.len(), 1);
+            assert!(plan[0] >= Duration::from_millis(60) && plan[0] <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn loss_and_duplication_rates_are_roughly_right() {
+        let link = conditioner(LinkConditions {
+            loss: 0.25,
+            duplicate: 0.5,
+            ..Default::default()
+        });
+        let plans: Vec<_> = (0..10_000).map(|_| link.plan(Instant::now())).collect();
+        let lost = plans.iter().filter(|p| p.is_empty()).count();
+        let doubled = plans.iter().filter(|p| p.len() == 2).count();
+        assert!((2000..3000).contains(&lost), "lost {lost}");
+        assert!((3250..4250).contains(&doubled), "doubled {doubled}");
+    }
+
+    #[test]
+    fn reordered_packets_are_held_back() {
+        let link = conditioner(LinkConditions {
+            latency_ms: 30,
+            reorder: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(link.plan(Instant::now()), vec![Duration::from_millis(60)]);
+    }
+
+    #[test]
+    fn outage_drops_everything_inside_the_window() {
+        let link = conditioner(LinkConditions {
+            outage_every_ms: 2000,
+            outage_ms: 1500,
+            ..Default::default()
+        });
+        let start = link.started;
+        assert_eq!(link.plan(start + Duration::from_millis(100)).len(), 1);
+        assert!(link.plan(start + Duration::from_millis(600)).is_empty());
+        assert!(link.plan(start + Duration::from_millis(1999)).is_empty());
+        assert_eq!(link.plan(start + Duration::from_millis(2100)).len(), 1);
+    }
+}
@@ -0,0 +1,371 @@
+// Delta compression of world snapshots.
+//
+// the server keeps the last few snapshots it built. once a client acks a tick
+// that snapshot becomes its baseline and only the fields that changed since then
+// are sent. numbers travel as differences of quantized integers, so both sides
+// end up holding the same quantized value no matter how many deltas are chained.
+use bevy::math::{IVec2, Vec2};
+
+use super::protocol::{
+    PlayerState, RopeState, WorldSnapshot, pack_player_flags, unpack_player_flags,
+};
+
+// quantization steps (value * scale is rounded to an integer).
+pub const POSITION_SCALE: f32 = 16.0; // 1/16 px
+pub const VELOCITY_SCALE: f32 = 16.0; // 1/16 px/s
+pub const MOMENTUM_SCALE: f32 = 1.0; // 1 kg*px/s
+pub const TIMER_SCALE: f32 = 1000.0; // 1 ms
+pub const PLATFORM_T_SCALE: f32 = 10000.0;
+
+// how many snapshots are kept around as possible baselines (~1s at 60 Hz).
+pub const SNAPSHOT_HISTORY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlayerDelta {
+    pub slot: u8,
+    pub position: Option<IVec2>,
+    pub velocity: Option<IVec2>,
+    pub momentum: Option<IVec2>,
+    pub flags: Option<u8>,
+    pub coyote_elapsed: Option<i32>,
+    pub jump_time_elapsed: Option<i32>,
+    pub wall_jump_elapsed: Option<i32>,
+    // frames applied since the baseline, usually one per tick.
+    pub last_input: Option<i32>,
+}
+
+impl PlayerDelta {
+    fn is_empty(&self) -> bool {
+        self.position.is_none()
+            && self.velocity.is_none()
+            && self.momentum.is_none()
+            && self.flags.is_none()
+            && self.coyote_elapsed.is_none()
+            && self.jump_time_elapsed.is_none()
+            && self.wall_jump_elapsed.is_none()
+            && self.last_input.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlatformDelta {
+    pub id: u16,
+    pub position: Option<IVec2>,
+    pub t: Option<i32>,
+    pub forward: Option<bool>,
+}
+
+// only players/platforms that changed are listed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SnapshotDelta {
+    pub tick: u32,
+    pub baseline: u32,
+    pub players: Vec<PlayerDelta>,
+    // ropes almost never change, so they are resent whole when they do.
+    pub ropes: Option<Vec<RopeState>>,
+    pub platforms: Vec<PlatformDelta>,
+    pub coins_collected: i32,
+    // coins picked up since the baseline.
+    pub new_despawned_coins: Vec<u16>,
+    pub game_over: Option<f32>,
+}
+
+fn quantize(v: f32, scale: f32) -> i32 {
+    (v * scale).round() as i32
+}
+
+fn quantize_vec2(v: Vec2, scale: f32) -> IVec2 {
+    IVec2::new(quantize(v.x, scale), quantize(v.y, scale))
+}
+
+fn diff_f32(base: f32, current: f32, scale: f32) -> Option<i32> {
+    let d = quantize(current, scale) - quantize(base, scale);
+    (d != 0).then_some(d)
+}
+
+fn diff_vec2(base: Vec2, current: Vec2, scale: f32) -> Option<IVec2> {
+    let d = quantize_vec2(current, scale) - quantize_vec2(base, scale);
+    (d != IVec2::ZERO).then_some(d)
+}
+
+fn apply_f32(base: f32, d: Option<i32>, scale: f32) -> f32 {
+    match d {
+        Some(d) => (quantize(base, scale) + d) as f32 / scale,
+        None => base,
+    }
+}
+
+fn apply_vec2(base: Vec2, d: Option<IVec2>, scale: f32) -> Vec2 {
+    match d {
+        Some(d) => (quantize_vec2(base, scale) + d).as_vec2() / scale,
+        None => base,
+    }
+}
+
+fn diff_player(base: &PlayerState, current: &PlayerState) -> PlayerDelta {
+    let flags = pack_player_flags(current);
+    PlayerDelta {
+        slot: current.slot,
+        position: diff_vec2(base.position, current.position, POSITION_SCALE),
+        velocity: diff_vec2(base.velocity, current.velocity, VELOCITY_SCALE),
+        momentum: diff_vec2(base.momentum, current.momentum, MOMENTUM_SCALE),
+        flags: (flags != pack_player_flags(base)).then_some(flags),
+        coyote_elapsed: diff_f32(base.coyote_elapsed, current.coyote_elapsed, TIMER_SCALE),
+        jump_time_elapsed: diff_f32(
+            base.jump_time_elapsed,
+            current.jump_time_elapsed,
+            TIMER_SCALE,
+        ),
+        wall_jump_elapsed: diff_f32(
+            base.wall_jump_elapsed,
+            current.wall_jump_elapsed,
+            TIMER_SCALE,
+        ),
+        last_input: (current.last_input != base.last_input)
+            .then(|| current.last_input.wrapping_sub(base.last_input) as i32),
+    }
+}
+
+fn apply_player(state: &mut PlayerState, delta: &PlayerDelta) {
+    state.position = apply_vec2(state.position, delta.position, POSITION_SCALE);
+    state.velocity = apply_vec2(state.velocity, delta.velocity, VELOCITY_SCALE);
+    state.momentum = apply_vec2(state.momentum, delta.momentum, MOMENTUM_SCALE);
+    if let Some(flags) = delta.flags {
+        unpack_player_flags(state, flags);
+    }
+    state.coyote_elapsed = apply_f32(state.coyote_elapsed, delta.coyote_elapsed, TIMER_SCALE);
+    state.jump_time_elapsed = apply_f32(
+        state.jump_time_elapsed,
+        delta.jump_time_elapsed,
+        TIMER_SCALE,
+    );
+    state.wall_jump_elapsed = apply_f32(
+        state.wall_jump_elapsed,
+        delta.wall_jump_elapsed,
+        TIMER_SCALE,
+    );
+    if let Some(d) = delta.last_input {
+        state.last_input = state.last_input.wrapping_add(d as u32);
+    }
+}
+
+// None when the two snapshots don't describe the same set of players and
+// platforms (or coins came back); the caller should send a full snapshot then.
+pub fn diff(baseline: &WorldSnapshot, current: &WorldSnapshot) -> Option<SnapshotDelta> {
+    let same_players = baseline.players.len() == current.players.len()
+        && baseline
+            .players
+            .iter()
+            .zip(&current.players)
+            .all(|(a, b)| a.slot == b.slot);
+    let same_platforms = baseline.platforms.len() == current.platforms.len()
+        && baseline
+            .platforms
+            .iter()
+            .zip(&current.platforms)
+            .all(|(a, b)| a.id == b.id);
+    let coins_only_added = baseline
+        .despawned_coins
+        .iter()
+        .all(|id| current.despawned_coins.contains(id));
+
+    if !same_players || !same_platforms || !coins_only_added {
+        return None;
+    }
+
+    let players = baseline
+        .players
+        .iter()
+        .zip(&current.players)
+        .map(|(base, cur)| diff_player(base, cur))
+        .filter(|d| !d.is_empty())
+        .collect();
+
+    let platforms = baseline
+        .platforms
+        .iter()
+        .zip(&current.platforms)
+        .map(|(base, cur)| PlatformDelta {
+            id: cur.id,
+            position: diff_vec2(base.position, cur.position, POSITION_SCALE),
+            t: diff_f32(base.t, cur.t, PLATFORM_T_SCALE),
+            forward: (base.forward != cur.forward).then_some(cur.forward),
+        })
+        .filter(|d| d.position.is_some() || d.t.is_some() || d.forward.is_some())
+        .collect();
+
+    Some(SnapshotDelta {
+        tick: current.tick,
+        baseline: baseline.tick,
+        players,
+        ropes: (baseline.ropes != current.ropes).then(|| current.ropes.clone()),
+        platforms,
+        coins_collected: current.coins_collected as i32 - baseline.coins_collected as i32,
+        new_despawned_coins: current
+            .despawned_coins
+            .iter()
+            .filter(|id| !baseline.despawned_coins.contains(id))
+            .copied()
+            .collect(),
+        game_over: current.game_over,
+    })
+}
+
+// rebuild the full snapshot the server had at `delta.tick`.
+// None if the delta refers to a player or platform the baseline doesn't have.
+pub fn apply(baseline: &WorldSnapshot, delta: &SnapshotDelta) -> Option<WorldSnapshot> {
+    let mut snapshot = baseline.clone();
+    snapshot.tick = delta.tick;
+
+    for d in &delta.players {
+        let state = snapshot.players.iter_mut().find(|p| p.slot == d.slot)?;
+        apply_player(state, d);
+    }
+
+    if let Some(ropes) = &delta.ropes {
+        snapshot.ropes = ropes.clone();
+    }
+
+    for d in &delta.platforms {
+        let state = snapshot.platforms.iter_mut().find(|p| p.id == d.id)?;
+        state.position = apply_vec2(state.position, d.position, POSITION_SCALE);
+        state.t = apply_f32(state.t, d.t, PLATFORM_T_SCALE);
+        if let Some(forward) = d.forward {
+            state.forward = forward;
+        }
+    }
+
+    snapshot.coins_collected = (baseline.coins_collected as i32 + delta.coins_collected) as u32;
+    snapshot
+        .despawned_coins
+        .extend_from_slice(&delta.new_despawned_coins);
+    snapshot.game_over = delta.game_over;
+
+    Some(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multiplayer::protocol::PlatformState;
+
+    fn baseline() -> WorldSnapshot {
+        WorldSnapshot {
+            tick: 10,
+            players: vec![
+                PlayerState {
+                    slot: 0,
+                    position: Vec2::new(50.0, 16.0),
+                    can_wall_jump: true,
+                    ability_available: true,
+                    ..Default::default()
+                },
+                PlayerState {
+                    slot: 1,
+                    position: Vec2::new(350.0, 16.0),
+                    ..Default::default()
+                },
+            ],
+            ropes: vec![RopeState {
+                head: 0,
+                tail: 1,
+                rest_length: 300.0,
+                max_extension: 300.0,
+                spring_constant: 80000.0,
+            }],
+            platforms: vec![PlatformState {
+                id: 3,
+                position: Vec2::new(600.0, 900.0),
+                t: 0.25,
+                forward: true,
+            }],
+            coins_collected: 1,
+            despawned_coins: vec![7],
+            game_over: None,
+        }
+    }
+
+    #[test]
+    fn unchanged_world_produces_empty_delta() {
+        let base = baseline();
+        let mut current = base.clone();
+        current.tick = 11;
+
+        let delta = diff(&base, &current).unwrap();
+        assert!(delta.players.is_empty());
+        assert!(delta.platforms.is_empty());
+        assert!(delta.ropes.is_none());
+        assert_eq!(apply(&base, &delta), Some(current));
+    }
+
+    #[test]
+    fn changed_fields_round_trip_to_quantized_values() {
+        let base = baseline();
+        let mut current = base.clone();
+        current.tick = 12;
+        current.players[1].position = Vec2::new(361.37, 40.02);
+        current.players[1].velocity = Vec2::new(-120.5, 300.0);
+        current.players[1].grounded = true;
+        current.players[1].coyote_elapsed = 0.1;
+        current.players[1].last_input = 40;
+        current.platforms[0].t = 0.3;
+        current.platforms[0].position.y = 910.0;
+        current.coins_collected = 2;
+        current.despawned_coins.push(9);
+
+        let delta = diff(&base, &current).unwrap();
+        assert_eq!(delta.players.len(), 1);
+        assert_eq!(delta.players[0].slot, 1);
+        assert_eq!(delta.new_despawned_coins, vec![9]);
+
+        let rebuilt = apply(&base, &delta).unwrap();
+        let p = &rebuilt.players[1];
+        assert!(
+            (p.position - current.players[1].position)
+                .abs()
+                .max_element()
+                <= 0.5 / POSITION_SCALE
+        );
+        assert_eq!(p.velocity, current.players[1].velocity);
+        assert!(p.grounded);
+        assert_eq!(p.last_input, 40);
+        assert_eq!(rebuilt.players[0], base.players[0]);
+        assert!((rebuilt.platforms[0].t - 0.3).abs() <= 0.5 / PLATFORM_T_SCALE);
+        assert_eq!(rebuilt.coins_collected, 2);
+        assert_eq!(rebuilt.despawned_coins, vec![7, 9]);
+    }
+
+    #[test]
+    fn chained_deltas_do_not_drift() {
+        // the client rebuilds from its own rebuilt snapshots; the server diffs exact ones.
+        let mut server = baseline();
+        let mut client = server.clone();
+
+        for i in 1..200 {
+            let mut next = server.clone();
+            next.tick += 1;
+            next.players[0].position.x += 0.013 * i as f32;
+            next.players[0].velocity.y -= 0.7;
+
+            let delta = diff(&server, &next).unwrap();
+            client = apply(&client, &delta).unwrap();
+            server = next;
+        }
+
+        let err = (client.players[0].position - server.players[0].position).abs();
+        assert!(err.max_element() <= 0.5 / POSITION_SCALE);
+    }
+
+    #[test]
+    fn different_entity_sets_need_a_full_snapshot() {
+        let base = baseline();
+
+        let mut fewer_players = base.clone();
+        fewer_players.players.pop();
+        assert!(diff(&base, &fewer_players).is_none());
+
+        let mut coins_back = base.clone();
+        coins_back.despawned_coins.clear();
+        assert!(diff(&base, &coins_back).is_none());
+    }
+}
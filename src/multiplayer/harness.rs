@@ -0,0 +1,434 @@
+// In-process multiplayer test harness.
+//
+// a server app and two headless clients (app::headless_app) in one process,
+// plus any spectators a test adds, running the real plugins over an in-memory
+// transport (or loopback udp, see Harness::over_udp). every app moves
+// exactly one fixed tick per step, so the game side of a run doesn't depend on
+// how fast the machine is; only the packets travel in real time, which is why
+// a step waits a moment for the network threads.
+//
+// inputs are scripted by holding keys in a client's ButtonInput, the same
+// resource send_input_state_system reads.
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use super::ai_partner::AiPartner;
+use super::client::{ClientConnection, ClientPredictionState, ConnectionPhase, InputHistory};
+use super::clock::ClockSync;
+use super::conditioner::LinkConditions;
+use super::input_buffer::InputBuffer;
+use super::lifecycle::RoomOccupancy;
+use super::protocol::{MatchPhase, RoomRequest};
+use super::server::{NetChannels, RoomHost, RoomInputs, UdpServerSocket};
+use super::transport::{MemoryNetwork, NetTransport};
+use super::{UdpClientPlugin, UdpServerPlugin};
+use crate::app::{GameMode, headless_app, room_app};
+use crate::player::Player;
+
+const TICK: Duration = Duration::from_micros(16_667);
+// time for the network tasks to move a step's packets.
+const NET_WAIT: Duration = Duration::from_millis(1);
+
+fn stepped(mut app: App) -> App {
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(TICK));
+    app
+}
+
+fn test_room() -> App {
+    stepped(room_app())
+}
+
+pub struct Harness {
+    pub server: App,
+    // the two players by slot, then spectators.
+    pub clients: Vec<App>,
+    // clients step() leaves alone, as if they hadn't been started yet.
+    pub held: Vec<usize>,
+    server_addr: SocketAddr,
+    transport: NetTransport,
+}
+
+fn client_app(server_addr: SocketAddr, mode: GameMode, transport: &NetTransport) -> App {
+    let mut client = headless_app(mode);
+    client.add_plugins(UdpClientPlugin {
+        server_addr: server_addr.to_string(),
+        room: RoomRequest::Any,
+        link: LinkConditions::default(),
+        transport: transport.clone(),
+    });
+    stepped(client)
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Self::build(NetTransport::Memory(MemoryNetwork::default()), false)
+    }
+
+    // the same over real sockets on loopback.
+    pub fn over_udp() -> Self {
+        Self::build(NetTransport::Udp, false)
+    }
+
+    // a server whose rooms have the AI drive empty slots.
+    pub fn with_ai_partner() -> Self {
+        Self::build(NetTransport::Memory(MemoryNetwork::default()), true)
+    }
+
+    fn build(transport: NetTransport, ai_partner: bool) -> Self {
+        let mut server = App::new();
+        server
+            .add_plugins(MinimalPlugins)
+            .add_plugins(UdpServerPlugin {
+                bind_addr: "127.0.0.1:0".to_string(),
+                new_room: test_room,
+                link: LinkConditions::default(),
+                transport: transport.clone(),
+                ai_partner,
+            });
+        let mut server = stepped(server);
+        // Startup binds the socket.
+        server.update();
+        let server_addr = server
+            .world()
+            .resource::<UdpServerSocket>()
+            .socket
+            .local_addr()
+            .unwrap();
+
+        let clients = (0..2)
+            .map(|slot| client_app(server_addr, GameMode::NetCoop(slot), &transport))
+            .collect();
+
+        Self {
+            server,
+            clients,
+            held: Vec::new(),
+            server_addr,
+            transport,
+        }
+    }
+
+    // a spectator client, connecting from the next step on. returns its index
+    // in `clients`.
+    pub fn spectate(&mut self) -> usize {
+        self.clients.push(client_app(
+            self.server_addr,
+            GameMode::Spectate,
+            &self.transport,
+        ));
+        self.clients.len() - 1
+    }
+
+    pub fn step(&mut self) {
+        self.server.update();
+        for (i, client) in self.clients.iter_mut().enumerate() {
+            if !self.held.contains(&i) {
+                client.update();
+            }
+        }
+        thread::sleep(NET_WAIT);
+    }
+
+    pub fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    // steps until `done` holds, for at most `ticks`. true if it did.
+    pub fn run_until(&mut self, ticks: u32, mut done: impl FnMut(&mut Self) -> bool) -> bool {
+        for _ in 0..ticks {
+            if done(self) {
+                return true;
+            }
+            self.step();
+        }
+        done(self)
+    }
+
+    pub fn press(&mut self, slot: usize, key: KeyCode) {
+        self.keys(slot).press(key);
+    }
+
+    pub fn release(&mut self, slot: usize, key: KeyCode) {
+        self.keys(slot).release(key);
+    }
+
+    fn keys(&mut self, slot: usize) -> Mut<'_, ButtonInput<KeyCode>> {
+        self.clients[slot]
+            .world_mut()
+            .resource_mut::<ButtonInput<KeyCode>>()
+    }
+
+    pub fn connection(&self, slot: usize) -> Option<&ClientConnection> {
+        self.clients[slot]
+            .world()
+            .get_resource::<ClientConnection>()
+    }
+
+    pub fn connected(&self, slot: usize) -> bool {
+        self.connection(slot)
+            .is_some_and(|c| matches!(c.phase, ConnectionPhase::Connected { .. }))
+    }
+
+    pub fn match_phase(&self, slot: usize) -> Option<MatchPhase> {
+        self.connection(slot).and_then(|c| c.match_phase)
+    }
+
+    pub fn prediction(&self, slot: usize) -> Option<&ClientPredictionState> {
+        self.clients[slot]
+            .world()
+            .get_resource::<ClientPredictionState>()
+    }
+
+    pub fn clock(&self, client: usize) -> Option<&ClockSync> {
+        self.clients[client].world().get_resource::<ClockSync>()
+    }
+
+    // newest input frame `client` has sent.
+    pub fn input_seq(&self, client: usize) -> Option<u32> {
+        let history = self.clients[client].world().get_resource::<InputHistory>()?;
+        history.entries.last().map(|e| e.seq)
+    }
+
+    // where `client` shows `player`, local or not.
+    pub fn client_position(&mut self, client: usize, player: usize) -> Option<Vec2> {
+        player_position(self.clients[client].world_mut(), player)
+    }
+
+    // where the server's room has `player`. the harness only ever opens one.
+    pub fn server_position(&mut self, player: usize) -> Option<Vec2> {
+        let mut host = self.server.world_mut().non_send_resource_mut::<RoomHost>();
+        let room = host.rooms.values_mut().next()?;
+        player_position(room.app.world_mut(), player)
+    }
+
+    pub fn server_tick(&self) -> Option<u32> {
+        let host = self.server.world().non_send_resource::<RoomHost>();
+        let room = host.rooms.values().next()?;
+        let channels = room.app.world().resource::<NetChannels>();
+        Some(channels.tick.load(Ordering::Relaxed))
+    }
+
+    // the room's jitter buffer for `slot`.
+    pub fn server_inputs(&self, slot: usize) -> Option<&InputBuffer> {
+        let host = self.server.world().non_send_resource::<RoomHost>();
+        let room = host.rooms.values().next()?;
+        room.app.world().resource::<RoomInputs>().0.get(slot)
+    }
+
+    pub fn room_occupancy(&mut self) -> Option<usize> {
+        let host = self.server.world().non_send_resource::<RoomHost>();
+        let room = host.rooms.values().next()?;
+        Some(room.app.world().resource::<RoomOccupancy>().players())
+    }
+
+    // whether the AI is driving `slot` in the room.
+    pub fn ai_drives(&self, slot: usize) -> bool {
+        let host = self.server.world().non_send_resource::<RoomHost>();
+        host.rooms.values().next().is_some_and(|room| {
+            room.app
+                .world()
+                .get_resource::<AiPartner>()
+                .is_some_and(|ai| ai.input(slot).is_some())
+        })
+    }
+}
+
+fn player_position(world: &mut World, slot: usize) -> Option<Vec2> {
+    world
+        .query::<(&Player, &Transform)>()
+        .iter(world)
+        .find(|(player, _)| player.slot() == slot)
+        .map(|(_, transform)| transform.translation.truncate())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONNECT_TICKS: u32 = 300;
+    // the room counts down for lifecycle::COUNTDOWN before it plays.
+    const ROUND_START_TICKS: u32 = 600;
+    // how far apart the client's and the server's positions may settle.
+    const CONVERGED: f32 = 2.0;
+    // pings go out every half second of real time, a step takes a few ms.
+    const PING_TICKS: u32 = 2000;
+
+    fn connected_harness() -> Harness {
+        connected(Harness::new())
+    }
+
+    fn connected(mut harness: Harness) -> Harness {
+        assert!(
+            harness.run_until(CONNECT_TICKS, |h| h.connected(0) && h.connected(1)),
+            "handshake did not complete"
+        );
+        harness
+    }
+
+    #[test]
+    fn both_clients_are_welcomed_into_the_same_room() {
+        let mut harness = connected(Harness::over_udp());
+
+        let rooms = [0, 1].map(|slot| harness.connection(slot).unwrap().room);
+        assert!(rooms[0].is_some());
+        assert_eq!(rooms[0], rooms[1]);
+        assert_eq!(harness.connection(0).unwrap().slot, 0);
+        assert_eq!(harness.connection(1).unwrap().slot, 1);
+
+        harness.step();
+        assert_eq!(harness.room_occupancy(), Some(2));
+    }
+
+    #[test]
+    fn snapshots_reach_both_clients() {
+        let mut harness = connected_harness();
+        assert!(harness.run_until(CONNECT_TICKS, |h| {
+            [0, 1].iter().all(|&slot| {
+                h.prediction(slot)
+                    .is_some_and(|p| p.last_server_tick > 0 && p.authoritative.is_some())
+            })
+        }));
+
+        // each client hears about its partner too, a little behind because
+        // remote players are interpolated.
+        let partner = harness.client_position(0, 1).unwrap();
+        let server = harness.server_position(1).unwrap();
+        assert!(partner.distance(server) < 100.0, "{partner} vs {server}");
+    }
+
+    #[test]
+    fn spectators_watch_without_taking_a_slot() {
+        let mut harness = Harness::new();
+        let spectator = harness.spectate();
+        assert!(harness.run_until(CONNECT_TICKS, |h| {
+            (0..3).all(|client| h.connected(client))
+                && h.prediction(spectator)
+                    .is_some_and(|p| p.last_server_tick > 0)
+        }));
+        assert_eq!(harness.room_occupancy(), Some(2));
+
+        // the round starts for the two climbers all the same.
+        assert!(harness.run_until(ROUND_START_TICKS, |h| {
+            h.match_phase(spectator) == Some(MatchPhase::Playing)
+        }));
+        harness.run(30);
+        for player in 0..2 {
+            let seen = harness.client_position(spectator, player).unwrap();
+            let server = harness.server_position(player).unwrap();
+            assert!(seen.distance(server) < 100.0, "{seen} vs {server}");
+        }
+    }
+
+    #[test]
+    fn inputs_reach_the_server_before_the_tick_they_are_stamped_for() {
+        let mut harness = connected_harness();
+        assert!(harness.run_until(PING_TICKS, |h| {
+            (0..2).all(|slot| {
+                h.prediction(slot)
+                    .and_then(|p| p.authoritative)
+                    .is_some_and(|a| a.last_input > 0)
+            })
+        }));
+        // the clock eases onto its lead after the first pongs.
+        harness.run(60);
+        let underruns = [0, 1].map(|slot| harness.server_inputs(slot).unwrap().underruns());
+
+        for _ in 0..300 {
+            harness.step();
+            let server = harness.server_tick().unwrap();
+            for slot in 0..2 {
+                // the frame the room just simulated was stamped for that tick,
+                // and it was already waiting there.
+                let inputs = harness.server_inputs(slot).unwrap();
+                assert_eq!(inputs.last_applied(), server, "slot {slot}");
+                assert_eq!(inputs.underruns(), underruns[slot], "slot {slot}");
+
+                // the client hears the same about each snapshot.
+                let prediction = harness.prediction(slot).unwrap();
+                let applied = prediction.authoritative.unwrap().last_input;
+                assert_eq!(applied, prediction.last_server_tick, "slot {slot}");
+
+                // and is already simulating ticks the server hasn't got to.
+                let sim = harness.clock(slot).unwrap().sim_tick.unwrap();
+                assert_eq!(harness.input_seq(slot), Some(sim));
+                assert!(
+                    sim > server && sim < server + 10,
+                    "sim {sim}, server {server}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn an_ai_partner_plays_the_empty_slot_until_a_client_claims_it() {
+        let mut harness = Harness::with_ai_partner();
+        harness.held.push(1);
+        assert!(harness.run_until(ROUND_START_TICKS, |h| {
+            h.match_phase(0) == Some(MatchPhase::Playing)
+        }));
+        assert_eq!(harness.room_occupancy(), Some(1));
+        assert!(harness.ai_drives(1));
+        assert!(!harness.ai_drives(0));
+
+        // the second client takes the climber over mid-round.
+        harness.held.clear();
+        assert!(harness.run_until(CONNECT_TICKS, |h| {
+            h.connected(1) && h.match_phase(1) == Some(MatchPhase::Playing)
+        }));
+        harness.step();
+        assert!(!harness.ai_drives(1));
+        assert_eq!(harness.connection(1).unwrap().slot, 1);
+        assert_eq!(harness.match_phase(0), Some(MatchPhase::Playing));
+
+        harness.run(60);
+        let predicted = harness.client_position(1, 1).unwrap();
+        let server = harness.server_position(1).unwrap();
+        assert!(predicted.distance(server) < 100.0, "{predicted} vs {server}");
+    }
+
+    #[test]
+    fn predicted_and_authoritative_positions_converge() {
+        let mut harness = connected_harness();
+        assert!(harness.run_until(ROUND_START_TICKS, |h| {
+            h.match_phase(0) == Some(MatchPhase::Playing)
+                && h.match_phase(1) == Some(MatchPhase::Playing)
+        }));
+        // settle on the ground first.
+        harness.run(60);
+        let start = harness.server_position(0).unwrap();
+
+        harness.press(0, KeyCode::KeyD);
+        harness.run(45);
+        harness.release(0, KeyCode::KeyD);
+        harness.run(120);
+
+        let server = harness.server_position(0).unwrap();
+        assert!(
+            server.x > start.x + 10.0,
+            "server never moved: {start} -> {server}"
+        );
+
+        let predicted = harness.client_position(0, 0).unwrap();
+        let authoritative = harness.prediction(0).unwrap().authoritative_pos;
+        assert!(
+            predicted.distance(server) < CONVERGED,
+            "{predicted} vs {server}"
+        );
+        assert!(
+            authoritative.distance(server) < CONVERGED,
+            "{authoritative} vs {server}"
+        );
+
+        let seen_by_partner = harness.client_position(1, 0).unwrap();
+        assert!(
+            seen_by_partner.distance(server) < CONVERGED,
+            "{seen_by_partner} vs {server}"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+// Server-side jitter buffer for one player's input stream.
+//
+// clients stamp every input frame with the server tick it is meant for and
+// run far enough ahead that it gets here a few ticks early. frames are
+// repeated in every packet, so they show up more than once and out of order;
+// the buffer keeps each once, in a slot for its tick, and the room takes out
+// exactly the one for the tick it is simulating.
+//
+// a frame that isn't there in time is an underrun: the last frame is held, the
+// same as a key still being down, and the frame is dropped if it turns up late.
+use std::collections::BTreeMap;
+
+use super::protocol;
+
+// ticks early a frame should arrive, the client's slack for jitter.
+pub const JITTER_DEPTH: usize = 2;
+// frames stamped further ahead of the room than this are a client whose clock
+// hasn't settled yet; don't hold on to them.
+const MAX_LEAD: u32 = 120;
+
+#[derive(Debug, Default)]
+pub struct InputBuffer {
+    frames: BTreeMap<u32, u8>,
+    // the tick last simulated, None before the first.
+    tick: Option<u32>,
+    // tick of the newest frame applied, None until the first one has been.
+    applied: Option<u32>,
+    last_mask: u8,
+    // ticks since the stream started whose frame wasn't there in time.
+    underruns: u32,
+}
+
+// one frame's input, plus the release edge the movement system wants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedInput {
+    pub mask: u8,
+    pub jump_just_released: bool,
+}
+
+impl InputBuffer {
+    // `masks[i]` is the frame for tick `seq - i`, like in Message::Input.
+    pub fn insert(&mut self, seq: u32, masks: &[u8]) {
+        for (i, &mask) in masks.iter().enumerate() {
+            let Some(frame) = seq.checked_sub(i as u32) else {
+                break;
+            };
+            // that tick has been simulated already.
+            if self.tick.is_some_and(|tick| frame <= tick) {
+                break;
+            }
+            if self
+                .tick
+                .is_some_and(|tick| frame > tick.saturating_add(MAX_LEAD))
+            {
+                continue;
+            }
+            self.frames.entry(frame).or_insert(mask);
+        }
+    }
+
+    // tick of the newest frame applied, 0 before the first. a held frame
+    // doesn't count, the client is told about each frame once.
+    pub fn last_applied(&self) -> u32 {
+        self.applied.unwrap_or(0)
+    }
+
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+
+    // the input for `tick`. None until the stream has started.
+    pub fn pop(&mut self, tick: u32) -> Option<AppliedInput> {
+        self.tick = Some(tick);
+        // frames for ticks that went by without them were lost or reordered
+        // past their slot.
+        while let Some(entry) = self.frames.first_entry()
+            && *entry.key() < tick
+        {
+            entry.remove();
+        }
+
+        let prev = self.last_mask;
+        match self.frames.remove(&tick) {
+            Some(mask) => {
+                self.applied = Some(tick);
+                self.last_mask = mask;
+            }
+            None if self.applied.is_none() => return None,
+            None => self.underruns += 1,
+        }
+
+        let jump = self.last_mask & protocol::INPUT_JUMP != 0;
+        let jump_prev = prev & protocol::INPUT_JUMP != 0;
+        Some(AppliedInput {
+            mask: self.last_mask,
+            jump_just_released: !jump && jump_prev,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::INPUT_JUMP;
+
+    // frames `seq` down to `seq - n + 1`, each frame's mask is its seq.
+    fn packet(seq: u32, n: u32) -> Vec<u8> {
+        (0..n).map(|i| (seq - i) as u8).collect()
+    }
+
+    // the room simulating `ticks`, None where it had no input yet.
+    fn masks(buffer: &mut InputBuffer, ticks: std::ops::RangeInclusive<u32>) -> Vec<Option<u8>> {
+        ticks.map(|t| buffer.pop(t).map(|i| i.mask)).collect()
+    }
+
+    #[test]
+    fn redundant_and_reordered_frames_play_once_on_their_tick() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert(3, &packet(3, 3));
+        buffer.insert(5, &packet(5, 3));
+        buffer.insert(4, &packet(4, 3));
+        assert_eq!(buffer.frames.len(), 5);
+        assert_eq!(
+            masks(&mut buffer, 1..=5),
+            [1, 2, 3, 4, 5].map(Some).to_vec()
+        );
+        assert_eq!(buffer.last_applied(), 5);
+        assert_eq!(buffer.underruns(), 0);
+
+        // everything in here has already been simulated.
+        buffer.insert(4, &packet(4, 4));
+        assert_eq!(buffer.frames.len(), 0);
+    }
+
+    #[test]
+    fn frames_wait_for_their_tick_and_the_stream_starts_on_the_first() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert(12, &packet(12, 3));
+        assert_eq!(
+            masks(&mut buffer, 8..=12),
+            vec![None, None, Some(10), Some(11), Some(12)]
+        );
+        assert_eq!(buffer.underruns(), 0);
+    }
+
+    #[test]
+    fn a_late_frame_is_an_underrun_and_the_last_one_is_held() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert(2, &[0, INPUT_JUMP]);
+        assert_eq!(buffer.pop(1).unwrap().mask, INPUT_JUMP);
+        assert!(buffer.pop(2).unwrap().jump_just_released);
+
+        buffer.insert(3, &[INPUT_JUMP]);
+        assert_eq!(buffer.pop(3).unwrap().mask, INPUT_JUMP);
+        // nothing for 4 yet: held.
+        let held = buffer.pop(4).unwrap();
+        assert_eq!(held.mask, INPUT_JUMP);
+        assert!(!held.jump_just_released);
+        assert_eq!(buffer.underruns(), 1);
+        assert_eq!(buffer.last_applied(), 3);
+
+        // 4 turning up now is too late, 5 plays on time.
+        buffer.insert(5, &[0, 0]);
+        assert_eq!(buffer.frames.len(), 1);
+        let released = buffer.pop(5).unwrap();
+        assert_eq!(released.mask, 0);
+        assert!(released.jump_just_released);
+        assert_eq!(buffer.last_applied(), 5);
+    }
+
+    #[test]
+    fn lost_frames_are_skipped_and_far_ahead_ones_ignored() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert(2, &packet(2, 2));
+        buffer.insert(10, &packet(10, 2));
+        assert_eq!(masks(&mut buffer, 1..=2), vec![Some(1), Some(2)]);
+        // 3 to 8 never came.
+        assert_eq!(masks(&mut buffer, 3..=10)[6..], [Some(9), Some(10)]);
+        assert_eq!(buffer.underruns(), 6);
+
+        buffer.insert(10 + MAX_LEAD + 5, &packet(10 + MAX_LEAD + 5, 8));
+        assert_eq!(buffer.frames.len(), 3);
+    }
+}
@@ -0,0 +1,226 @@
+// Interpolation of remote players.
+//
+// every Player::Net keeps the last few server states it was sent and is drawn
+// `delay` behind the newest server time, between the two ticks around that
+// point. when packets stop coming it keeps moving along the last velocity for
+// a short while (the README's <200ms case) and then holds still.
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::protocol::PlayerState;
+use super::snapshot::apply_player_motion;
+use crate::components::motion::{GroundState, JumpController, Momentum, Velocity};
+use crate::player::Player;
+
+// ~0.5s of history at 60 Hz, more than any sane delay.
+const BUFFER_CAPACITY: usize = 32;
+
+#[derive(Resource, Debug, Clone)]
+pub struct InterpolationSettings {
+    // how far behind the newest server state remote players are drawn.
+    // should cover a couple of snapshot intervals plus jitter.
+    pub delay: Duration,
+    // how long to keep extrapolating once the buffer runs dry.
+    pub max_extrapolation: Duration,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(200),
+        }
+    }
+}
+
+// estimate of (server time - local time), from snapshot arrival times.
+#[derive(Resource, Debug, Default)]
+pub struct InterpolationClock {
+    pub offset: Option<f64>,
+}
+
+// a jump bigger than this (server restart, long stall) resets the estimate.
+const CLOCK_SNAP_SECS: f64 = 0.25;
+const CLOCK_SMOOTHING: f64 = 0.05;
+
+impl InterpolationClock {
+    pub fn observe(&mut self, server_secs: f64, local_secs: f64) {
+        let sample = server_secs - local_secs;
+        self.offset = Some(match self.offset {
+            Some(offset) if (sample - offset).abs() < CLOCK_SNAP_SECS => {
+                offset + (sample - offset) * CLOCK_SMOOTHING
+            }
+            _ => sample,
+        });
+    }
+
+    pub fn server_secs(&self, local_secs: f64) -> Option<f64> {
+        self.offset.map(|offset| local_secs + offset)
+    }
+}
+
+#[derive(Component, Debug, Default)]
+pub struct SnapshotBuffer {
+    samples: VecDeque<(u32, PlayerState)>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, tick: u32, state: PlayerState) {
+        if self.samples.back().is_some_and(|(last, _)| tick <= *last) {
+            return;
+        }
+        if self.samples.len() == BUFFER_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((tick, state));
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    // state at `render_tick` (fractional server tick). motion is blended,
+    // everything else comes from the older of the two samples.
+    pub fn sample(
+        &self,
+        render_tick: f64,
+        tick_secs: f64,
+        max_extrapolation_ticks: f64,
+    ) -> Option<PlayerState> {
+        let (first_tick, first) = self.samples.front()?;
+        if render_tick <= *first_tick as f64 {
+            return Some(*first);
+        }
+
+        for ((a_tick, a), (b_tick, b)) in self.samples.iter().zip(self.samples.iter().skip(1)) {
+            let (a_tick, b_tick) = (*a_tick as f64, *b_tick as f64);
+            if render_tick <= b_tick {
+                let t = ((render_tick - a_tick) / (b_tick - a_tick)) as f32;
+                return Some(PlayerState {
+                    position: a.position.lerp(b.position, t),
+                    velocity: a.velocity.lerp(b.velocity, t),
+                    momentum: a.momentum.lerp(b.momentum, t),
+                    ..*a
+                });
+            }
+        }
+
+        // ran past the newest sample.
+        let (last_tick, last) = self.samples.back()?;
+        let ahead = (render_tick - *last_tick as f64).min(max_extrapolation_ticks);
+        Some(PlayerState {
+            position: last.position + last.velocity * (ahead * tick_secs) as f32,
+            ..*last
+        })
+    }
+}
+
+pub fn interpolate_remote_players_system(
+    settings: Res<InterpolationSettings>,
+    clock: Res<InterpolationClock>,
+    real: Res<Time<Real>>,
+    fixed: Res<Time<Fixed>>,
+    mut players: Query<(
+        &Player,
+        &SnapshotBuffer,
+        &mut Transform,
+        &mut Velocity,
+        &mut Momentum,
+        &mut GroundState,
+        &mut JumpController,
+    )>,
+) {
+    let Some(server_secs) = clock.server_secs(real.elapsed_secs_f64()) else {
+        return;
+    };
+    let tick_secs = fixed.timestep().as_secs_f64();
+    let render_tick = (server_secs - settings.delay.as_secs_f64()) / tick_secs;
+    let max_extrapolation = settings.max_extrapolation.as_secs_f64() / tick_secs;
+
+    for (player, buffer, mut transform, mut velocity, mut momentum, mut ground, mut jump) in
+        players.iter_mut()
+    {
+        if !matches!(player, Player::Net(_)) {
+            continue;
+        }
+        let Some(state) = buffer.sample(render_tick, tick_secs, max_extrapolation) else {
+            continue;
+        };
+
+        transform.translation.x = state.position.x;
+        transform.translation.y = state.position.y;
+        apply_player_motion(&state, &mut velocity, &mut momentum, &mut ground, &mut jump);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f64 = 1.0 / 60.0;
+
+    fn at(x: f32, vx: f32) -> PlayerState {
+        PlayerState {
+            position: Vec2::new(x, 0.0),
+            velocity: Vec2::new(vx, 0.0),
+            ..Default::default()
+        }
+    }
+
+    fn buffer() -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(10, at(0.0, 60.0));
+        buffer.push(12, at(2.0, 60.0));
+        buffer.push(14, at(4.0, 60.0));
+        buffer
+    }
+
+    #[test]
+    fn interpolates_between_surrounding_ticks() {
+        let state = buffer().sample(11.0, TICK, 12.0).unwrap();
+        assert!((state.position.x - 1.0).abs() < 1e-4);
+
+        let state = buffer().sample(13.5, TICK, 12.0).unwrap();
+        assert!((state.position.x - 3.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn holds_oldest_sample_before_buffer_start() {
+        let state = buffer().sample(5.0, TICK, 12.0).unwrap();
+        assert_eq!(state.position.x, 0.0);
+    }
+
+    #[test]
+    fn extrapolates_for_a_limited_time() {
+        // 60 px/s is 1 px per tick.
+        let state = buffer().sample(16.0, TICK, 12.0).unwrap();
+        assert!((state.position.x - 6.0).abs() < 1e-3);
+
+        let state = buffer().sample(100.0, TICK, 12.0).unwrap();
+        assert!((state.position.x - 16.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn stale_and_duplicate_ticks_are_ignored() {
+        let mut buffer = buffer();
+        buffer.push(13, at(100.0, 0.0));
+        buffer.push(14, at(100.0, 0.0));
+        let state = buffer.sample(13.0, TICK, 12.0).unwrap();
+        assert!((state.position.x - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn clock_smooths_small_jitter_and_snaps_on_big_jumps() {
+        let mut clock = InterpolationClock::default();
+        clock.observe(10.0, 4.0);
+        assert_eq!(clock.server_secs(5.0), Some(11.0));
+
+        clock.observe(10.1, 4.0);
+        let offset = clock.offset.unwrap();
+        assert!(offset > 6.0 && offset < 6.1);
+
+        clock.observe(0.5, 4.0);
+        assert_eq!(clock.offset, Some(-3.5));
+    }
+}
@@ -0,0 +1,212 @@
+// Match lifecycle of a server room.
+//
+//   Waiting -> Countdown -> Playing -> Finished -> Restarting -> Waiting / Countdown
+//
+// the room owns it and tells its clients about every transition on the
+// reliable channel; they follow along instead of deciding game over
+// themselves. a round ends on MaxHeightReached (summit or spikes). the next
+// one starts FINISHED_HOLD later, or as soon as a player asks for a restart.
+//
+// restarting leaves InGame and enters it again, which despawns and respawns
+// the players, rope, coins and platforms, so every round starts from a fresh
+// copy of the map without touching the process.
+use bevy::prelude::*;
+use std::time::Duration;
+
+use super::ai_partner::AiPartner;
+use super::protocol::{MatchPhase, ReliableEvent};
+use super::server::{NetChannels, send_room_event};
+use crate::config::MyAppState;
+use crate::game_ui::ui::{MaxHeight, TotalCoin};
+use crate::physics::MaxHeightReached;
+
+pub const PLAYERS_PER_ROOM: usize = 2;
+pub const COUNTDOWN: Duration = Duration::from_secs(3);
+// how long the leaderboard stays up before the next round on its own.
+pub const FINISHED_HOLD: Duration = Duration::from_secs(10);
+
+// player slots a client holds, kept up to date by the lobby.
+#[derive(Resource, Debug, Default)]
+pub struct RoomOccupancy(pub [bool; PLAYERS_PER_ROOM]);
+
+impl RoomOccupancy {
+    pub fn players(&self) -> usize {
+        self.0.iter().filter(|&&claimed| claimed).count()
+    }
+
+    pub fn claimed(&self, slot: usize) -> bool {
+        self.0.get(slot).copied().unwrap_or(false)
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct MatchState {
+    pub phase: MatchPhase,
+    pub round: u32,
+    // room time the current phase started at.
+    pub since: Duration,
+    // a player asked for the next round while the leaderboard is up.
+    pub restart_requested: bool,
+}
+
+impl Default for MatchState {
+    fn default() -> Self {
+        Self {
+            phase: MatchPhase::Waiting,
+            round: 1,
+            since: Duration::ZERO,
+            restart_requested: false,
+        }
+    }
+}
+
+impl MatchState {
+    // the phase to move to, if any. `in_game` is whether the room's world is
+    // back in InGame after a restart.
+    pub fn next_phase(
+        &self,
+        now: Duration,
+        occupancy: usize,
+        game_over: bool,
+        in_game: bool,
+    ) -> Option<MatchPhase> {
+        let full = occupancy >= PLAYERS_PER_ROOM;
+        let elapsed = now.saturating_sub(self.since);
+        match self.phase {
+            MatchPhase::Waiting if full => Some(MatchPhase::Countdown),
+            MatchPhase::Countdown if !full => Some(MatchPhase::Waiting),
+            MatchPhase::Countdown if elapsed >= COUNTDOWN => Some(MatchPhase::Playing),
+            MatchPhase::Playing if game_over => Some(MatchPhase::Finished),
+            MatchPhase::Finished if self.restart_requested || elapsed >= FINISHED_HOLD => {
+                Some(MatchPhase::Restarting)
+            }
+            MatchPhase::Restarting if in_game && full => Some(MatchPhase::Countdown),
+            MatchPhase::Restarting if in_game => Some(MatchPhase::Waiting),
+            _ => None,
+        }
+    }
+
+    fn enter(&mut self, phase: MatchPhase, now: Duration) {
+        self.phase = phase;
+        self.since = now;
+        self.restart_requested = false;
+    }
+}
+
+pub fn match_lifecycle_system(
+    time: Res<Time>,
+    occupancy: Res<RoomOccupancy>,
+    ai: Option<Res<AiPartner>>,
+    mut game_over: EventReader<MaxHeightReached>,
+    app_state: Res<State<MyAppState>>,
+    mut next_app_state: ResMut<NextState<MyAppState>>,
+    mut state: ResMut<MatchState>,
+    mut total_coin: ResMut<TotalCoin>,
+    mut max_height: ResMut<MaxHeight>,
+    channels: Res<NetChannels>,
+) {
+    let game_over = game_over.read().count() > 0;
+    let in_game = *app_state.get() == MyAppState::InGame;
+    let now = time.elapsed();
+    // AI partners fill the room as soon as one client is in it.
+    let players = match ai {
+        Some(_) if occupancy.players() > 0 => PLAYERS_PER_ROOM,
+        _ => occupancy.players(),
+    };
+    let Some(phase) = state.next_phase(now, players, game_over, in_game) else {
+        return;
+    };
+
+    match phase {
+        MatchPhase::Finished => next_app_state.set(MyAppState::EndCredit),
+        MatchPhase::Restarting => {
+            total_coin.amount = 0;
+            max_height.amount = 0;
+            state.round += 1;
+            next_app_state.set(MyAppState::InGame);
+        }
+        _ => {}
+    }
+
+    println!(
+        "[Server] Room {} round {}: {:?} -> {:?}",
+        channels.room, state.round, state.phase, phase
+    );
+    state.enter(phase, now);
+    send_room_event(&channels, ReliableEvent::PhaseChanged(phase));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(phase: MatchPhase) -> MatchState {
+        MatchState {
+            phase,
+            ..Default::default()
+        }
+    }
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn a_full_room_counts_down_into_a_round() {
+        let state = at(MatchPhase::Waiting);
+        assert_eq!(state.next_phase(SEC, 1, false, true), None);
+        assert_eq!(
+            state.next_phase(SEC, 2, false, true),
+            Some(MatchPhase::Countdown)
+        );
+
+        let state = at(MatchPhase::Countdown);
+        assert_eq!(state.next_phase(SEC, 2, false, true), None);
+        assert_eq!(
+            state.next_phase(SEC, 1, false, true),
+            Some(MatchPhase::Waiting)
+        );
+        assert_eq!(
+            state.next_phase(COUNTDOWN, 2, false, true),
+            Some(MatchPhase::Playing)
+        );
+    }
+
+    #[test]
+    fn game_over_only_ends_a_running_round() {
+        assert_eq!(
+            at(MatchPhase::Playing).next_phase(SEC, 2, true, true),
+            Some(MatchPhase::Finished)
+        );
+        // a partner dropping out doesn't end the round.
+        assert_eq!(
+            at(MatchPhase::Playing).next_phase(SEC, 1, false, true),
+            None
+        );
+        assert_eq!(at(MatchPhase::Waiting).next_phase(SEC, 1, true, true), None);
+    }
+
+    #[test]
+    fn finished_rounds_restart_on_request_or_after_the_hold() {
+        let mut state = at(MatchPhase::Finished);
+        assert_eq!(state.next_phase(SEC, 2, false, false), None);
+        assert_eq!(
+            state.next_phase(FINISHED_HOLD, 2, false, false),
+            Some(MatchPhase::Restarting)
+        );
+        state.restart_requested = true;
+        assert_eq!(
+            state.next_phase(SEC, 2, false, false),
+            Some(MatchPhase::Restarting)
+        );
+
+        let state = at(MatchPhase::Restarting);
+        assert_eq!(state.next_phase(SEC, 2, false, false), None);
+        assert_eq!(
+            state.next_phase(SEC, 2, false, true),
+            Some(MatchPhase::Countdown)
+        );
+        assert_eq!(
+            state.next_phase(SEC, 1, false, true),
+            Some(MatchPhase::Waiting)
+        );
+    }
+}
@@ -1,57 +1,165 @@
 use bevy::prelude::*;
+pub mod ai_partner;
 pub mod client;
+pub mod clock;
+pub mod conditioner;
+pub mod delta;
+#[cfg(all(test, feature = "server"))]
+mod harness;
+pub mod input_buffer;
+pub mod interpolation;
+pub mod lifecycle;
+pub mod prediction;
+pub mod protocol;
+pub mod rate_limit;
+pub mod reliable;
+pub mod runtime;
 pub mod server;
+pub mod snapshot;
+pub mod spectator;
+pub mod stats;
+pub mod transport;
 
 use crate::{app::GameMode, config::MyAppState};
 use client::*;
 use server::*;
+use spectator::*;
 
-pub struct UdpServerPlugin;
+pub struct UdpServerPlugin {
+    pub bind_addr: String,
+    // builds the world for a new room (see app::room_app).
+    pub new_room: fn() -> App,
+    pub link: conditioner::LinkConditions,
+    pub transport: transport::NetTransport,
+    // let the AI drive player slots no client holds.
+    pub ai_partner: bool,
+}
 
 impl Plugin for UdpServerPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ClientRegistry::default())
+        app.insert_resource(ServerBindAddress(self.bind_addr.clone()))
+            .insert_resource(self.link)
+            .insert_resource(self.transport.clone())
+            .init_resource::<runtime::NetRuntime>()
+            .insert_resource(ClientRegistry::default())
+            .insert_non_send_resource(RoomHost::new(
+                self.new_room,
+                self.ai_partner.then(ai_partner::AiPolicy::load),
+            ))
+            .add_systems(Startup, setup_udp_server)
             .add_systems(
-                Startup,
-                setup_udp_server.after(crate::player::load_players::spawn_players),
+                Update,
+                (
+                    handle_handshakes_system,
+                    close_empty_rooms_system,
+                    update_rooms_system,
+                    send_reliable_system,
+                )
+                    .chain()
+                    .run_if(resource_exists::<LobbyChannels>),
             )
+            .add_systems(FixedUpdate, evict_stale_clients_system.run_if(has_clients))
             .add_systems(
-                FixedUpdate,
-                send_snapshots_system
-                    .run_if(has_clients)
-                    .run_if(in_state(MyAppState::InGame)),
+                Update,
+                log_connection_stats_system.run_if(resource_exists::<UdpServerSocket>),
             )
             .add_systems(
-                FixedUpdate,
-                process_remote_inputs_system.run_if(in_state(MyAppState::InGame)),
+                Last,
+                (
+                    disconnect_clients_system.run_if(resource_exists::<UdpServerSocket>),
+                    runtime::shutdown_runtime_system,
+                )
+                    .chain()
+                    .run_if(on_event::<AppExit>),
             );
     }
 }
 
 pub struct UdpClientPlugin {
     pub server_addr: String,
+    pub room: protocol::RoomRequest,
+    pub link: conditioner::LinkConditions,
+    pub transport: transport::NetTransport,
 }
 
 impl Plugin for UdpClientPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ServerAddress(self.server_addr.clone()))
+            .insert_resource(RequestedRoom(self.room))
+            .insert_resource(self.link)
+            .insert_resource(self.transport.clone())
+            .init_resource::<runtime::NetRuntime>()
+            .init_resource::<interpolation::InterpolationSettings>()
+            .init_resource::<interpolation::InterpolationClock>()
             .add_systems(
                 OnEnter(MyAppState::InGame),
-                client_handshake.run_if(|mode: Option<Res<GameMode>>| {
-                    matches!(mode.as_deref(), Some(GameMode::NetCoop(_)))
-                }),
+                (
+                    client_disconnect.run_if(leaving_net_game),
+                    client_handshake.run_if(|mode: Option<Res<GameMode>>| {
+                        matches!(
+                            mode.as_deref(),
+                            Some(GameMode::NetCoop(_) | GameMode::Spectate)
+                        )
+                    }),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                FixedUpdate,
+                // stamped with the tick the clock just moved to.
+                (send_input_state_system.after(clock::advance_clock_system),)
+                    .run_if(|mode: Option<Res<GameMode>>| {
+                        matches!(mode.as_deref(), Some(GameMode::NetCoop(_)))
+                    })
+                    .run_if(client_connected),
+            )
+            .add_systems(
+                FixedUpdate,
+                send_spectator_ack_system
+                    .run_if(spectating)
+                    .run_if(client_connected),
+            )
+            .add_systems(
+                Update,
+                switch_followed_player_system
+                    .run_if(spectating)
+                    .run_if(in_state(MyAppState::InGame)),
+            )
+            .add_systems(
+                Update,
+                client_connection_system.run_if(resource_exists::<ClientConnection>),
+            )
+            .add_systems(
+                Update,
+                interpolation::interpolate_remote_players_system
+                    .run_if(in_state(MyAppState::InGame)),
+            )
+            .add_systems(
+                Update,
+                apply_reliable_events_system.run_if(resource_exists::<ClientNetChannels>),
+            )
+            .add_systems(
+                Update,
+                stats::update_net_stats_system.run_if(resource_exists::<stats::NetStats>),
             )
             .add_systems(
                 FixedUpdate,
-                (send_input_state_system,).run_if(|mode: Option<Res<GameMode>>| {
-                    matches!(mode.as_deref(), Some(GameMode::NetCoop(_)))
-                }),
+                clock::advance_clock_system.run_if(resource_exists::<clock::ClockSync>),
             )
+            .add_systems(Update, clock::adjust_clock_speed_system)
             .add_systems(
                 FixedUpdate,
-                apply_snapshot_system
+                (apply_snapshot_system, predict_local_player_system)
+                    .chain()
                     .run_if(resource_exists::<ClientNetChannels>)
+                    .run_if(resource_exists::<prediction::PredictionWorld>)
                     .run_if(in_state(MyAppState::InGame)),
+            )
+            .add_systems(
+                Last,
+                (client_disconnect, runtime::shutdown_runtime_system)
+                    .chain()
+                    .run_if(on_event::<AppExit>),
             );
     }
 }
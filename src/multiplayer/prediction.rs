@@ -0,0 +1,229 @@
+// Client-side prediction.
+//
+// a private copy of both climbers, the rope and the colliders near them, stepped with
+// the same physics::step::step PhysicsPlugin runs on FixedUpdate. when a
+// snapshot arrives the copy is reset to the server state and the local inputs
+// the server hasn't applied yet are replayed on top, so the result is what the
+// server will compute once those inputs reach it.
+use bevy::math::bounding::{Aabb2d, BoundingVolume};
+use bevy::prelude::*;
+use std::time::Duration;
+
+use super::protocol::{self, PlayerState, WorldSnapshot};
+use super::snapshot::{apply_player_motion, capture_player};
+use crate::components::motion::{Momentum, Velocity};
+use crate::components::rope::RopeConstraint;
+use crate::map::{Collider, EasedPlatform, TrampolineBounce};
+use crate::physics::broadphase::{SWEEP_MARGIN, swept_area};
+use crate::physics::step::{self, Body, BodyInput, PhysicsState, RopeLink, Solid};
+
+#[derive(Resource)]
+pub struct PredictionWorld {
+    // bodies[slot] is that slot's climber.
+    state: PhysicsState,
+}
+
+impl Default for PredictionWorld {
+    fn default() -> Self {
+        Self {
+            state: PhysicsState {
+                bodies: vec![Body::at(Vec2::ZERO), Body::at(Vec2::ZERO)],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl PredictionWorld {
+    // mirror the main world's colliders near the climbers (moving platforms
+    // move, coins and spawned platforms come and go); see reach. moving
+    // platforms are wound back `ticks` of their current motion so the replay
+    // moves them to where they are now.
+    pub fn sync_colliders<'a>(
+        &mut self,
+        colliders: impl Iterator<
+            Item = (
+                Entity,
+                &'a Transform,
+                &'a Collider,
+                bool,
+                bool,
+                Option<&'a TrampolineBounce>,
+                Option<&'a EasedPlatform>,
+            ),
+        >,
+        ticks: usize,
+    ) {
+        self.state.solids = colliders
+            .map(
+                |(_, transform, collider, is_one_way, is_spike, trampoline, moving)| {
+                    let mut solid = Solid::new(
+                        transform, collider, is_one_way, is_spike, trampoline, moving,
+                    );
+                    solid.aabb = solid.aabb.translated_by(-solid.motion * ticks as f32);
+                    solid
+                },
+            )
+            .collect();
+    }
+
+    // the areas each climber can get to in `ticks` steps of `dt` from where it
+    // is now, for picking colliders out of the broadphase.
+    pub fn reach(&self, ticks: usize, dt: Duration) -> Vec<Aabb2d> {
+        let ticks = ticks.max(1) as f32;
+        self.state
+            .bodies
+            .iter()
+            .map(|body| {
+                swept_area(
+                    &body.collider.translated_by(body.position),
+                    body.velocity * dt.as_secs_f32() * ticks,
+                    SWEEP_MARGIN * ticks,
+                )
+            })
+            .collect()
+    }
+
+    // rewind to the server's view of the world.
+    pub fn reset(&mut self, snapshot: &WorldSnapshot) {
+        for state in &snapshot.players {
+            if let Some(body) = self.state.bodies.get_mut(state.slot as usize) {
+                reset_body(body, state);
+            }
+        }
+
+        self.state.ropes = snapshot
+            .ropes
+            .first()
+            .map(|state| RopeLink {
+                head: state.head as usize % 2,
+                tail: state.tail as usize % 2,
+                constraint: RopeConstraint {
+                    rest_length: state.rest_length,
+                    max_extension: state.max_extension,
+                    spring_constant: state.spring_constant,
+                },
+            })
+            .into_iter()
+            .collect();
+    }
+
+    // one fixed tick with `mask` held by the player in `slot`.
+    pub fn step(&mut self, slot: usize, mask: u8, prev_mask: u8, dt: Duration) {
+        let jump = mask & protocol::INPUT_JUMP != 0;
+        let jump_prev = prev_mask & protocol::INPUT_JUMP != 0;
+        let mut inputs = [None; 2];
+        inputs[slot % 2] = Some(BodyInput {
+            left: mask & protocol::INPUT_LEFT != 0,
+            right: mask & protocol::INPUT_RIGHT != 0,
+            jump_pressed: jump,
+            jump_just_released: !jump && jump_prev,
+            down: mask & protocol::INPUT_DOWN != 0,
+        });
+        for solid in &mut self.state.solids {
+            solid.aabb = solid.aabb.translated_by(solid.motion);
+        }
+        // coins and deaths are the server's call.
+        step::step(&mut self.state, &inputs, dt);
+    }
+
+    pub fn player_state(&self, slot: usize) -> Option<PlayerState> {
+        let body = self.state.bodies.get(slot)?;
+        Some(capture_player(
+            slot,
+            &Transform::from_translation(body.position.extend(0.0)),
+            &Velocity(body.velocity),
+            &Momentum(body.momentum),
+            &body.ground,
+            &body.jump,
+        ))
+    }
+}
+
+fn reset_body(body: &mut Body, state: &PlayerState) {
+    let mut velocity = Velocity::default();
+    let mut momentum = Momentum::default();
+    apply_player_motion(
+        state,
+        &mut velocity,
+        &mut momentum,
+        &mut body.ground,
+        &mut body.jump,
+    );
+    body.position = state.position;
+    body.velocity = velocity.0;
+    body.momentum = momentum.0;
+    body.net_force = Vec2::ZERO;
+    body.control_force = Vec2::ZERO;
+    body.rope_force = Vec2::ZERO;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::player::PLAYER_LENGTH;
+
+    const DT: Duration = Duration::from_micros(15625);
+
+    fn world_on_floor() -> PredictionWorld {
+        let mut world = PredictionWorld::default();
+        let transform = Transform::from_xyz(400.0, 100.0, 0.0);
+        let collider = Collider {
+            aabb: Aabb2d::new(Vec2::ZERO, Vec2::new(400.0, 10.0)),
+        };
+        world.sync_colliders(
+            std::iter::once((
+                Entity::from_raw(1),
+                &transform,
+                &collider,
+                true,
+                false,
+                None,
+                None,
+            )),
+            0,
+        );
+        world.reset(&WorldSnapshot {
+            players: vec![
+                PlayerState {
+                    slot: 0,
+                    position: Vec2::new(300.0, 200.0),
+                    ..Default::default()
+                },
+                PlayerState {
+                    slot: 1,
+                    position: Vec2::new(600.0, 200.0),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        });
+        world
+    }
+
+    #[test]
+    fn idle_player_falls_and_lands() {
+        let mut world = world_on_floor();
+        for _ in 0..120 {
+            world.step(0, 0, 0, DT);
+        }
+
+        let state = world.player_state(0).unwrap();
+        assert!(state.grounded);
+        assert!((state.position.y - (110.0 + PLAYER_LENGTH / 2.0)).abs() < 2.0);
+    }
+
+    #[test]
+    fn holding_right_moves_right() {
+        let mut idle = world_on_floor();
+        let mut moving = world_on_floor();
+        for _ in 0..60 {
+            idle.step(0, 0, 0, DT);
+            moving.step(0, protocol::INPUT_RIGHT, protocol::INPUT_RIGHT, DT);
+        }
+
+        let idle = idle.player_state(0).unwrap();
+        let moving = moving.player_state(0).unwrap();
+        assert!(moving.position.x > idle.position.x + 10.0);
+    }
+}
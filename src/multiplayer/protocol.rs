@@ -0,0 +1,1248 @@
+// Shared wire format for everything that goes over the UDP socket.
+//
+// every datagram starts with a two byte header:
+//   [version: u8][kind: u8][payload...]
+// all multi-byte fields are big-endian, except the zigzag varints that carry
+// quantized differences inside delta snapshots.
+//
+// decode never panics: anything short, unknown, or from a different protocol
+// version comes back as a ProtocolError so the receive loops can just drop it.
+use bevy::math::{IVec2, Vec2};
+use std::fmt;
+
+use super::delta::{PlatformDelta, PlayerDelta, SnapshotDelta};
+
+pub const PROTOCOL_VERSION: u8 = 11;
+
+// largest datagram either side will read.
+pub const MAX_PACKET_SIZE: usize = 1500;
+
+const HEADER_LEN: usize = 2;
+
+// input bitmask layout (same bits the keyboard is packed into on the client).
+pub const INPUT_JUMP: u8 = 1 << 0;
+pub const INPUT_LEFT: u8 = 1 << 1;
+pub const INPUT_DOWN: u8 = 1 << 2;
+pub const INPUT_RIGHT: u8 = 1 << 3;
+
+// most input frames one Input packet may carry.
+pub const MAX_INPUT_FRAMES: usize = 32;
+
+// the slot a spectator asks for in Hello and is welcomed with. spectators get
+// snapshots and reliable events but control nobody, and any number can watch.
+pub const SPECTATOR_SLOT: u8 = u8::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum MessageKind {
+    Hello = 0,
+    Welcome = 1,
+    Reject = 2,
+    Input = 3,
+    Snapshot = 4,
+    Disconnect = 5,
+    DeltaSnapshot = 6,
+    Ping = 7,
+    PartnerLeft = 8,
+    Reliable = 9,
+    ReliableAck = 10,
+    Pong = 11,
+}
+
+impl MessageKind {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Hello),
+            1 => Some(Self::Welcome),
+            2 => Some(Self::Reject),
+            3 => Some(Self::Input),
+            4 => Some(Self::Snapshot),
+            5 => Some(Self::Disconnect),
+            6 => Some(Self::DeltaSnapshot),
+            7 => Some(Self::Ping),
+            8 => Some(Self::PartnerLeft),
+            9 => Some(Self::Reliable),
+            10 => Some(Self::ReliableAck),
+            11 => Some(Self::Pong),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RejectReason {
+    VersionMismatch = 0,
+    InvalidSlot = 1,
+    // someone else is connected in that slot.
+    SlotTaken = 2,
+    // Hello asked to join a room the server isn't hosting.
+    NoSuchRoom = 3,
+    Unknown = 255,
+}
+
+impl RejectReason {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::VersionMismatch,
+            1 => Self::InvalidSlot,
+            2 => Self::SlotTaken,
+            3 => Self::NoSuchRoom,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+// which room a Hello wants to be put in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoomRequest {
+    // the first room with the slot free, or a new one if there is none.
+    #[default]
+    Any,
+    // always a fresh room.
+    Create,
+    Join(u32),
+}
+
+const ROOM_ANY: u8 = 0;
+const ROOM_CREATE: u8 = 1;
+const ROOM_JOIN: u8 = 2;
+
+// where a room's match is at (see lifecycle.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MatchPhase {
+    // fewer than two players in the room.
+    Waiting = 0,
+    Countdown = 1,
+    Playing = 2,
+    // someone died or reached the summit, the leaderboard is up.
+    Finished = 3,
+    // the room is resetting the world for the next round.
+    Restarting = 4,
+}
+
+impl MatchPhase {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Waiting),
+            1 => Some(Self::Countdown),
+            2 => Some(Self::Playing),
+            3 => Some(Self::Finished),
+            4 => Some(Self::Restarting),
+            _ => None,
+        }
+    }
+}
+
+// things that must arrive, exactly once and in order (see reliable.rs).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReliableEvent {
+    // server -> client: coin `id` was picked up, `total` is the new count.
+    CoinCollected { id: u16, total: u32 },
+    // server -> client: MaxHeightReached fired.
+    GameOver { height: f32 },
+    // server -> client: the room's match moved to `phase`.
+    PhaseChanged(MatchPhase),
+    // client -> server: play again after the match ended.
+    RestartRequested,
+}
+
+const EVENT_COIN_COLLECTED: u8 = 0;
+const EVENT_GAME_OVER: u8 = 1;
+const EVENT_PHASE_CHANGED: u8 = 2;
+const EVENT_RESTART_REQUESTED: u8 = 3;
+
+// player state flags, packed into one byte.
+const FLAG_GROUNDED: u8 = 1 << 0;
+const FLAG_JUMPING: u8 = 1 << 1;
+const FLAG_CAN_WALL_JUMP: u8 = 1 << 2;
+const FLAG_ABILITY: u8 = 1 << 3;
+
+// which fields follow a player entry in a delta snapshot.
+const PLAYER_POSITION: u8 = 1 << 0;
+const PLAYER_VELOCITY: u8 = 1 << 1;
+const PLAYER_MOMENTUM: u8 = 1 << 2;
+const PLAYER_FLAGS: u8 = 1 << 3;
+const PLAYER_COYOTE: u8 = 1 << 4;
+const PLAYER_JUMP_TIME: u8 = 1 << 5;
+const PLAYER_WALL_JUMP: u8 = 1 << 6;
+const PLAYER_LAST_INPUT: u8 = 1 << 7;
+
+// same for a platform entry. forward is a single bit so it lives in the mask.
+const PLATFORM_POSITION: u8 = 1 << 0;
+const PLATFORM_T: u8 = 1 << 1;
+const PLATFORM_FORWARD_CHANGED: u8 = 1 << 2;
+const PLATFORM_FORWARD: u8 = 1 << 3;
+
+// everything the physics and jump logic read for one climber.
+// timers are sent as elapsed seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PlayerState {
+    pub slot: u8,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub momentum: Vec2,
+    pub grounded: bool,
+    pub coyote_elapsed: f32,
+    pub is_jumping: bool,
+    pub jump_time_elapsed: f32,
+    pub can_wall_jump: bool,
+    pub wall_jump_elapsed: f32,
+    pub ability_available: bool,
+    // newest Input seq of this player the server had applied by this tick,
+    // 0 before the first. the client replays only the frames after it.
+    pub last_input: u32,
+}
+
+// rope endpoints are identified by player slot.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RopeState {
+    pub head: u8,
+    pub tail: u8,
+    pub rest_length: f32,
+    pub max_extension: f32,
+    pub spring_constant: f32,
+}
+
+// moving platforms are identified by their MapEntityId.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PlatformState {
+    pub id: u16,
+    pub position: Vec2,
+    pub t: f32,
+    pub forward: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WorldSnapshot {
+    pub tick: u32,
+    pub players: Vec<PlayerState>,
+    pub ropes: Vec<RopeState>,
+    pub platforms: Vec<PlatformState>,
+    // TotalCoin on the server.
+    pub coins_collected: u32,
+    // MapEntityIds of every coin that has been picked up so far.
+    pub despawned_coins: Vec<u16>,
+    // set on the tick a MaxHeightReached fired (spike, enemy or summit).
+    pub game_over: Option<f32>,
+}
+
+const PLAYER_STATE_LEN: usize = 1 + 3 * 8 + 1 + 3 * 4 + 4;
+const ROPE_STATE_LEN: usize = 2 + 3 * 4;
+const PLATFORM_STATE_LEN: usize = 2 + 8 + 4 + 1;
+// smallest possible entries in a delta: id + mask with no fields.
+const PLAYER_DELTA_MIN_LEN: usize = 2;
+const PLATFORM_DELTA_MIN_LEN: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // client -> server: ask for a player slot (0 = P1, 1 = P2, or
+    // SPECTATOR_SLOT to watch).
+    // `token` is 0 on the first connect and the one from the last Welcome when
+    // reconnecting, which proves we are the client the slot belongs to.
+    Hello {
+        slot: u8,
+        token: u64,
+        room: RoomRequest,
+    },
+    // server -> client: handshake accepted, you control `slot` in `room`.
+    // `session` names the connection in logs; `token` is its secret and has to
+    // come with every Input.
+    Welcome {
+        slot: u8,
+        session: u32,
+        room: u32,
+        token: u64,
+    },
+    // server -> client: handshake refused.
+    Reject {
+        reason: RejectReason,
+    },
+    // client -> server: the newest input frames, newest first (`masks[i]` is
+    // frame `seq - i`) so a lost packet is covered by the next one, plus the
+    // newest snapshot tick the client has (0 = none yet) so the server can
+    // delta against it. spectators send no frames, only the ack.
+    Input {
+        token: u64,
+        seq: u32,
+        masks: Vec<u8>,
+        ack: u32,
+    },
+    // server -> client: authoritative world state.
+    Snapshot(WorldSnapshot),
+    // server -> client: world state relative to a snapshot the client acked.
+    DeltaSnapshot(SnapshotDelta),
+    // either direction: the sender is going away.
+    Disconnect,
+    // client -> server: keep-alive, answered with a Pong carrying the same
+    // id so the client can time the round trip.
+    Ping {
+        id: u32,
+    },
+    // server -> client: the player in `slot` timed out or disconnected.
+    PartnerLeft {
+        slot: u8,
+    },
+    // either direction: message `seq` on the reliable channel.
+    Reliable {
+        seq: u32,
+        event: ReliableEvent,
+    },
+    // either direction: the sender has every reliable message below `next`.
+    ReliableAck {
+        next: u32,
+    },
+    // server -> client: answer to Ping `id`, with the room's newest tick
+    // so the client can line its clock up with the server's.
+    Pong {
+        id: u32,
+        tick: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    Empty,
+    VersionMismatch { expected: u8, found: u8 },
+    UnknownKind(u8),
+    Truncated { needed: usize, remaining: usize },
+    TrailingBytes(usize),
+    MalformedVarint,
+    UnknownEvent(u8),
+    UnknownRoomRequest(u8),
+    TooManyInputFrames(usize),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Empty => write!(f, "empty packet"),
+            ProtocolError::VersionMismatch { expected, found } => write!(
+                f,
+                "protocol version mismatch (expected {}, found {})",
+                expected, found
+            ),
+            ProtocolError::UnknownKind(kind) => write!(f, "unknown message kind {}", kind),
+            ProtocolError::Truncated { needed, remaining } => write!(
+                f,
+                "truncated packet (needed {} more bytes, {} remaining)",
+                needed, remaining
+            ),
+            ProtocolError::TrailingBytes(n) => write!(f, "{} unexpected trailing bytes", n),
+            ProtocolError::MalformedVarint => write!(f, "varint longer than 5 bytes"),
+            ProtocolError::UnknownEvent(kind) => write!(f, "unknown reliable event {}", kind),
+            ProtocolError::UnknownRoomRequest(kind) => write!(f, "unknown room request {}", kind),
+            ProtocolError::TooManyInputFrames(count) => {
+                write!(f, "{} input frames in one packet", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+// -----------------------------------------------------------
+//                        ENCODE
+// -----------------------------------------------------------
+pub fn encode(msg: &Message) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16);
+    buf.push(PROTOCOL_VERSION);
+
+    match msg {
+        Message::Hello { slot, token, room } => {
+            buf.push(MessageKind::Hello as u8);
+            buf.push(*slot);
+            buf.extend_from_slice(&token.to_be_bytes());
+            match room {
+                RoomRequest::Any => buf.push(ROOM_ANY),
+                RoomRequest::Create => buf.push(ROOM_CREATE),
+                RoomRequest::Join(id) => {
+                    buf.push(ROOM_JOIN);
+                    buf.extend_from_slice(&id.to_be_bytes());
+                }
+            }
+        }
+        Message::Welcome {
+            slot,
+            session,
+            room,
+            token,
+        } => {
+            buf.push(MessageKind::Welcome as u8);
+            buf.push(*slot);
+            buf.extend_from_slice(&session.to_be_bytes());
+            buf.extend_from_slice(&room.to_be_bytes());
+            buf.extend_from_slice(&token.to_be_bytes());
+        }
+        Message::Reject { reason } => {
+            buf.push(MessageKind::Reject as u8);
+            buf.push(*reason as u8);
+        }
+        Message::Input {
+            token,
+            seq,
+            masks,
+            ack,
+        } => {
+            buf.push(MessageKind::Input as u8);
+            buf.extend_from_slice(&token.to_be_bytes());
+            buf.extend_from_slice(&seq.to_be_bytes());
+            let masks = &masks[..masks.len().min(MAX_INPUT_FRAMES)];
+            buf.push(masks.len() as u8);
+            buf.extend_from_slice(masks);
+            buf.extend_from_slice(&ack.to_be_bytes());
+        }
+        Message::Snapshot(snapshot) => {
+            buf.push(MessageKind::Snapshot as u8);
+            encode_snapshot(&mut buf, snapshot);
+        }
+        Message::DeltaSnapshot(delta) => {
+            buf.push(MessageKind::DeltaSnapshot as u8);
+            encode_delta(&mut buf, delta);
+        }
+        Message::Disconnect => {
+            buf.push(MessageKind::Disconnect as u8);
+        }
+        Message::Ping { id } => {
+            buf.push(MessageKind::Ping as u8);
+            buf.extend_from_slice(&id.to_be_bytes());
+        }
+        Message::Pong { id, tick } => {
+            buf.push(MessageKind::Pong as u8);
+            buf.extend_from_slice(&id.to_be_bytes());
+            buf.extend_from_slice(&tick.to_be_bytes());
+        }
+        Message::PartnerLeft { slot } => {
+            buf.push(MessageKind::PartnerLeft as u8);
+            buf.push(*slot);
+        }
+        Message::Reliable { seq, event } => {
+            buf.push(MessageKind::Reliable as u8);
+            buf.extend_from_slice(&seq.to_be_bytes());
+            encode_event(&mut buf, event);
+        }
+        Message::ReliableAck { next } => {
+            buf.push(MessageKind::ReliableAck as u8);
+            buf.extend_from_slice(&next.to_be_bytes());
+        }
+    }
+
+    buf
+}
+
+fn encode_event(buf: &mut Vec<u8>, event: &ReliableEvent) {
+    match event {
+        ReliableEvent::CoinCollected { id, total } => {
+            buf.push(EVENT_COIN_COLLECTED);
+            buf.extend_from_slice(&id.to_be_bytes());
+            buf.extend_from_slice(&total.to_be_bytes());
+        }
+        ReliableEvent::GameOver { height } => {
+            buf.push(EVENT_GAME_OVER);
+            put_f32(buf, *height);
+        }
+        ReliableEvent::PhaseChanged(phase) => {
+            buf.push(EVENT_PHASE_CHANGED);
+            buf.push(*phase as u8);
+        }
+        ReliableEvent::RestartRequested => buf.push(EVENT_RESTART_REQUESTED),
+    }
+}
+
+fn put_f32(buf: &mut Vec<u8>, v: f32) {
+    buf.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_vec2(buf: &mut Vec<u8>, v: Vec2) {
+    put_f32(buf, v.x);
+    put_f32(buf, v.y);
+}
+
+// zigzag so small negative differences stay small, then LEB128.
+fn put_varint(buf: &mut Vec<u8>, v: i32) {
+    let mut z = ((v << 1) ^ (v >> 31)) as u32;
+    while z >= 0x80 {
+        buf.push((z as u8 & 0x7F) | 0x80);
+        z >>= 7;
+    }
+    buf.push(z as u8);
+}
+
+fn put_ivec2(buf: &mut Vec<u8>, v: IVec2) {
+    put_varint(buf, v.x);
+    put_varint(buf, v.y);
+}
+
+pub(super) fn pack_player_flags(p: &PlayerState) -> u8 {
+    let mut flags = 0u8;
+    if p.grounded {
+        flags |= FLAG_GROUNDED;
+    }
+    if p.is_jumping {
+        flags |= FLAG_JUMPING;
+    }
+    if p.can_wall_jump {
+        flags |= FLAG_CAN_WALL_JUMP;
+    }
+    if p.ability_available {
+        flags |= FLAG_ABILITY;
+    }
+    flags
+}
+
+pub(super) fn unpack_player_flags(p: &mut PlayerState, flags: u8) {
+    p.grounded = flags & FLAG_GROUNDED != 0;
+    p.is_jumping = flags & FLAG_JUMPING != 0;
+    p.can_wall_jump = flags & FLAG_CAN_WALL_JUMP != 0;
+    p.ability_available = flags & FLAG_ABILITY != 0;
+}
+
+fn encode_rope(buf: &mut Vec<u8>, rope: &RopeState) {
+    buf.push(rope.head);
+    buf.push(rope.tail);
+    put_f32(buf, rope.rest_length);
+    put_f32(buf, rope.max_extension);
+    put_f32(buf, rope.spring_constant);
+}
+
+fn encode_game_over(buf: &mut Vec<u8>, game_over: Option<f32>) {
+    match game_over {
+        Some(height) => {
+            buf.push(1);
+            put_f32(buf, height);
+        }
+        None => buf.push(0),
+    }
+}
+
+fn encode_snapshot(buf: &mut Vec<u8>, snapshot: &WorldSnapshot) {
+    buf.extend_from_slice(&snapshot.tick.to_be_bytes());
+
+    buf.push(snapshot.players.len() as u8);
+    for p in &snapshot.players {
+        buf.push(p.slot);
+        put_vec2(buf, p.position);
+        put_vec2(buf, p.velocity);
+        put_vec2(buf, p.momentum);
+        buf.push(pack_player_flags(p));
+
+        put_f32(buf, p.coyote_elapsed);
+        put_f32(buf, p.jump_time_elapsed);
+        put_f32(buf, p.wall_jump_elapsed);
+        buf.extend_from_slice(&p.last_input.to_be_bytes());
+    }
+
+    buf.push(snapshot.ropes.len() as u8);
+    for rope in &snapshot.ropes {
+        encode_rope(buf, rope);
+    }
+
+    buf.extend_from_slice(&(snapshot.platforms.len() as u16).to_be_bytes());
+    for platform in &snapshot.platforms {
+        buf.extend_from_slice(&platform.id.to_be_bytes());
+        put_vec2(buf, platform.position);
+        put_f32(buf, platform.t);
+        buf.push(platform.forward as u8);
+    }
+
+    buf.extend_from_slice(&snapshot.coins_collected.to_be_bytes());
+    buf.extend_from_slice(&(snapshot.despawned_coins.len() as u16).to_be_bytes());
+    for id in &snapshot.despawned_coins {
+        buf.extend_from_slice(&id.to_be_bytes());
+    }
+
+    encode_game_over(buf, snapshot.game_over);
+}
+
+fn encode_delta(buf: &mut Vec<u8>, delta: &SnapshotDelta) {
+    buf.extend_from_slice(&delta.tick.to_be_bytes());
+    buf.extend_from_slice(&delta.baseline.to_be_bytes());
+
+    buf.push(delta.players.len() as u8);
+    for p in &delta.players {
+        let mut mask = 0u8;
+        for (present, bit) in [
+            (p.position.is_some(), PLAYER_POSITION),
+            (p.velocity.is_some(), PLAYER_VELOCITY),
+            (p.momentum.is_some(), PLAYER_MOMENTUM),
+            (p.flags.is_some(), PLAYER_FLAGS),
+            (p.coyote_elapsed.is_some(), PLAYER_COYOTE),
+            (p.jump_time_elapsed.is_some(), PLAYER_JUMP_TIME),
+            (p.wall_jump_elapsed.is_some(), PLAYER_WALL_JUMP),
+            (p.last_input.is_some(), PLAYER_LAST_INPUT),
+        ] {
+            if present {
+                mask |= bit;
+            }
+        }
+        buf.push(p.slot);
+        buf.push(mask);
+
+        for v in [p.position, p.velocity, p.momentum].into_iter().flatten() {
+            put_ivec2(buf, v);
+        }
+        if let Some(flags) = p.flags {
+            buf.push(flags);
+        }
+        for v in [
+            p.coyote_elapsed,
+            p.jump_time_elapsed,
+            p.wall_jump_elapsed,
+            p.last_input,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            put_varint(buf, v);
+        }
+    }
+
+    match &delta.ropes {
+        Some(ropes) => {
+            buf.push(1);
+            buf.push(ropes.len() as u8);
+            for rope in ropes {
+                encode_rope(buf, rope);
+            }
+        }
+        None => buf.push(0),
+    }
+
+    buf.extend_from_slice(&(delta.platforms.len() as u16).to_be_bytes());
+    for platform in &delta.platforms {
+        let mut mask = 0u8;
+        if platform.position.is_some() {
+            mask |= PLATFORM_POSITION;
+        }
+        if platform.t.is_some() {
+            mask |= PLATFORM_T;
+        }
+        if let Some(forward) = platform.forward {
+            mask |= PLATFORM_FORWARD_CHANGED;
+            if forward {
+                mask |= PLATFORM_FORWARD;
+            }
+        }
+        buf.extend_from_slice(&platform.id.to_be_bytes());
+        buf.push(mask);
+        if let Some(v) = platform.position {
+            put_ivec2(buf, v);
+        }
+        if let Some(t) = platform.t {
+            put_varint(buf, t);
+        }
+    }
+
+    put_varint(buf, delta.coins_collected);
+    buf.extend_from_slice(&(delta.new_despawned_coins.len() as u16).to_be_bytes());
+    for id in &delta.new_despawned_coins {
+        buf.extend_from_slice(&id.to_be_bytes());
+    }
+
+    encode_game_over(buf, delta.game_over);
+}
+
+// -----------------------------------------------------------
+//                        DECODE
+// -----------------------------------------------------------
+pub fn decode(data: &[u8]) -> Result<Message, ProtocolError> {
+    if data.is_empty() {
+        return Err(ProtocolError::Empty);
+    }
+    if data[0] != PROTOCOL_VERSION {
+        return Err(ProtocolError::VersionMismatch {
+            expected: PROTOCOL_VERSION,
+            found: data[0],
+        });
+    }
+    if data.len() < HEADER_LEN {
+        return Err(ProtocolError::Truncated {
+            needed: HEADER_LEN - data.len(),
+            remaining: 0,
+        });
+    }
+
+    let kind = MessageKind::from_u8(data[1]).ok_or(ProtocolError::UnknownKind(data[1]))?;
+    let mut r = Reader::new(&data[HEADER_LEN..]);
+
+    let msg = match kind {
+        MessageKind::Hello => Message::Hello {
+            slot: r.u8()?,
+            token: r.u64()?,
+            room: match r.u8()? {
+                ROOM_ANY => RoomRequest::Any,
+                ROOM_CREATE => RoomRequest::Create,
+                ROOM_JOIN => RoomRequest::Join(r.u32()?),
+                // not "any": a room we can't read is not one to be put in.
+                other => return Err(ProtocolError::UnknownRoomRequest(other)),
+            },
+        },
+        MessageKind::Welcome => Message::Welcome {
+            slot: r.u8()?,
+            session: r.u32()?,
+            room: r.u32()?,
+            token: r.u64()?,
+        },
+        MessageKind::Reject => Message::Reject {
+            reason: RejectReason::from_u8(r.u8()?),
+        },
+        MessageKind::Input => {
+            let token = r.u64()?;
+            let seq = r.u32()?;
+            let count = r.u8()? as usize;
+            if count > MAX_INPUT_FRAMES {
+                return Err(ProtocolError::TooManyInputFrames(count));
+            }
+            let masks = (0..count).map(|_| r.u8()).collect::<Result<_, _>>()?;
+            Message::Input {
+                token,
+                seq,
+                masks,
+                ack: r.u32()?,
+            }
+        }
+        MessageKind::Snapshot => Message::Snapshot(decode_snapshot(&mut r)?),
+        MessageKind::DeltaSnapshot => Message::DeltaSnapshot(decode_delta(&mut r)?),
+        MessageKind::Disconnect => Message::Disconnect,
+        MessageKind::Ping => Message::Ping { id: r.u32()? },
+        MessageKind::Pong => Message::Pong {
+            id: r.u32()?,
+            tick: r.u32()?,
+        },
+        MessageKind::PartnerLeft => Message::PartnerLeft { slot: r.u8()? },
+        MessageKind::Reliable => Message::Reliable {
+            seq: r.u32()?,
+            event: decode_event(&mut r)?,
+        },
+        MessageKind::ReliableAck => Message::ReliableAck { next: r.u32()? },
+    };
+
+    r.finish()?;
+    Ok(msg)
+}
+
+fn decode_event(r: &mut Reader) -> Result<ReliableEvent, ProtocolError> {
+    let kind = r.u8()?;
+    Ok(match kind {
+        EVENT_COIN_COLLECTED => ReliableEvent::CoinCollected {
+            id: r.u16()?,
+            total: r.u32()?,
+        },
+        EVENT_GAME_OVER => ReliableEvent::GameOver { height: r.f32()? },
+        EVENT_PHASE_CHANGED => {
+            let phase = r.u8()?;
+            ReliableEvent::PhaseChanged(
+                MatchPhase::from_u8(phase).ok_or(ProtocolError::UnknownEvent(phase))?,
+            )
+        }
+        EVENT_RESTART_REQUESTED => ReliableEvent::RestartRequested,
+        other => return Err(ProtocolError::UnknownEvent(other)),
+    })
+}
+
+fn decode_snapshot(r: &mut Reader) -> Result<WorldSnapshot, ProtocolError> {
+    let tick = r.u32()?;
+
+    // every list checks its whole body up front so a bogus count can't make us allocate.
+    let count = r.u8()? as usize;
+    r.require(count * PLAYER_STATE_LEN)?;
+    let mut players = Vec::with_capacity(count);
+    for _ in 0..count {
+        let slot = r.u8()?;
+        let position = r.vec2()?;
+        let velocity = r.vec2()?;
+        let momentum = r.vec2()?;
+        let flags = r.u8()?;
+        let mut state = PlayerState {
+            slot,
+            position,
+            velocity,
+            momentum,
+            coyote_elapsed: r.f32()?,
+            jump_time_elapsed: r.f32()?,
+            wall_jump_elapsed: r.f32()?,
+            last_input: r.u32()?,
+            ..Default::default()
+        };
+        unpack_player_flags(&mut state, flags);
+        players.push(state);
+    }
+
+    let ropes = decode_ropes(r)?;
+
+    let count = r.u16()? as usize;
+    r.require(count * PLATFORM_STATE_LEN)?;
+    let mut platforms = Vec::with_capacity(count);
+    for _ in 0..count {
+        platforms.push(PlatformState {
+            id: r.u16()?,
+            position: r.vec2()?,
+            t: r.f32()?,
+            forward: r.u8()? != 0,
+        });
+    }
+
+    let coins_collected = r.u32()?;
+    let count = r.u16()? as usize;
+    r.require(count * 2)?;
+    let mut despawned_coins = Vec::with_capacity(count);
+    for _ in 0..count {
+        despawned_coins.push(r.u16()?);
+    }
+
+    let game_over = decode_game_over(r)?;
+
+    Ok(WorldSnapshot {
+        tick,
+        players,
+        ropes,
+        platforms,
+        coins_collected,
+        despawned_coins,
+        game_over,
+    })
+}
+
+fn decode_ropes(r: &mut Reader) -> Result<Vec<RopeState>, ProtocolError> {
+    let count = r.u8()? as usize;
+    r.require(count * ROPE_STATE_LEN)?;
+    let mut ropes = Vec::with_capacity(count);
+    for _ in 0..count {
+        ropes.push(RopeState {
+            head: r.u8()?,
+            tail: r.u8()?,
+            rest_length: r.f32()?,
+            max_extension: r.f32()?,
+            spring_constant: r.f32()?,
+        });
+    }
+    Ok(ropes)
+}
+
+fn decode_game_over(r: &mut Reader) -> Result<Option<f32>, ProtocolError> {
+    Ok(match r.u8()? {
+        0 => None,
+        _ => Some(r.f32()?),
+    })
+}
+
+fn decode_delta(r: &mut Reader) -> Result<SnapshotDelta, ProtocolError> {
+    let tick = r.u32()?;
+    let baseline = r.u32()?;
+
+    let count = r.u8()? as usize;
+    r.require(count * PLAYER_DELTA_MIN_LEN)?;
+    let mut players = Vec::with_capacity(count);
+    for _ in 0..count {
+        let slot = r.u8()?;
+        let mask = r.u8()?;
+        let has = |bit: u8| mask & bit != 0;
+        players.push(PlayerDelta {
+            slot,
+            position: r.opt_ivec2(has(PLAYER_POSITION))?,
+            velocity: r.opt_ivec2(has(PLAYER_VELOCITY))?,
+            momentum: r.opt_ivec2(has(PLAYER_MOMENTUM))?,
+            flags: if has(PLAYER_FLAGS) {
+                Some(r.u8()?)
+            } else {
+                None
+            },
+            coyote_elapsed: r.opt_varint(has(PLAYER_COYOTE))?,
+            jump_time_elapsed: r.opt_varint(has(PLAYER_JUMP_TIME))?,
+            wall_jump_elapsed: r.opt_varint(has(PLAYER_WALL_JUMP))?,
+            last_input: r.opt_varint(has(PLAYER_LAST_INPUT))?,
+        });
+    }
+
+    let ropes = match r.u8()? {
+        0 => None,
+        _ => Some(decode_ropes(r)?),
+    };
+
+    let count = r.u16()? as usize;
+    r.require(count * PLATFORM_DELTA_MIN_LEN)?;
+    let mut platforms = Vec::with_capacity(count);
+    for _ in 0..count {
+        let id = r.u16()?;
+        let mask = r.u8()?;
+        platforms.push(PlatformDelta {
+            id,
+            position: r.opt_ivec2(mask & PLATFORM_POSITION != 0)?,
+            t: r.opt_varint(mask & PLATFORM_T != 0)?,
+            forward: (mask & PLATFORM_FORWARD_CHANGED != 0).then_some(mask & PLATFORM_FORWARD != 0),
+        });
+    }
+
+    let coins_collected = r.varint()?;
+    let count = r.u16()? as usize;
+    r.require(count * 2)?;
+    let mut new_despawned_coins = Vec::with_capacity(count);
+    for _ in 0..count {
+        new_despawned_coins.push(r.u16()?);
+    }
+
+    let game_over = decode_game_over(r)?;
+
+    Ok(SnapshotDelta {
+        tick,
+        baseline,
+        players,
+        ropes,
+        platforms,
+        coins_collected,
+        new_despawned_coins,
+        game_over,
+    })
+}
+
+// bounds-checked cursor over a packet body.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn require(&self, n: usize) -> Result<(), ProtocolError> {
+        if self.remaining() < n {
+            Err(ProtocolError::Truncated {
+                needed: n,
+                remaining: self.remaining(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        self.require(N)?;
+        let mut out = [0u8; N];
+        out.copy_from_slice(&self.data[self.pos..self.pos + N]);
+        self.pos += N;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_be_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_be_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, ProtocolError> {
+        Ok(u64::from_be_bytes(self.bytes()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, ProtocolError> {
+        Ok(f32::from_be_bytes(self.bytes()?))
+    }
+
+    fn vec2(&mut self) -> Result<Vec2, ProtocolError> {
+        Ok(Vec2::new(self.f32()?, self.f32()?))
+    }
+
+    fn varint(&mut self) -> Result<i32, ProtocolError> {
+        let mut z = 0u32;
+        // an i32 never needs more than 5 groups of 7 bits.
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            z |= ((byte & 0x7F) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(((z >> 1) as i32) ^ -((z & 1) as i32));
+            }
+        }
+        Err(ProtocolError::MalformedVarint)
+    }
+
+    fn opt_varint(&mut self, present: bool) -> Result<Option<i32>, ProtocolError> {
+        if present {
+            self.varint().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn opt_ivec2(&mut self, present: bool) -> Result<Option<IVec2>, ProtocolError> {
+        if present {
+            Ok(Some(IVec2::new(self.varint()?, self.varint()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn finish(&self) -> Result<(), ProtocolError> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(ProtocolError::TrailingBytes(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_snapshot() -> WorldSnapshot {
+        WorldSnapshot {
+            tick: 42,
+            players: vec![
+                PlayerState {
+                    slot: 0,
+                    position: Vec2::new(50.0, 16.0),
+                    velocity: Vec2::new(-3.5, 120.25),
+                    momentum: Vec2::new(-420.0, 14430.0),
+                    grounded: true,
+                    coyote_elapsed: 0.0,
+                    is_jumping: false,
+                    jump_time_elapsed: 0.0,
+                    can_wall_jump: true,
+                    wall_jump_elapsed: 0.2,
+                    ability_available: true,
+                    last_input: 7001,
+                },
+                PlayerState {
+                    slot: 1,
+                    position: Vec2::new(350.5, -12.25),
+                    is_jumping: true,
+                    jump_time_elapsed: 0.1,
+                    coyote_elapsed: 0.05,
+                    ..Default::default()
+                },
+            ],
+            ropes: vec![RopeState {
+                head: 0,
+                tail: 1,
+                rest_length: 300.0,
+                max_extension: 300.0,
+                spring_constant: 80000.0,
+            }],
+            platforms: vec![PlatformState {
+                id: 17,
+                position: Vec2::new(640.0, 1200.0),
+                t: 0.37,
+                forward: false,
+            }],
+            coins_collected: 3,
+            despawned_coins: vec![2, 9, 40],
+            game_over: Some(2048.0),
+        }
+    }
+
+    fn sample_delta() -> SnapshotDelta {
+        SnapshotDelta {
+            tick: 43,
+            baseline: 40,
+            players: vec![PlayerDelta {
+                slot: 1,
+                position: Some(IVec2::new(-3, 250)),
+                flags: Some(FLAG_GROUNDED | FLAG_ABILITY),
+                wall_jump_elapsed: Some(i32::MIN),
+                last_input: Some(1),
+                ..Default::default()
+            }],
+            ropes: Some(sample_snapshot().ropes),
+            platforms: vec![PlatformDelta {
+                id: 17,
+                t: Some(i32::MAX),
+                forward: Some(true),
+                ..Default::default()
+            }],
+            coins_collected: -1,
+            new_despawned_coins: vec![5],
+            game_over: None,
+        }
+    }
+
+    fn round_trip(msg: Message) {
+        let bytes = encode(&msg);
+        assert_eq!(decode(&bytes), Ok(msg));
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        round_trip(Message::Hello {
+            slot: 1,
+            token: 0,
+            room: RoomRequest::Any,
+        });
+        round_trip(Message::Hello {
+            slot: 0,
+            token: 9,
+            room: RoomRequest::Create,
+        });
+        round_trip(Message::Hello {
+            slot: 1,
+            token: u64::MAX,
+            room: RoomRequest::Join(0xAABB_CCDD),
+        });
+        round_trip(Message::Welcome {
+            slot: 0,
+            session: 0x0102_0304,
+            room: 7,
+            token: 0x0506_0708_090A_0B0C,
+        });
+        round_trip(Message::Reject {
+            reason: RejectReason::InvalidSlot,
+        });
+        round_trip(Message::Input {
+            token: 0x0102_0304_0506_0708,
+            seq: 0xDEAD_BEEF,
+            masks: vec![INPUT_JUMP | INPUT_RIGHT, INPUT_JUMP, 0],
+            ack: 41,
+        });
+        round_trip(Message::Input {
+            token: 0,
+            seq: 1,
+            masks: Vec::new(),
+            ack: 0,
+        });
+        round_trip(Message::Snapshot(sample_snapshot()));
+        round_trip(Message::DeltaSnapshot(sample_delta()));
+        round_trip(Message::DeltaSnapshot(SnapshotDelta::default()));
+        round_trip(Message::Snapshot(WorldSnapshot::default()));
+        round_trip(Message::Disconnect);
+        round_trip(Message::Ping { id: 5 });
+        round_trip(Message::Pong {
+            id: 0xFFFF_0000,
+            tick: 123_456,
+        });
+        round_trip(Message::PartnerLeft { slot: 1 });
+        for event in [
+            ReliableEvent::CoinCollected { id: 12, total: 3 },
+            ReliableEvent::GameOver { height: 1234.5 },
+            ReliableEvent::PhaseChanged(MatchPhase::Waiting),
+            ReliableEvent::PhaseChanged(MatchPhase::Countdown),
+            ReliableEvent::PhaseChanged(MatchPhase::Playing),
+            ReliableEvent::PhaseChanged(MatchPhase::Finished),
+            ReliableEvent::PhaseChanged(MatchPhase::Restarting),
+            ReliableEvent::RestartRequested,
+        ] {
+            round_trip(Message::Reliable { seq: 77, event });
+        }
+        round_trip(Message::ReliableAck { next: 78 });
+    }
+
+    #[test]
+    fn empty_and_unknown_packets_are_rejected() {
+        assert_eq!(decode(&[]), Err(ProtocolError::Empty));
+        assert_eq!(
+            decode(&[PROTOCOL_VERSION, 200]),
+            Err(ProtocolError::UnknownKind(200))
+        );
+        // the old ad-hoc handshake strings must not be mistaken for anything.
+        assert!(decode(b"MAIN").is_err());
+        assert!(decode(b"ACK").is_err());
+
+        // nor a room request from a newer (or garbled) client.
+        let mut hello = encode(&Message::Hello {
+            slot: 0,
+            token: 0,
+            room: RoomRequest::Create,
+        });
+        *hello.last_mut().unwrap() = 9;
+        assert_eq!(decode(&hello), Err(ProtocolError::UnknownRoomRequest(9)));
+    }
+
+    #[test]
+    fn version_mismatch_is_reported() {
+        let mut bytes = encode(&Message::Hello {
+            slot: 0,
+            token: 0,
+            room: RoomRequest::Any,
+        });
+        bytes[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            decode(&bytes),
+            Err(ProtocolError::VersionMismatch {
+                expected: PROTOCOL_VERSION,
+                found: PROTOCOL_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_snapshot_does_not_panic() {
+        let bytes = encode(&Message::Snapshot(sample_snapshot()));
+
+        for len in 1..bytes.len() {
+            assert!(
+                matches!(decode(&bytes[..len]), Err(ProtocolError::Truncated { .. })),
+                "prefix of length {} should be truncated",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_delta_does_not_panic() {
+        let bytes = encode(&Message::DeltaSnapshot(sample_delta()));
+
+        for len in 1..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn small_differences_encode_in_one_byte() {
+        for v in [0, 1, -1, 63, -64] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, v);
+            assert_eq!(buf.len(), 1, "{} should fit in one byte", v);
+        }
+    }
+
+    #[test]
+    fn overlong_varint_is_rejected() {
+        let mut r = Reader::new(&[0xFF; 6]);
+        assert_eq!(r.varint(), Err(ProtocolError::MalformedVarint));
+    }
+
+    #[test]
+    fn player_count_larger_than_body_is_rejected() {
+        let mut bytes = vec![PROTOCOL_VERSION, MessageKind::Snapshot as u8];
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.push(u8::MAX);
+        assert!(matches!(
+            decode(&bytes),
+            Err(ProtocolError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn oversized_input_packets_are_rejected() {
+        let mut bytes = vec![PROTOCOL_VERSION, MessageKind::Input as u8];
+        bytes.extend_from_slice(&7u64.to_be_bytes());
+        bytes.extend_from_slice(&40u32.to_be_bytes());
+        bytes.push(MAX_INPUT_FRAMES as u8 + 1);
+        bytes.extend(std::iter::repeat_n(0, MAX_INPUT_FRAMES + 5));
+        assert_eq!(
+            decode(&bytes),
+            Err(ProtocolError::TooManyInputFrames(MAX_INPUT_FRAMES + 1))
+        );
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut bytes = encode(&Message::Input {
+            token: 3,
+            seq: 1,
+            masks: vec![0],
+            ack: 0,
+        });
+        bytes.push(0);
+        assert_eq!(decode(&bytes), Err(ProtocolError::TrailingBytes(1)));
+    }
+}
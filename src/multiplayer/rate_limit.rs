@@ -0,0 +1,119 @@
+// Handshake rate limiting.
+//
+// Hello is the one message a stranger can make the server do work for (and
+// answer), so the receive task checks every one here before queueing it. each
+// ip gets a small burst that refills slowly. it is keyed on the ip alone since
+// a fresh port costs nothing. an honest client's backed off retries stay well
+// inside it, even a few of them behind one NAT.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+pub const HANDSHAKE_BURST: u32 = 8;
+// one more attempt is allowed per this much time.
+pub const HANDSHAKE_REFILL: Duration = Duration::from_millis(250);
+// past this many addresses, the ones back to a full burst are forgotten.
+const MAX_TRACKED: usize = 1024;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: u32,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let steps = (now.saturating_duration_since(self.refilled).as_millis()
+            / HANDSHAKE_REFILL.as_millis()) as u32;
+        if steps == 0 {
+            return;
+        }
+        self.tokens = self.tokens.saturating_add(steps).min(HANDSHAKE_BURST);
+        self.refilled = if self.tokens == HANDSHAKE_BURST {
+            now
+        } else {
+            self.refilled + HANDSHAKE_REFILL * steps
+        };
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HandshakeLimiter {
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl HandshakeLimiter {
+    // whether a Hello from `ip` should be handled; counts it if so.
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= MAX_TRACKED && !self.buckets.contains_key(&ip) {
+            self.buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < HANDSHAKE_BURST
+            });
+        }
+
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: HANDSHAKE_BURST,
+            refilled: now,
+        });
+        bucket.refill(now);
+        if bucket.tokens == 0 {
+            return false;
+        }
+        bucket.tokens -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn a_burst_is_allowed_then_refused_until_it_refills() {
+        let mut limiter = HandshakeLimiter::default();
+        let start = Instant::now();
+        for _ in 0..HANDSHAKE_BURST {
+            assert!(limiter.allow(ip(1), start));
+        }
+        assert!(!limiter.allow(ip(1), start));
+        assert!(!limiter.allow(ip(1), start + HANDSHAKE_REFILL / 2));
+
+        assert!(limiter.allow(ip(1), start + HANDSHAKE_REFILL));
+        assert!(!limiter.allow(ip(1), start + HANDSHAKE_REFILL));
+
+        // other addresses have budgets of their own.
+        assert!(limiter.allow(ip(2), start));
+    }
+
+    #[test]
+    fn a_quiet_address_gets_its_whole_burst_back() {
+        let mut limiter = HandshakeLimiter::default();
+        let start = Instant::now();
+        for _ in 0..HANDSHAKE_BURST {
+            limiter.allow(ip(1), start);
+        }
+
+        let later = start + HANDSHAKE_REFILL * (HANDSHAKE_BURST * 10);
+        let allowed = (0..HANDSHAKE_BURST * 2)
+            .filter(|_| limiter.allow(ip(1), later))
+            .count();
+        assert_eq!(allowed, HANDSHAKE_BURST as usize);
+    }
+
+    #[test]
+    fn backed_off_client_retries_are_never_refused() {
+        let mut limiter = HandshakeLimiter::default();
+        let mut now = Instant::now();
+        // the client's reconnect schedule, starting from 250ms and doubling.
+        for attempt in 0..10 {
+            assert!(limiter.allow(ip(1), now));
+            now += Duration::from_millis(250) * (1 << attempt.min(5));
+        }
+    }
+}
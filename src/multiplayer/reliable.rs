@@ -0,0 +1,224 @@
+// Reliable, ordered channel for events that must not be lost.
+//
+// each side numbers what it sends from 0. a message is resent every
+// RESEND_INTERVAL until the other side acks it. acks are cumulative: "I have
+// everything below `next`". the receiver holds anything that arrives early and
+// hands events out strictly in order, each exactly once.
+//
+// sequence numbers restart with every handshake; restart() keeps whatever was
+// still unacked so nothing queued before a reconnect is dropped.
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::protocol::ReliableEvent;
+
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(200);
+
+// far enough ahead of what we expect that it can't be from this session.
+const MAX_RECEIVE_WINDOW: u32 = 1024;
+
+#[derive(Debug)]
+struct Pending {
+    seq: u32,
+    event: ReliableEvent,
+    last_sent: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct ReliableChannel {
+    next_send: u32,
+    unacked: VecDeque<Pending>,
+    next_recv: u32,
+    early: BTreeMap<u32, ReliableEvent>,
+}
+
+impl ReliableChannel {
+    pub fn send(&mut self, event: ReliableEvent) {
+        self.unacked.push_back(Pending {
+            seq: self.next_send,
+            event,
+            last_sent: None,
+        });
+        self.next_send += 1;
+    }
+
+    // everything never sent or not acked for RESEND_INTERVAL. marks them sent.
+    pub fn due(&mut self, now: Instant) -> Vec<(u32, ReliableEvent)> {
+        self.unacked
+            .iter_mut()
+            .filter(|p| {
+                p.last_sent
+                    .is_none_or(|t| now.duration_since(t) >= RESEND_INTERVAL)
+            })
+            .map(|p| {
+                p.last_sent = Some(now);
+                (p.seq, p.event)
+            })
+            .collect()
+    }
+
+    // the other side has everything below `next`.
+    pub fn on_ack(&mut self, next: u32) {
+        while self.unacked.front().is_some_and(|p| p.seq < next) {
+            self.unacked.pop_front();
+        }
+    }
+
+    // events that are now deliverable, in order. duplicates and messages from
+    // too far ahead are dropped; either way the caller should ack with `ack()`.
+    pub fn on_receive(&mut self, seq: u32, event: ReliableEvent) -> Vec<ReliableEvent> {
+        let mut ready = Vec::new();
+        self.on_receive_with(seq, event, |event| {
+            ready.push(event);
+            true
+        });
+        ready
+    }
+
+    // on_receive, handing deliverable events to `deliver` one by one. the first
+    // one it refuses (a full queue) and everything after it stay unacked, so
+    // the sender resends them and they are offered again then.
+    pub fn on_receive_with(
+        &mut self,
+        seq: u32,
+        event: ReliableEvent,
+        mut deliver: impl FnMut(ReliableEvent) -> bool,
+    ) {
+        if seq < self.next_recv || seq - self.next_recv >= MAX_RECEIVE_WINDOW {
+            return;
+        }
+        self.early.insert(seq, event);
+
+        while let Some(&event) = self.early.get(&self.next_recv) {
+            if !deliver(event) {
+                return;
+            }
+            self.early.remove(&self.next_recv);
+            self.next_recv += 1;
+        }
+    }
+
+    pub fn ack(&self) -> u32 {
+        self.next_recv
+    }
+
+    pub fn pending(&self) -> usize {
+        self.unacked.len()
+    }
+
+    // new session: both sides count from 0 again. unacked events go out again
+    // under new numbers.
+    pub fn restart(&mut self) {
+        let unacked = std::mem::take(&mut self.unacked);
+        *self = Self::default();
+        for pending in unacked {
+            self.send(pending.event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(id: u16) -> ReliableEvent {
+        ReliableEvent::CoinCollected {
+            id,
+            total: id as u32,
+        }
+    }
+
+    #[test]
+    fn out_of_order_and_duplicates_are_delivered_once_in_order() {
+        let mut rx = ReliableChannel::default();
+        assert!(rx.on_receive(1, coin(1)).is_empty());
+        assert!(rx.on_receive(2, coin(2)).is_empty());
+        assert_eq!(rx.ack(), 0);
+
+        assert_eq!(rx.on_receive(0, coin(0)), vec![coin(0), coin(1), coin(2)]);
+        assert_eq!(rx.ack(), 3);
+
+        assert!(rx.on_receive(1, coin(1)).is_empty());
+        assert!(rx.on_receive(0, coin(0)).is_empty());
+        assert_eq!(rx.on_receive(3, coin(3)), vec![coin(3)]);
+    }
+
+    #[test]
+    fn refused_events_stay_unacked_until_a_resend_gets_them_through() {
+        let mut rx = ReliableChannel::default();
+        let mut delivered = Vec::new();
+        rx.on_receive_with(0, coin(0), |event| {
+            delivered.push(event);
+            true
+        });
+        // the queue is full.
+        rx.on_receive_with(1, coin(1), |_| false);
+        rx.on_receive_with(2, coin(2), |_| false);
+        assert_eq!(rx.ack(), 1);
+
+        // the sender only resends what wasn't acked.
+        rx.on_receive_with(1, coin(1), |event| {
+            delivered.push(event);
+            true
+        });
+        assert_eq!(rx.ack(), 3);
+        assert_eq!(delivered, vec![coin(0), coin(1), coin(2)]);
+    }
+
+    #[test]
+    fn unacked_messages_are_resent_after_the_interval() {
+        let mut tx = ReliableChannel::default();
+        tx.send(coin(0));
+        tx.send(coin(1));
+
+        let start = Instant::now();
+        assert_eq!(tx.due(start), vec![(0, coin(0)), (1, coin(1))]);
+        assert!(tx.due(start + RESEND_INTERVAL / 2).is_empty());
+
+        tx.on_ack(1);
+        assert_eq!(tx.due(start + RESEND_INTERVAL), vec![(1, coin(1))]);
+
+        tx.on_ack(2);
+        assert_eq!(tx.pending(), 0);
+        assert!(tx.due(start + RESEND_INTERVAL * 4).is_empty());
+    }
+
+    #[test]
+    fn lossy_link_still_delivers_everything_in_order() {
+        let mut tx = ReliableChannel::default();
+        let mut rx = ReliableChannel::default();
+        for id in 0..20 {
+            tx.send(coin(id));
+        }
+
+        let mut delivered = Vec::new();
+        let mut now = Instant::now();
+        for round in 0..50 {
+            // drop every other packet, and every ack on even rounds.
+            for (i, (seq, event)) in tx.due(now).into_iter().enumerate() {
+                if (i + round) % 2 == 0 {
+                    delivered.extend(rx.on_receive(seq, event));
+                }
+            }
+            if round % 2 == 1 {
+                tx.on_ack(rx.ack());
+            }
+            now += RESEND_INTERVAL;
+        }
+
+        assert_eq!(delivered, (0..20).map(coin).collect::<Vec<_>>());
+        assert_eq!(tx.pending(), 0);
+    }
+
+    #[test]
+    fn restart_renumbers_unacked_messages() {
+        let mut tx = ReliableChannel::default();
+        tx.send(coin(0));
+        tx.send(coin(1));
+        tx.send(coin(2));
+        tx.on_ack(1);
+
+        tx.restart();
+        assert_eq!(tx.due(Instant::now()), vec![(0, coin(1)), (1, coin(2))]);
+    }
+}
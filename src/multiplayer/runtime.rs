@@ -0,0 +1,131 @@
+// Network runtime.
+//
+// every network task runs on one tokio runtime per app, owned by the NetRuntime
+// resource: the server's receive and broadcast loops, a client's receiver, and
+// packets the link conditioner holds back. a NetTask stops its task when it is
+// dropped, so tearing down a connection is removing the resource holding it.
+//
+// the runtime shuts down on AppExit (or when the app is dropped): tasks are
+// cancelled at their next await and the worker threads get SHUTDOWN_GRACE to
+// wind down. the plugins say goodbye to their peers first, see
+// server::disconnect_clients_system and client::client_disconnect.
+use bevy::prelude::*;
+use std::future::Future;
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::task::JoinHandle;
+
+// one socket per app, there isn't much to spread around.
+const WORKER_THREADS: usize = 2;
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Resource)]
+pub struct NetRuntime {
+    // None once shut down.
+    runtime: Option<Runtime>,
+    handle: Handle,
+}
+
+impl Default for NetRuntime {
+    fn default() -> Self {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(WORKER_THREADS)
+            .thread_name("net")
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("Failed to start the network runtime");
+        Self {
+            handle: runtime.handle().clone(),
+            runtime: Some(runtime),
+        }
+    }
+}
+
+impl NetRuntime {
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) -> NetTask {
+        NetTask(Some(self.handle.spawn(task)))
+    }
+
+    // later spawns are dropped without running.
+    pub fn shutdown(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_timeout(SHUTDOWN_GRACE);
+            println!("[Net] Network runtime shut down");
+        }
+    }
+}
+
+impl Drop for NetRuntime {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// a running network task, cancelled when dropped.
+pub struct NetTask(Option<JoinHandle<()>>);
+
+impl NetTask {
+    // let it run until the runtime shuts down.
+    pub fn detach(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for NetTask {
+    fn drop(&mut self) {
+        if let Some(task) = &self.0 {
+            task.abort();
+        }
+    }
+}
+
+pub fn shutdown_runtime_system(mut runtime: ResMut<NetRuntime>) {
+    runtime.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // set when the task's future is dropped, finished or not.
+    struct Finished(Arc<AtomicBool>);
+
+    impl Drop for Finished {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn forever(finished: &Arc<AtomicBool>) -> impl Future<Output = ()> + Send + 'static {
+        let guard = Finished(finished.clone());
+        async move {
+            let _guard = guard;
+            std::future::pending::<()>().await;
+        }
+    }
+
+    #[test]
+    fn dropped_tasks_stop_and_shutdown_stops_detached_ones() {
+        let mut runtime = NetRuntime::default();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let detached = Arc::new(AtomicBool::new(false));
+
+        drop(runtime.spawn(forever(&dropped)));
+        runtime.spawn(forever(&detached)).detach();
+        runtime
+            .handle()
+            .block_on(async { tokio::time::sleep(Duration::from_millis(20)).await });
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(!detached.load(Ordering::SeqCst));
+
+        runtime.shutdown();
+        assert!(detached.load(Ordering::SeqCst));
+    }
+}
@@ -1,28 +1,80 @@
+use super::ai_partner::{AiPartner, AiPolicy, drive_ai_partners_system};
+use super::conditioner::{ConditionedSocket, LinkConditions};
+use super::delta::{self, SNAPSHOT_HISTORY};
+use super::input_buffer::InputBuffer;
+use super::lifecycle::{MatchState, PLAYERS_PER_ROOM, RoomOccupancy, match_lifecycle_system};
+use super::protocol::{
+    self, MatchPhase, Message, PlayerState, ProtocolError, RejectReason, ReliableEvent,
+    RoomRequest, SPECTATOR_SLOT, WorldSnapshot,
+};
+use super::rate_limit::HandshakeLimiter;
+use super::reliable::ReliableChannel;
+use super::runtime::NetRuntime;
+use super::snapshot::{capture_platform, capture_player, capture_rope};
+use super::stats::{SERVER_STATS_INTERVAL, Traffic};
+use super::transport::NetTransport;
+use crate::components::motion::{GroundState, JumpController, Momentum, Velocity};
+use crate::components::rope::Rope;
+use crate::config::MyAppState;
+use crate::game_ui::ui::TotalCoin;
+use crate::map::{Coin, EasedPlatform, MapEntityId};
+use crate::physics::MaxHeightReached;
 use crate::player::{Player, player_control::PlayerInputEvent};
-use async_channel::{Receiver, Sender};
-use async_io::Timer;
+use async_channel::{Receiver, Sender, TrySendError};
 use bevy::prelude::*;
-use bevy::tasks::{IoTaskPool, TaskPool, TaskPoolBuilder};
 use std::{
-    collections::HashMap,
-    net::{SocketAddr, UdpSocket},
-    sync::{Arc, RwLock},
-    thread,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU32, Ordering},
+    },
     time::{Duration, Instant},
 };
 
+// a client we haven't heard from (inputs or pings) for this long is dropped
+// and its slot freed.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// packets that can wait between the network tasks and the ECS. when one is
+// full, handshakes are dropped (the client retries), the oldest inputs and
+// snapshots make way for new ones, and reliable events go unacked until the
+// client resends them.
+const HANDSHAKE_QUEUE: usize = 64;
+const SNAPSHOT_QUEUE: usize = 64;
+const INPUT_QUEUE: usize = 256;
+const RELIABLE_QUEUE: usize = 64;
+
+// handed out in Welcome, a client reconnecting with it gets its old slot back.
+static NEXT_SESSION_ID: AtomicU32 = AtomicU32::new(1);
+
+pub type RoomId = u32;
+
 // A snapshot message built on the ECS thread and sent to network task
+// encoding happens on the network side since every client gets a delta against
+// its own baseline.
 #[derive(Debug)]
 pub struct SnapshotMsg {
-    pub data: Vec<u8>,
+    pub room: RoomId,
+    pub tick: u32,
+    pub snapshot: WorldSnapshot,
 }
 
 // data associated with a socket mapping.
 #[derive(Debug)]
 pub struct ClientSession {
     pub last_seen: Instant,
-    pub player: Entity,
-    pub prev_mask: u8,
+    pub room: RoomId,
+    // a player slot, or SPECTATOR_SLOT.
+    pub slot: u8,
+    pub session: u32,
+    // the secret from Welcome. inputs without it are dropped, and a Hello from
+    // another address needs it to take the session over.
+    pub token: u64,
+    // newest snapshot tick the client told us it has, baseline for deltas.
+    pub acked_tick: Option<u32>,
+    // coin pickups, game over, phase changes and restart requests.
+    pub reliable: ReliableChannel,
 }
 
 // we might not need a lock here, we build the client registry relatively Synchronously
@@ -30,208 +82,720 @@ pub struct ClientSession {
 #[derive(Resource, Default, Clone)]
 pub struct ClientRegistry {
     pub clients: Arc<RwLock<HashMap<SocketAddr, ClientSession>>>,
+    // inbox of every open room, the receive task routes on ClientSession::room.
+    pub rooms: Arc<RwLock<HashMap<RoomId, RoomInbox>>>,
+}
+
+// what the receive task can hand to a room's ECS world.
+pub struct RoomInbox {
+    pub inputs: Sender<RemoteInputEvent>,
+    pub reliable: Sender<RemoteReliableEvent>,
+    // the tick the room last simulated, sent back with every Pong. it keeps
+    // counting on the leaderboard, when there are no snapshots.
+    pub tick: Arc<AtomicU32>,
 }
 
 #[derive(Resource)]
 pub struct UdpServerSocket {
-    pub socket: UdpSocket,
+    pub socket: ConditionedSocket,
 }
 
 #[derive(Debug)]
 // not actually an event very bad name oops.
+// the frames of one Input packet, newest first (see Message::Input).
 pub struct RemoteInputEvent {
-    pub player: Entity,
-    pub left: bool,
-    pub right: bool,
-    pub jump_pressed: bool,
-    pub jump_just_released: bool,
+    pub slot: u8,
+    pub seq: u32,
+    pub masks: Vec<u8>,
+}
+
+// a Hello from the receive task. rooms can only be built on the main thread
+// (App isn't Send), so handshakes are finished by handle_handshakes_system.
+#[derive(Debug)]
+pub struct HandshakeRequest {
+    pub addr: SocketAddr,
+    pub slot: u8,
+    pub token: u64,
+    pub room: RoomRequest,
+}
+
+// a reliable event a client sent, delivered in order.
+#[derive(Debug)]
+pub struct RemoteReliableEvent {
+    pub slot: u8,
+    pub event: ReliableEvent,
+}
+
+// a reliable event a room wants every client in it to get.
+#[derive(Debug)]
+pub struct RoomEvent {
+    pub room: RoomId,
+    pub event: ReliableEvent,
+}
+
+// lives in the server app.
+// rx_handshakes | receive task -> room management
+// tx_snapshots and tx_events are cloned into every room's NetChannels.
+#[derive(Resource)]
+pub struct LobbyChannels {
+    pub rx_handshakes: Receiver<HandshakeRequest>,
+    pub tx_snapshots: Sender<SnapshotMsg>,
+    pub tx_events: Sender<RoomEvent>,
+    pub rx_events: Receiver<RoomEvent>,
 }
 
-// tx_snapshots for getting state out of the simulation -> UDP thread
-// rx_inputs for sending input events | UDP thread -> main game loop (inputs)
+// lives in a room's app.
+// tx_snapshots for getting state out of the simulation -> broadcast task
+// tx_events for reliable events -> lobby, which queues them per client
+// rx_inputs for sending input events | receive task -> main game loop (inputs)
+// rx_reliable for reliable events from clients | receive task -> main game loop
+// tick is shared with the room's RoomInbox | main game loop -> receive task
 #[derive(Resource)]
 pub struct NetChannels {
+    pub room: RoomId,
     pub tx_snapshots: Sender<SnapshotMsg>,
+    pub tx_events: Sender<RoomEvent>,
     pub rx_inputs: Receiver<RemoteInputEvent>,
+    pub rx_reliable: Receiver<RemoteReliableEvent>,
+    pub tick: Arc<AtomicU32>,
 }
 
-fn custom_network_pool() -> TaskPool {
-    let threads = std::thread::available_parallelism()
-        .map(|n| n.get())
-        .unwrap_or(2)
-        .max(4);
+// every player's jitter buffer, by slot. a resource rather than a Local so the
+// snapshot can tell each client which of its frames have been applied.
+#[derive(Resource, Default)]
+pub struct RoomInputs(pub [InputBuffer; PLAYERS_PER_ROOM]);
 
-    let pool = TaskPoolBuilder::default()
-        .num_threads(threads)
-        .thread_name("udp-network".into())
-        .build();
+// one match. a whole App so players, rope, map entities, coins and game state
+// can't leak between rooms; the server app steps it like a sub app.
+pub struct Room {
+    pub app: App,
+}
 
-    println!(
-        "[Init] Custom network pool with {} threads",
-        pool.thread_num()
-    );
-    pool
+// every open room, stored as a non-send resource on the server app.
+pub struct RoomHost {
+    pub rooms: BTreeMap<RoomId, Room>,
+    next_id: RoomId,
+    new_room: fn() -> App,
+    // drives empty slots in every room when set.
+    ai_partner: Option<AiPolicy>,
+}
+
+impl RoomHost {
+    pub fn new(new_room: fn() -> App, ai_partner: Option<AiPolicy>) -> Self {
+        Self {
+            rooms: BTreeMap::new(),
+            next_id: 1,
+            new_room,
+            ai_partner,
+        }
+    }
+
+    fn open_room(&mut self, registry: &ClientRegistry, channels: &LobbyChannels) -> RoomId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (tx_inputs, rx_inputs) = async_channel::bounded::<RemoteInputEvent>(INPUT_QUEUE);
+        let (tx_reliable, rx_reliable) =
+            async_channel::bounded::<RemoteReliableEvent>(RELIABLE_QUEUE);
+        let tick = Arc::new(AtomicU32::new(0));
+
+        let mut app = (self.new_room)();
+        app.insert_resource(NetChannels {
+            room: id,
+            tx_snapshots: channels.tx_snapshots.clone(),
+            tx_events: channels.tx_events.clone(),
+            rx_inputs,
+            rx_reliable,
+            tick: tick.clone(),
+        })
+        .add_systems(
+            FixedUpdate,
+            (send_snapshots_system, send_coin_and_death_events_system)
+                .after(process_remote_inputs_system)
+                .run_if(in_state(MyAppState::InGame)),
+        )
+        .init_resource::<MatchState>()
+        .init_resource::<RoomOccupancy>()
+        .init_resource::<RoomInputs>()
+        // drained in every state so nothing stale piles up on the leaderboard.
+        .add_systems(
+            FixedUpdate,
+            (
+                process_remote_inputs_system,
+                process_remote_reliable_system,
+                match_lifecycle_system,
+            )
+                .chain(),
+        );
+        if let Some(policy) = &self.ai_partner {
+            app.insert_resource(AiPartner::new(policy.clone()))
+                .add_systems(
+                    FixedUpdate,
+                    drive_ai_partners_system.before(process_remote_inputs_system),
+                );
+        }
+        app.finish();
+        app.cleanup();
+        // enters InGame, which spawns the players.
+        app.update();
+
+        registry.rooms.write().unwrap().insert(
+            id,
+            RoomInbox {
+                inputs: tx_inputs,
+                reliable: tx_reliable,
+                tick,
+            },
+        );
+        self.rooms.insert(id, Room { app });
+        println!("[Server] Opened room {} ({} open)", id, self.rooms.len());
+        id
+    }
 }
 
-// make registry
-// init async_channels
-pub fn setup_udp_server(mut commands: Commands, players: Query<(Entity, &Player)>) {
-    let socket = UdpSocket::bind("0.0.0.0:5000").expect("Failed to bind UDP socket");
-    socket.set_nonblocking(false).unwrap();
-    println!("[UDP Server] Listening on 0.0.0.0:5000");
+#[derive(Resource)]
+pub struct ServerBindAddress(pub String);
 
+// make registry, channels and the network tasks.
+pub fn setup_udp_server(
+    mut commands: Commands,
+    bind_addr: Res<ServerBindAddress>,
+    link: Res<LinkConditions>,
+    transport: Res<NetTransport>,
+    runtime: Res<NetRuntime>,
+) {
+    let socket = transport
+        .bind(&bind_addr.0, &runtime)
+        .unwrap_or_else(|e| panic!("Failed to bind UDP socket on {}: {}", bind_addr.0, e));
+    println!("[UDP Server] Listening on {}", bind_addr.0);
+    let socket = ConditionedSocket::new(socket, *link, &runtime);
     let registry = ClientRegistry::default();
-    let socket_clone = socket.try_clone().unwrap();
 
-    commands.insert_resource(UdpServerSocket { socket });
-    commands.insert_resource(registry.clone());
+    let (tx_snapshots, rx_snapshots) = async_channel::bounded::<SnapshotMsg>(SNAPSHOT_QUEUE);
+    let (tx_handshakes, rx_handshakes) =
+        async_channel::bounded::<HandshakeRequest>(HANDSHAKE_QUEUE);
+    // rooms -> lobby never leaves the main thread and is drained every frame.
+    let (tx_events, rx_events) = async_channel::unbounded::<RoomEvent>();
 
-    let (tx_snapshots, rx_snapshots) = async_channel::unbounded::<SnapshotMsg>();
-    let (tx_inputs, rx_inputs) = async_channel::unbounded::<RemoteInputEvent>();
+    // both run until the runtime shuts down.
+    runtime
+        .spawn(receive_loop(socket.clone(), registry.clone(), tx_handshakes))
+        .detach();
+    runtime
+        .spawn(broadcast_loop(socket.clone(), registry.clone(), rx_snapshots))
+        .detach();
 
-    // this could cause race conditions I need to think a bit more about it.
-    let tx_inputs = tx_inputs.clone();
+    commands.insert_resource(UdpServerSocket { socket });
+    commands.insert_resource(registry);
+    commands.insert_resource(LobbyChannels {
+        rx_handshakes,
+        tx_snapshots,
+        tx_events,
+        rx_events,
+    });
+}
 
-    let mut locals: Vec<(Entity, usize)> = players
-        .iter()
-        .filter_map(|(e, p)| match p {
-            Player::Local(id) => Some((e, *id)),
-            _ => None,
-        })
-        .collect();
+// everything clients send. handshakes are queued for handle_handshakes_system,
+// inputs and reliable events go to their room's world, pings and acks are
+// answered right here.
+async fn receive_loop(
+    socket: ConditionedSocket,
+    registry: ClientRegistry,
+    tx_handshakes: Sender<HandshakeRequest>,
+) {
+    let mut buf = [0u8; protocol::MAX_PACKET_SIZE];
+    let mut limiter = HandshakeLimiter::default();
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // e.g. an icmp unreachable for a client that went away.
+            Err(e) => {
+                eprintln!("[UDP Server] Recv error: {:?}", e);
+                continue;
+            }
+        };
+        match protocol::decode(&buf[..len]) {
+            // over budget: dropped without an answer, so a flood
+            // can't be bounced off us either.
+            Ok(Message::Hello { .. }) if !limiter.allow(addr.ip(), Instant::now()) => {}
+            Ok(Message::Hello { slot, token, room }) => {
+                // handle_handshakes_system builds the client session
+                // and creates the mapping in ClientRegistry.
+                let request = HandshakeRequest {
+                    addr,
+                    slot,
+                    token,
+                    room,
+                };
+                if let Err(e) = tx_handshakes.try_send(request) {
+                    eprintln!("[Server] Failed to queue handshake: {}", e);
+                }
+            }
+            Ok(Message::Ping { id }) => {
+                let room = {
+                    let mut map = registry.clients.write().unwrap();
+                    let Some(client) = map.get_mut(&addr) else {
+                        continue;
+                    };
+                    client.last_seen = Instant::now();
+                    client.room
+                };
 
-    locals.sort_by_key(|&(_, id)| id);
-
-    let (p1, p2) = (locals[0].0, locals[1].0);
-    // Recieve from client
-    // send inputs from clients to main ecs thread.
-    {
-        // shouldn't cause race issues; I am only setting on connection
-        // and not mutating at all.
-        let recv_socket = socket_clone.try_clone().unwrap();
-        let recv_clients = registry.clone();
-        thread::spawn(move || {
-            let mut buf = [0u8; 1024];
-            loop {
-                match recv_socket.recv_from(&mut buf) {
-                    Ok((len, addr)) => {
-                        let data = &buf[..len];
-
-                        if data == b"MAIN" || data == b"PLAY" {
-                            // builds client session and creates mapping in ClientRegistry
-                            handle_handshake(&recv_socket, &recv_clients, addr, data, p1, p2);
-                        } else {
-                            // we received some packet which was not a hankshake acknowledgement
-                            if let Some(event) = parse_input_packet(addr, data, &recv_clients) {
-                                // send input event to ECS thread through the async_channel.
-                                if let Err(e) = tx_inputs.try_send(event) {
-                                    eprintln!("[Server] Failed to send input event to ECS: {}", e);
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("[UDP Server] Recv error: {:?}", e);
-                        break;
+                let tick = registry
+                    .rooms
+                    .read()
+                    .unwrap()
+                    .get(&room)
+                    .map_or(0, |inbox| inbox.tick.load(Ordering::Relaxed));
+                send_message(&socket, addr, &Message::Pong { id, tick });
+            }
+            Ok(Message::Input {
+                token,
+                seq,
+                masks,
+                ack,
+            }) => {
+                if let Some((room, event)) =
+                    parse_input_packet(addr, token, seq, masks, ack, &registry)
+                {
+                    // a full queue loses its oldest packet, every packet repeats
+                    // the frames before it anyway.
+                    let rooms = registry.rooms.read().unwrap();
+                    if let Some(inbox) = rooms.get(&room) {
+                        inbox.inputs.force_send(event).ok();
                     }
                 }
             }
-        });
+            Ok(Message::Reliable { seq, event }) => {
+                let Some(room) = registry
+                    .clients
+                    .read()
+                    .unwrap()
+                    .get(&addr)
+                    .map(|client| client.room)
+                else {
+                    continue;
+                };
+                let inbox = registry
+                    .rooms
+                    .read()
+                    .unwrap()
+                    .get(&room)
+                    .map(|inbox| inbox.reliable.clone());
+
+                // never wait on a room here, this task reads for all of them.
+                // what a full inbox refuses stays unacked and the client sends
+                // it again.
+                let next = {
+                    let mut map = registry.clients.write().unwrap();
+                    let Some(client) = map.get_mut(&addr) else {
+                        continue;
+                    };
+                    client.last_seen = Instant::now();
+                    let slot = client.slot;
+                    // a closed room has no use for them either.
+                    client.reliable.on_receive_with(seq, event, |event| {
+                        inbox.as_ref().is_none_or(|inbox| {
+                            !matches!(
+                                inbox.try_send(RemoteReliableEvent { slot, event }),
+                                Err(TrySendError::Full(_))
+                            )
+                        })
+                    });
+                    // ack duplicates too, the first ack may have been lost.
+                    client.reliable.ack()
+                };
+                send_message(&socket, addr, &Message::ReliableAck { next });
+            }
+            Ok(Message::ReliableAck { next }) => {
+                let mut map = registry.clients.write().unwrap();
+                if let Some(client) = map.get_mut(&addr) {
+                    client.last_seen = Instant::now();
+                    client.reliable.on_ack(next);
+                }
+            }
+            Ok(Message::Disconnect) => {
+                let mut map = registry.clients.write().unwrap();
+                if let Some(session) = map.remove(&addr) {
+                    println!("[Server] {} disconnected", addr);
+                    notify_partner_left(&socket, &map, &session);
+                }
+            }
+            Ok(other) => {
+                eprintln!("[Server] Unexpected message from {}: {:?}", addr, other);
+            }
+            Err(ProtocolError::VersionMismatch { found, .. }) => {
+                eprintln!("[Server] {} speaks protocol v{}, rejecting", addr, found);
+                send_message(
+                    &socket,
+                    addr,
+                    &Message::Reject {
+                        reason: RejectReason::VersionMismatch,
+                    },
+                );
+            }
+            Err(e) => {
+                eprintln!("[Server] Dropping malformed packet from {}: {}", addr, e);
+            }
+        }
     }
+}
 
-    // wait for game state / snapshot messages from ECS thread
-    // brodcast changes to all clients.
-    // there could be sepatate threads for each client. (but maybe this was is easier and less
-    // complicated)
-    {
-        let broadcast_socket = socket_clone;
-        let broadcast_clients = registry.clone();
+// wait for snapshots from the rooms and send every client in the room its
+// delta.
+async fn broadcast_loop(
+    socket: ConditionedSocket,
+    registry: ClientRegistry,
+    rx_snapshots: Receiver<SnapshotMsg>,
+) {
+    // recent snapshots per room, oldest first. candidates for client baselines.
+    let mut histories: HashMap<RoomId, VecDeque<WorldSnapshot>> = HashMap::new();
+    while let Ok(msg) = rx_snapshots.recv().await {
+        let clients_guard = registry.clients.read().unwrap();
+        let targets: Vec<(SocketAddr, Option<u32>)> = clients_guard
+            .iter()
+            .filter(|(_, session)| session.room == msg.room)
+            .map(|(addr, session)| (*addr, session.acked_tick))
+            .collect();
+        drop(clients_guard);
 
-        thread::spawn(move || {
-            let mut tick_count: u64 = 0;
-            println!("[Thread] UDP broadcast thread started");
+        // forget rooms that have been closed.
+        {
+            let rooms = registry.rooms.read().unwrap();
+            histories.retain(|room, _| rooms.contains_key(room));
+        }
+        let history = histories
+            .entry(msg.room)
+            .or_insert_with(|| VecDeque::with_capacity(SNAPSHOT_HISTORY));
 
-            while let Ok(msg) = rx_snapshots.recv_blocking() {
-                tick_count += 1;
+        // clients acking the same tick get the same bytes.
+        let mut encoded: HashMap<Option<u32>, Vec<u8>> = HashMap::new();
+        for (_, acked) in &targets {
+            encoded
+                .entry(*acked)
+                .or_insert_with(|| encode_for_baseline(history, *acked, &msg.snapshot));
+        }
 
-                // Decode tick number if present
-                let tick = if msg.data.len() >= 4 {
-                    u32::from_be_bytes(msg.data[0..4].try_into().unwrap_or_default())
-                } else {
-                    0
-                };
+        if history.len() == SNAPSHOT_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(msg.snapshot);
 
-                let clients_guard = broadcast_clients.clients.read().unwrap();
-                let client_count = clients_guard.len();
+        for (addr, acked) in targets {
+            if let Err(e) = socket.send_to(&encoded[&acked], addr) {
+                eprintln!("[Server] send error to {}: {}", addr, e);
+            }
+        }
+    }
+}
 
-                println!(
-                    "[Broadcast] tick={} | snapshot_size={} bytes | connected_clients={}",
-                    tick,
-                    msg.data.len(),
-                    client_count
-                );
+// the server is going away: free everyone's slot on their side, they go back
+// to reconnecting.
+pub fn disconnect_clients_system(registry: Res<ClientRegistry>, socket: Res<UdpServerSocket>) {
+    let map = registry.clients.read().unwrap();
+    println!("[Server] Shutting down, disconnecting {} clients", map.len());
+    for addr in map.keys() {
+        send_message(&socket.socket, *addr, &Message::Disconnect);
+    }
+}
 
-                if client_count == 0 {
-                    println!("[Broadcast] No clients yet — skipping tick={}", tick);
-                    continue;
-                }
+// finish the handshakes the receive task queued, opening rooms as needed.
+pub fn handle_handshakes_system(
+    channels: Res<LobbyChannels>,
+    registry: Res<ClientRegistry>,
+    socket: Res<UdpServerSocket>,
+    mut host: NonSendMut<RoomHost>,
+) {
+    while let Ok(request) = channels.rx_handshakes.try_recv() {
+        handle_handshake(&socket.socket, &registry, &mut host, &channels, request);
+    }
+}
 
-                let addrs: Vec<SocketAddr> = clients_guard.keys().cloned().collect();
+// queue what the rooms want delivered for every client in them, then send
+// whatever is new or overdue for a resend.
+pub fn send_reliable_system(
+    channels: Res<LobbyChannels>,
+    registry: Res<ClientRegistry>,
+    socket: Res<UdpServerSocket>,
+) {
+    let mut map = registry.clients.write().unwrap();
+    while let Ok(RoomEvent { room, event }) = channels.rx_events.try_recv() {
+        for session in map.values_mut().filter(|s| s.room == room) {
+            session.reliable.send(event);
+        }
+    }
 
-                for (i, addr) in addrs.into_iter().enumerate() {
-                    let data = msg.data.clone(); // OWNED snapshot bytes
-                    let sock = broadcast_socket.try_clone().unwrap(); // OWNED UDP socket
+    let now = Instant::now();
+    for (addr, session) in map.iter_mut() {
+        for (seq, event) in session.reliable.due(now) {
+            send_message(&socket.socket, *addr, &Message::Reliable { seq, event });
+        }
+    }
+}
 
-                    let simulated_ping = Duration::from_millis(1);
+// run one frame of every room. each room keeps its own clock, so FixedUpdate
+// catches up inside the room exactly like it would in a standalone app.
+pub fn update_rooms_system(registry: Res<ClientRegistry>, mut host: NonSendMut<RoomHost>) {
+    // spectators keep a room open but don't start its rounds; SPECTATOR_SLOT
+    // is past the end of the slots.
+    let mut occupancy: HashMap<RoomId, [bool; PLAYERS_PER_ROOM]> = HashMap::new();
+    for session in registry.clients.read().unwrap().values() {
+        if let Some(claimed) = occupancy
+            .entry(session.room)
+            .or_default()
+            .get_mut(session.slot as usize)
+        {
+            *claimed = true;
+        }
+    }
 
-                    IoTaskPool::get()
-                        .spawn(async move {
-                            Timer::after(simulated_ping).await;
+    for (id, room) in host.rooms.iter_mut() {
+        room.app.world_mut().resource_mut::<RoomOccupancy>().0 =
+            occupancy.get(id).copied().unwrap_or_default();
+        room.app.update();
+    }
+}
 
-                            if let Err(e) = sock.send_to(&data, addr) {
-                                eprintln!("[Client] send error: {}", e);
-                            }
-                        })
-                        .detach();
-                }
+// a room with nobody left in it is dropped along with its world.
+pub fn close_empty_rooms_system(registry: Res<ClientRegistry>, mut host: NonSendMut<RoomHost>) {
+    close_empty_rooms(&registry, &mut host);
+}
 
-                // println!(
-                //     "[Broadcast] Finished tick={} ({} clients)\n",
-                //     tick, client_count
-                // );
-            }
+fn close_empty_rooms(registry: &ClientRegistry, host: &mut RoomHost) {
+    let occupied: BTreeSet<RoomId> = registry
+        .clients
+        .read()
+        .unwrap()
+        .values()
+        .map(|session| session.room)
+        .collect();
 
-            println!("[Thread] UDP broadcast thread exited unexpectedly!");
-        });
-    }
-    commands.insert_resource(NetChannels {
-        tx_snapshots,
-        rx_inputs,
-    });
-}
+    let empty: Vec<RoomId> = host
+        .rooms
+        .keys()
+        .filter(|id| !occupied.contains(id))
+        .copied()
+        .collect();
 
-pub fn truncate_f32(v: f32, decimals: u32) -> f32 {
-    let factor = 10f32.powi(decimals as i32);
-    (v * factor).trunc() / factor
+    for id in empty {
+        registry.rooms.write().unwrap().remove(&id);
+        host.rooms.remove(&id);
+        println!(
+            "[Server] Closed empty room {} ({} open)",
+            id,
+            host.rooms.len()
+        );
+    }
 }
 
 // listen for structs (RemoteInputEvent) sent through the channel in the (async receiving task).
-// apply input state to player via events (meh solution maybe needs refactor).
+// every packet goes into its player's jitter buffer, and each fixed tick
+// applies every player's frame stamped with that tick via events (meh solution
+// maybe needs refactor).
 pub fn process_remote_inputs_system(
     channels: Res<NetChannels>,
+    state: Res<MatchState>,
+    players: Query<(Entity, &Player)>,
     mut writer: EventWriter<PlayerInputEvent>,
+    mut buffers: ResMut<RoomInputs>,
+    ai: Option<Res<AiPartner>>,
 ) {
+    // the room's clock starts here, so each frame comes out on the tick the
+    // client stamped it with.
+    let tick = channels.tick.fetch_add(1, Ordering::Relaxed) + 1;
     while let Ok(remote) = channels.rx_inputs.try_recv() {
+        if let Some(buffer) = buffers.0.get_mut(remote.slot as usize) {
+            buffer.insert(remote.seq, &remote.masks);
+        }
+    }
+
+    // drained even on the leaderboard; players are respawned every round, so
+    // look the slot up each tick.
+    for (slot, buffer) in buffers.0.iter_mut().enumerate() {
+        // no client feeds a slot the AI drives. whoever claims it next starts
+        // with an empty buffer.
+        let input = match ai.as_ref().and_then(|ai| ai.input(slot)) {
+            Some(input) => {
+                *buffer = InputBuffer::default();
+                input
+            }
+            None => {
+                let Some(input) = buffer.pop(tick) else {
+                    continue;
+                };
+                input
+            }
+        };
+        // nobody moves before the countdown is over.
+        if state.phase != MatchPhase::Playing {
+            continue;
+        }
+        let Some((entity, _)) = players.iter().find(|(_, p)| p.slot() == slot) else {
+            continue;
+        };
         writer.write(PlayerInputEvent {
-            entity: remote.player,
-            left: remote.left,
-            right: remote.right,
-            jump_pressed: remote.jump_pressed,
-            jump_just_released: remote.jump_just_released,
+            entity,
+            left: input.mask & protocol::INPUT_LEFT != 0,
+            right: input.mask & protocol::INPUT_RIGHT != 0,
+            jump_pressed: input.mask & protocol::INPUT_JUMP != 0,
+            jump_just_released: input.jump_just_released,
+            down: input.mask & protocol::INPUT_DOWN != 0,
         });
     }
 }
 
+// a restart only means something once the round is over; match_lifecycle_system
+// starts the next one.
+pub fn process_remote_reliable_system(channels: Res<NetChannels>, mut state: ResMut<MatchState>) {
+    while let Ok(RemoteReliableEvent { slot, event }) = channels.rx_reliable.try_recv() {
+        match event {
+            // only the climbers decide when to go again.
+            ReliableEvent::RestartRequested
+                if state.phase == MatchPhase::Finished && slot != SPECTATOR_SLOT =>
+            {
+                println!(
+                    "[Server] {} asked room {} for a restart",
+                    who(slot),
+                    channels.room
+                );
+                state.restart_requested = true;
+            }
+            ReliableEvent::RestartRequested => {}
+            other => {
+                eprintln!("[Server] {} sent unexpected {:?}", who(slot), other);
+            }
+        }
+    }
+}
+
+pub fn send_room_event(channels: &NetChannels, event: ReliableEvent) {
+    let msg = RoomEvent {
+        room: channels.room,
+        event,
+    };
+    if let Err(e) = channels.tx_events.try_send(msg) {
+        eprintln!("[Server] Failed to queue reliable event: {}", e);
+    }
+}
+
+// coin pickups and deaths, which clients must see even if the snapshots
+// carrying them are lost.
+pub fn send_coin_and_death_events_system(
+    coins: Query<&MapEntityId, With<Coin>>,
+    total_coin: Res<TotalCoin>,
+    mut game_over: EventReader<MaxHeightReached>,
+    channels: Res<NetChannels>,
+    // coins present last tick.
+    mut present: Local<BTreeSet<u16>>,
+) {
+    let now: BTreeSet<u16> = coins.iter().map(|id| id.0).collect();
+    for &id in present.difference(&now) {
+        send_room_event(
+            &channels,
+            ReliableEvent::CoinCollected {
+                id,
+                total: total_coin.amount,
+            },
+        );
+    }
+    *present = now;
+
+    if let Some(ev) = game_over.read().last() {
+        send_room_event(&channels, ReliableEvent::GameOver { height: ev.height });
+    }
+}
+
+// drop sessions that went quiet (crashed client, lost network) so their slot
+// can be taken again, and tell whoever is left.
+pub fn evict_stale_clients_system(registry: Res<ClientRegistry>, socket: Res<UdpServerSocket>) {
+    let mut map = registry.clients.write().unwrap();
+
+    let stale: Vec<SocketAddr> = map
+        .iter()
+        .filter(|(_, session)| session.last_seen.elapsed() > CLIENT_TIMEOUT)
+        .map(|(addr, _)| *addr)
+        .collect();
+
+    for addr in stale {
+        if let Some(session) = map.remove(&addr) {
+            println!(
+                "[Server] {} ({}) timed out, freeing slot ({} reliable messages undelivered)",
+                addr,
+                who(session.slot),
+                session.reliable.pending()
+            );
+            notify_partner_left(&socket.socket, &map, &session);
+        }
+    }
+}
+
+// totals at the last stats line, to report the rates since.
+#[derive(Default)]
+pub struct StatsLog {
+    at: Option<Instant>,
+    totals: HashMap<SocketAddr, Traffic>,
+}
+
+// one line per connection every SERVER_STATS_INTERVAL, instead of one per
+// snapshot. also forgets the traffic of addresses that are gone.
+pub fn log_connection_stats_system(
+    mut log: Local<StatsLog>,
+    registry: Res<ClientRegistry>,
+    socket: Res<UdpServerSocket>,
+) {
+    let now = Instant::now();
+    let Some(at) = log.at else {
+        log.at = Some(now);
+        return;
+    };
+    let elapsed = now.duration_since(at);
+    if elapsed < SERVER_STATS_INTERVAL {
+        return;
+    }
+
+    let map = registry.clients.read().unwrap();
+    let traffic = socket.socket.traffic();
+    traffic.retain(|addr| map.contains_key(addr));
+
+    let secs = elapsed.as_secs_f64();
+    let mut totals = HashMap::with_capacity(map.len());
+    for (addr, session) in map.iter() {
+        let total = traffic.get(*addr);
+        let window = total.since(&log.totals.get(addr).copied().unwrap_or_default());
+        totals.insert(*addr, total);
+        println!(
+            "[Server] {} {} room={} in={:.0} B/s ({:.1} pkt/s) out={:.0} B/s ({:.1} pkt/s, avg {} B) acked={:?}",
+            addr,
+            who(session.slot),
+            session.room,
+            window.bytes_received as f64 / secs,
+            window.packets_received as f64 / secs,
+            window.bytes_sent as f64 / secs,
+            window.packets_sent as f64 / secs,
+            window.bytes_sent.checked_div(window.packets_sent).unwrap_or(0),
+            session.acked_tick,
+        );
+    }
+    *log = StatsLog {
+        at: Some(now),
+        totals,
+    };
+}
+
+// tell the rest of `left`'s room.
+fn notify_partner_left(
+    socket: &ConditionedSocket,
+    clients: &HashMap<SocketAddr, ClientSession>,
+    left: &ClientSession,
+) {
+    if left.slot == SPECTATOR_SLOT {
+        return;
+    }
+    for (addr, _) in clients.iter().filter(|(_, s)| s.room == left.room) {
+        send_message(socket, *addr, &Message::PartnerLeft { slot: left.slot });
+    }
+}
+
 pub fn has_clients(registry: Option<Res<ClientRegistry>>) -> bool {
     if let Some(reg) = registry {
         let map = reg.clients.read().unwrap();
@@ -242,113 +806,628 @@ pub fn has_clients(registry: Option<Res<ClientRegistry>>) -> bool {
 }
 
 pub fn send_snapshots_system(
-    players: Query<(&Transform, &Player)>,
+    players: Query<(
+        &Player,
+        &Transform,
+        &Velocity,
+        &Momentum,
+        &GroundState,
+        &JumpController,
+    )>,
+    slots: Query<&Player>,
+    ropes: Query<&Rope>,
+    platforms: Query<(&MapEntityId, &Transform, &EasedPlatform)>,
+    coins: Query<&MapEntityId, With<Coin>>,
+    total_coin: Res<TotalCoin>,
+    mut game_over: EventReader<MaxHeightReached>,
     channels: Res<NetChannels>,
-    mut tick: Local<u32>,
+    inputs: Res<RoomInputs>,
+    // every coin id we have ever seen, so picked up coins can be listed.
+    mut known_coins: Local<BTreeSet<u16>>,
 ) {
-    *tick += 1;
+    // advanced by process_remote_inputs_system, which runs first.
+    let tick = channels.tick.load(Ordering::Relaxed);
 
-    let decimals = 1;
-    let mut locals: [Option<&Transform>; 2] = [None, None];
+    // Player::Local(0) first, then Player::Local(1)
+    let mut player_states: Vec<PlayerState> = players
+        .iter()
+        .map(|(player, transform, velocity, momentum, ground, jump)| {
+            let mut state =
+                capture_player(player.slot(), transform, velocity, momentum, ground, jump);
+            if let Some(buffer) = inputs.0.get(player.slot()) {
+                state.last_input = buffer.last_applied();
+            }
+            state
+        })
+        .collect();
+    player_states.sort_by_key(|p| p.slot);
 
-    // Collect references to Player::Local(0) and Player::Local(1)
-    for (transform, player) in &players {
-        if let Player::Local(id) = player {
-            locals[*id] = Some(transform);
-        }
-    }
+    let rope_states = ropes
+        .iter()
+        .filter_map(|rope| {
+            let head = slots.get(rope.attached_entity_head).ok()?.slot();
+            let tail = slots.get(rope.attached_entity_tail).ok()?.slot();
+            Some(capture_rope(rope, head, tail))
+        })
+        .collect();
 
-    // Fallback: only count players we actually found
-    let player_count = locals.iter().flatten().count() as u16;
+    let platform_states = platforms
+        .iter()
+        .map(|(id, transform, platform)| capture_platform(id.0, transform, platform))
+        .collect();
 
-    // tick (4 bytes) + player_count (2 bytes) + N*(x:4, y:4)
-    let mut buf = Vec::with_capacity(4 + 2 + player_count as usize * 8);
-    buf.extend_from_slice(&tick.to_be_bytes());
-    buf.extend_from_slice(&player_count.to_be_bytes());
+    let present: BTreeSet<u16> = coins.iter().map(|id| id.0).collect();
+    known_coins.extend(present.iter().copied());
+    let despawned_coins = known_coins.difference(&present).copied().collect();
 
-    // Write Player::Local(0) first, then Player::Local(1)
-    for id in 0..2 {
-        if let Some(transform) = locals[id] {
-            let x = truncate_f32(transform.translation.x, decimals);
-            let y = truncate_f32(transform.translation.y, decimals);
+    let game_over = game_over.read().last().map(|ev| ev.height);
 
-            buf.extend_from_slice(&x.to_be_bytes());
-            buf.extend_from_slice(&y.to_be_bytes());
-        }
-    }
+    let snapshot = WorldSnapshot {
+        tick,
+        players: player_states,
+        ropes: rope_states,
+        platforms: platform_states,
+        coins_collected: total_coin.amount,
+        despawned_coins,
+        game_over,
+    };
 
-    if let Err(e) = channels.tx_snapshots.try_send(SnapshotMsg { data: buf }) {
+    // the broadcast task falling behind drops old snapshots, not this one.
+    if let Err(e) = channels.tx_snapshots.force_send(SnapshotMsg {
+        room: channels.room,
+        tick,
+        snapshot,
+    }) {
         eprintln!("[Server] Failed to send snapshot to net task: {}", e);
     }
 }
 
+// delta against the client's acked snapshot when we still have it,
+// otherwise the full snapshot.
+fn encode_for_baseline(
+    history: &VecDeque<WorldSnapshot>,
+    acked: Option<u32>,
+    current: &WorldSnapshot,
+) -> Vec<u8> {
+    let delta = acked
+        .and_then(|tick| history.iter().find(|s| s.tick == tick))
+        .and_then(|baseline| delta::diff(baseline, current));
+
+    match delta {
+        Some(delta) => protocol::encode(&Message::DeltaSnapshot(delta)),
+        None => protocol::encode(&Message::Snapshot(current.clone())),
+    }
+}
+
+// "P1", "P2" or "spectator", for the logs.
+fn who(slot: u8) -> String {
+    if slot == SPECTATOR_SLOT {
+        "spectator".to_string()
+    } else {
+        format!("P{}", slot + 1)
+    }
+}
+
+fn send_message(socket: &ConditionedSocket, addr: SocketAddr, msg: &Message) {
+    if let Err(e) = socket.send_to(&protocol::encode(msg), addr) {
+        eprintln!("[Server] send error to {}: {}", addr, e);
+    }
+}
+
 fn handle_handshake(
-    socket: &UdpSocket,
+    socket: &ConditionedSocket,
     registry: &ClientRegistry,
-    addr: SocketAddr,
-    msg: &[u8],
-    p1: Entity,
-    p2: Entity,
+    host: &mut RoomHost,
+    channels: &LobbyChannels,
+    request: HandshakeRequest,
 ) {
-    let player_entity = if msg == b"MAIN" {
-        println!("[Server] {} identified as P1 player", addr);
-        p1
-    } else {
-        println!("[Server] {} identified as P2 player", addr);
-        p2
+    let HandshakeRequest {
+        addr,
+        slot,
+        token,
+        room,
+    } = request;
+
+    if slot > 1 && slot != SPECTATOR_SLOT {
+        println!("[Server] {} asked for invalid slot {}", addr, slot);
+        send_message(
+            socket,
+            addr,
+            &Message::Reject {
+                reason: RejectReason::InvalidSlot,
+            },
+        );
+        return;
+    }
+
+    // a retried Hello whose Welcome got lost goes back to the same room.
+    let current_room = registry
+        .clients
+        .read()
+        .unwrap()
+        .get(&addr)
+        .filter(|s| s.slot == slot)
+        .map(|s| s.room);
+
+    let room = match (room, current_room) {
+        (RoomRequest::Join(id), _) if host.rooms.contains_key(&id) => id,
+        (RoomRequest::Join(id), _) => {
+            println!("[Server] {} asked for unknown room {}", addr, id);
+            send_message(
+                socket,
+                addr,
+                &Message::Reject {
+                    reason: RejectReason::NoSuchRoom,
+                },
+            );
+            return;
+        }
+        (_, Some(id)) => id,
+        (RoomRequest::Create, None) => host.open_room(registry, channels),
+        (RoomRequest::Any, None) => {
+            // any room will do for a spectator, there is always a seat.
+            let clients = registry.clients.read().unwrap();
+            let open = host.rooms.keys().copied().find(|id| {
+                slot == SPECTATOR_SLOT
+                    || !clients
+                        .values()
+                        .any(|s| s.room == *id && s.slot == slot && s.token != token)
+            });
+            drop(clients);
+            match open {
+                Some(id) => id,
+                None => host.open_room(registry, channels),
+            }
+        }
     };
 
+    println!(
+        "[Server] {} identified as {} in room {}",
+        addr,
+        who(slot),
+        room
+    );
+
     let mut map = registry.clients.write().unwrap();
+
+    // someone already holds the slot in this room. that's fine if it is the
+    // same client coming back (same address, or a new address with the old
+    // token), its stale entry is replaced. anyone else only gets the slot once
+    // the holder has timed out. spectators share their slot, so only their
+    // own old entry counts.
+    let holder = map
+        .iter()
+        .find(|(a, s)| {
+            s.room == room
+                && s.slot == slot
+                && (slot != SPECTATOR_SLOT || **a == addr || s.token == token)
+        })
+        .map(|(a, s)| (*a, s.session, s.token, s.last_seen));
+    let (session, token, mut reliable) = match holder {
+        Some((old_addr, old_session, old_token, _)) if old_addr == addr || old_token == token => {
+            println!("[Server] {} reclaimed slot {} in room {}", addr, slot, room);
+            // whatever the client hadn't acked yet goes out again, renumbered
+            // for the new session.
+            let mut reliable = map.remove(&old_addr).unwrap().reliable;
+            reliable.restart();
+            (old_session, old_token, reliable)
+        }
+        Some((old_addr, _, _, last_seen)) if last_seen.elapsed() > CLIENT_TIMEOUT => {
+            println!(
+                "[Server] {} took over slot {} in room {} from {}, which timed out",
+                addr, slot, room, old_addr
+            );
+            if let Some(old) = map.remove(&old_addr) {
+                notify_partner_left(socket, &map, &old);
+            }
+            (
+                NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+                new_token(),
+                ReliableChannel::default(),
+            )
+        }
+        Some((old_addr, ..)) => {
+            println!(
+                "[Server] {} asked for slot {} in room {} but {} holds it",
+                addr, slot, room, old_addr
+            );
+            send_message(
+                socket,
+                addr,
+                &Message::Reject {
+                    reason: RejectReason::SlotTaken,
+                },
+            );
+            return;
+        }
+        None => (
+            NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            new_token(),
+            ReliableChannel::default(),
+        ),
+    };
+
+    // the same address moving to another room or slot leaves its old seat the
+    // way a disconnect would, instead of leaving a ghost behind.
+    if let Some(old) = map.remove(&addr) {
+        println!(
+            "[Server] {} left {} in room {} for {} in room {}",
+            addr,
+            who(old.slot),
+            old.room,
+            who(slot),
+            room
+        );
+        notify_partner_left(socket, &map, &old);
+    }
+
+    // where the match is at, for a client that missed the transitions.
+    let phase = host.rooms[&room].app.world().resource::<MatchState>().phase;
+    reliable.send(ReliableEvent::PhaseChanged(phase));
+
     map.insert(
         addr,
         ClientSession {
             last_seen: Instant::now(),
-            prev_mask: 0,
-            player: player_entity,
+            room,
+            slot,
+            session,
+            token,
+            acked_tick: None,
+            reliable,
+        },
+    );
+
+    send_message(
+        socket,
+        addr,
+        &Message::Welcome {
+            slot,
+            session,
+            room,
+            token,
         },
     );
+}
 
-    let _ = socket.send_to(b"ACK", addr);
+// never 0, which a Hello uses for "no session yet".
+fn new_token() -> u64 {
+    rand::random_range(1..=u64::MAX)
 }
 
 // validates packet and returns player input state struct to send to the bevy ecs thread.
+// deduping and ordering the frames is left to the room's jitter buffer.
 fn parse_input_packet(
     addr: SocketAddr,
-    data: &[u8],
+    token: u64,
+    seq: u32,
+    masks: Vec<u8>,
+    ack: u32,
     clients: &ClientRegistry,
-) -> Option<RemoteInputEvent> {
-    if data.len() < 5 {
+) -> Option<(RoomId, RemoteInputEvent)> {
+    let mut map = clients.clients.write().unwrap();
+    // get client via their address, and make sure it's really them.
+    let client = map.get_mut(&addr)?;
+    if client.token != token {
         return None;
     }
 
-    let _seq = u32::from_be_bytes(data[0..4].try_into().unwrap());
-    let mask = data[4];
+    client.last_seen = Instant::now();
+    // inputs can arrive out of order, never move the baseline backwards.
+    if ack != 0 && client.acked_tick.is_none_or(|t| ack > t) {
+        client.acked_tick = Some(ack);
+    }
+    // spectators only send these for the ack.
+    if client.slot == SPECTATOR_SLOT {
+        return None;
+    }
 
-    let mut map = clients.clients.write().unwrap();
-    // get client via their address.
-    let client = map.get_mut(&addr)?;
+    Some((
+        client.room,
+        RemoteInputEvent {
+            slot: client.slot,
+            seq,
+            masks,
+        },
+    ))
+}
 
-    let prev_mask = client.prev_mask;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multiplayer::transport::{MemoryNetwork, Transport};
+    use crate::player::Player;
 
-    // detect changes between prev packet and this packet to detect if you should
-    // emit a PlayerInputEvent when receiving inputs from the thread to ecs loop via the channel.
-    let jump_pressed = mask & (1 << 0) != 0;
-    let jump_prev_pressed = prev_mask & (1 << 0) != 0;
+    // just the two climbers, enough for the lobby to hand out slots.
+    fn bare_room() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, bevy::state::app::StatesPlugin))
+            .insert_state(MyAppState::InGame)
+            .init_resource::<TotalCoin>()
+            .init_resource::<crate::game_ui::ui::MaxHeight>()
+            .add_event::<MaxHeightReached>()
+            .add_event::<PlayerInputEvent>()
+            .add_systems(Startup, |mut commands: Commands| {
+                commands.spawn(Player::Local(1));
+                commands.spawn(Player::Local(0));
+            });
+        app
+    }
 
-    // not needed yet.
-    let jump_just_pressed = jump_pressed && !jump_prev_pressed;
-    let jump_just_released = !jump_pressed && jump_prev_pressed;
+    struct Lobby {
+        network: MemoryNetwork,
+        socket: ConditionedSocket,
+        registry: ClientRegistry,
+        host: RoomHost,
+        channels: LobbyChannels,
+        _tx_handshakes: Sender<HandshakeRequest>,
+        _rx_snapshots: Receiver<SnapshotMsg>,
+        runtime: NetRuntime,
+    }
 
-    // update session in client registry.
-    // maybe this prev data should be stored somewhere else.
-    client.prev_mask = mask;
-    client.last_seen = Instant::now();
+    impl Lobby {
+        fn new() -> Self {
+            let (tx_snapshots, _rx_snapshots) = async_channel::unbounded();
+            let (_tx_handshakes, rx_handshakes) = async_channel::unbounded();
+            let (tx_events, rx_events) = async_channel::unbounded();
+            let runtime = NetRuntime::default();
+            let network = MemoryNetwork::default();
+            Self {
+                network: network.clone(),
+                socket: ConditionedSocket::new(
+                    NetTransport::Memory(network)
+                        .bind("127.0.0.1:0", &runtime)
+                        .unwrap(),
+                    LinkConditions::default(),
+                    &runtime,
+                ),
+                registry: ClientRegistry::default(),
+                host: RoomHost::new(bare_room, None),
+                channels: LobbyChannels {
+                    rx_handshakes,
+                    tx_snapshots,
+                    tx_events,
+                    rx_events,
+                },
+                _tx_handshakes,
+                _rx_snapshots,
+                runtime,
+            }
+        }
+
+        fn hello(&mut self, port: u16, slot: u8, room: RoomRequest) -> Option<RoomId> {
+            self.hello_with_token(port, slot, 0, room)
+        }
+
+        fn hello_with_token(
+            &mut self,
+            port: u16,
+            slot: u8,
+            token: u64,
+            room: RoomRequest,
+        ) -> Option<RoomId> {
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            let request = HandshakeRequest {
+                addr,
+                slot,
+                token,
+                room,
+            };
+            handle_handshake(
+                &self.socket,
+                &self.registry,
+                &mut self.host,
+                &self.channels,
+                request,
+            );
+            let clients = self.registry.clients.read().unwrap();
+            clients.get(&addr).map(|s| s.room)
+        }
 
-    Some(RemoteInputEvent {
-        player: client.player,
-        left: mask & (1 << 1) != 0,
-        right: mask & (1 << 3) != 0,
-        jump_pressed,
-        jump_just_released,
-    })
+        // everything the server has sent `client` so far.
+        fn received(&self, client: &impl Transport) -> Vec<Message> {
+            let mut buf = [0u8; protocol::MAX_PACKET_SIZE];
+            let mut received = Vec::new();
+            while let Ok(Ok((len, _))) = self.runtime.handle().block_on(async {
+                tokio::time::timeout(Duration::from_millis(100), client.recv_from(&mut buf)).await
+            }) {
+                received.push(protocol::decode(&buf[..len]).unwrap());
+            }
+            received
+        }
+    }
+
+    #[test]
+    fn any_fills_open_rooms_before_opening_new_ones() {
+        let mut lobby = Lobby::new();
+
+        let a_room = lobby.hello(4001, 0, RoomRequest::Any).unwrap();
+        let b_room = lobby.hello(4002, 1, RoomRequest::Any).unwrap();
+        assert_eq!(a_room, b_room);
+
+        // P1 is taken in the first room.
+        let c_room = lobby.hello(4003, 0, RoomRequest::Any).unwrap();
+        assert_ne!(c_room, a_room);
+        assert_eq!(lobby.host.rooms.len(), 2);
+
+        let d_room = lobby.hello(4004, 1, RoomRequest::Join(c_room)).unwrap();
+        assert_eq!(d_room, c_room);
+
+        // a retried Hello doesn't open another room.
+        let e_room = lobby.hello(4005, 0, RoomRequest::Create).unwrap();
+        assert_eq!(lobby.hello(4005, 0, RoomRequest::Create).unwrap(), e_room);
+        assert_eq!(lobby.host.rooms.len(), 3);
+    }
+
+    #[test]
+    fn unknown_rooms_and_taken_slots_are_refused() {
+        let mut lobby = Lobby::new();
+        let room = lobby.hello(4001, 0, RoomRequest::Create).unwrap();
+
+        assert!(lobby.hello(4002, 1, RoomRequest::Join(room + 1)).is_none());
+        assert!(lobby.hello(4003, 0, RoomRequest::Join(room)).is_none());
+        assert!(lobby.hello(4004, 2, RoomRequest::Any).is_none());
+        assert_eq!(lobby.host.rooms.len(), 1);
+    }
+
+    #[test]
+    fn held_slots_need_the_token_or_a_timeout() {
+        let mut lobby = Lobby::new();
+        let room = lobby.hello(4001, 0, RoomRequest::Create).unwrap();
+        let first = SocketAddr::from(([127, 0, 0, 1], 4001));
+        let token = lobby.registry.clients.read().unwrap()[&first].token;
+        assert_ne!(token, 0);
+
+        // inputs only count with the token.
+        let input = |addr, token| parse_input_packet(addr, token, 1, vec![0], 0, &lobby.registry);
+        assert!(input(first, token.wrapping_add(1)).is_none());
+        assert!(input(first, token).is_some());
+
+        // nobody else gets the slot while its holder is around.
+        assert!(lobby.hello(4002, 0, RoomRequest::Join(room)).is_none());
+        assert!(
+            lobby
+                .hello_with_token(4002, 0, token.wrapping_add(1), RoomRequest::Join(room))
+                .is_none()
+        );
+
+        // the holder can come back from a new address with its token.
+        let moved = SocketAddr::from(([127, 0, 0, 1], 4003));
+        assert_eq!(
+            lobby.hello_with_token(4003, 0, token, RoomRequest::Join(room)),
+            Some(room)
+        );
+        assert_eq!(lobby.registry.clients.read().unwrap()[&moved].token, token);
+        assert!(!lobby.registry.clients.read().unwrap().contains_key(&first));
+
+        // once it has timed out, the slot is up for grabs, and its partner is
+        // told.
+        let partner = lobby
+            .network
+            .bind(SocketAddr::from(([127, 0, 0, 1], 4004)))
+            .unwrap();
+        assert_eq!(lobby.hello(4004, 1, RoomRequest::Join(room)), Some(room));
+        lobby
+            .registry
+            .clients
+            .write()
+            .unwrap()
+            .get_mut(&moved)
+            .unwrap()
+            .last_seen = Instant::now() - CLIENT_TIMEOUT * 2;
+        assert_eq!(lobby.hello(4002, 0, RoomRequest::Join(room)), Some(room));
+        assert!(
+            lobby
+                .received(&partner)
+                .contains(&Message::PartnerLeft { slot: 0 })
+        );
+        let clients = lobby.registry.clients.read().unwrap();
+        assert!(!clients.contains_key(&moved));
+        assert_ne!(
+            clients[&SocketAddr::from(([127, 0, 0, 1], 4002))].token,
+            token
+        );
+    }
+
+    #[test]
+    fn spectators_share_a_room_without_taking_a_slot() {
+        let mut lobby = Lobby::new();
+        let room = lobby.hello(4001, 0, RoomRequest::Any).unwrap();
+
+        assert_eq!(
+            lobby.hello(4002, SPECTATOR_SLOT, RoomRequest::Any),
+            Some(room)
+        );
+        assert_eq!(
+            lobby.hello(4003, SPECTATOR_SLOT, RoomRequest::Join(room)),
+            Some(room)
+        );
+        // P2 is still free.
+        assert_eq!(lobby.hello(4004, 1, RoomRequest::Any), Some(room));
+        assert_eq!(lobby.host.rooms.len(), 1);
+        assert_eq!(lobby.registry.clients.read().unwrap().len(), 4);
+
+        // a spectator's inputs only move its delta baseline.
+        let addr = SocketAddr::from(([127, 0, 0, 1], 4002));
+        let token = lobby.registry.clients.read().unwrap()[&addr].token;
+        assert!(parse_input_packet(addr, token, 0, Vec::new(), 7, &lobby.registry).is_none());
+        assert_eq!(
+            lobby.registry.clients.read().unwrap()[&addr].acked_tick,
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn empty_rooms_are_closed() {
+        let mut lobby = Lobby::new();
+        let first = lobby.hello(4001, 0, RoomRequest::Create).unwrap();
+        let second = lobby.hello(4002, 0, RoomRequest::Create).unwrap();
+
+        lobby
+            .registry
+            .clients
+            .write()
+            .unwrap()
+            .remove(&SocketAddr::from(([127, 0, 0, 1], 4001)));
+        close_empty_rooms(&lobby.registry, &mut lobby.host);
+
+        assert!(!lobby.host.rooms.contains_key(&first));
+        assert!(lobby.host.rooms.contains_key(&second));
+        let rooms = lobby.registry.rooms.read().unwrap();
+        assert!(!rooms.contains_key(&first) && rooms.contains_key(&second));
+    }
+
+    #[test]
+    fn moving_to_another_room_frees_the_old_slot() {
+        let mut lobby = Lobby::new();
+        let partner = lobby
+            .network
+            .bind(SocketAddr::from(([127, 0, 0, 1], 4002)))
+            .unwrap();
+        let first = lobby.hello(4001, 0, RoomRequest::Create).unwrap();
+        assert_eq!(lobby.hello(4002, 1, RoomRequest::Join(first)), Some(first));
+
+        let second = lobby.hello(4003, 0, RoomRequest::Create).unwrap();
+        assert_eq!(lobby.hello(4001, 1, RoomRequest::Join(second)), Some(second));
+        let clients = lobby.registry.clients.read().unwrap();
+        assert_eq!(clients.len(), 3);
+        assert!(!clients.values().any(|s| s.room == first && s.slot == 0));
+        drop(clients);
+
+        // the partner hears about it, and the slot is free again.
+        assert!(
+            lobby
+                .received(&partner)
+                .contains(&Message::PartnerLeft { slot: 0 })
+        );
+        assert_eq!(lobby.hello(4004, 0, RoomRequest::Join(first)), Some(first));
+    }
+
+    #[test]
+    fn reconnecting_keeps_unacked_reliable_events() {
+        let mut lobby = Lobby::new();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 4001));
+        let room = lobby.hello(4001, 0, RoomRequest::Create).unwrap();
+
+        let pending = |lobby: &Lobby| {
+            lobby.registry.clients.read().unwrap()[&addr]
+                .reliable
+                .pending()
+        };
+        let event = ReliableEvent::CoinCollected { id: 3, total: 1 };
+        lobby
+            .registry
+            .clients
+            .write()
+            .unwrap()
+            .get_mut(&addr)
+            .unwrap()
+            .reliable
+            .send(event);
+        // the coin, plus the room's phase every Welcome comes with.
+        assert_eq!(pending(&lobby), 2);
+
+        assert_eq!(lobby.hello(4001, 0, RoomRequest::Join(room)), Some(room));
+        assert_eq!(pending(&lobby), 3);
+    }
 }
@@ -0,0 +1,101 @@
+// Conversions between ECS components and the protocol snapshot types.
+// the server captures with these, the client applies with them, so both
+// sides agree on exactly which fields make up a player's state.
+use bevy::prelude::*;
+use std::time::Duration;
+
+use super::protocol::{PlatformState, PlayerState, RopeState};
+use crate::components::motion::{GroundState, JumpController, Momentum, Velocity};
+use crate::components::rope::Rope;
+use crate::map::EasedPlatform;
+
+pub fn capture_player(
+    slot: usize,
+    transform: &Transform,
+    velocity: &Velocity,
+    momentum: &Momentum,
+    ground: &GroundState,
+    jump: &JumpController,
+) -> PlayerState {
+    PlayerState {
+        slot: slot as u8,
+        position: transform.translation.truncate(),
+        velocity: velocity.0,
+        momentum: momentum.0,
+        grounded: ground.is_grounded,
+        coyote_elapsed: ground.coyote_timer.elapsed_secs(),
+        is_jumping: jump.is_jumping,
+        jump_time_elapsed: jump.jump_time_elapsed,
+        can_wall_jump: jump.can_wall_jump,
+        wall_jump_elapsed: jump.wall_jump_timer.elapsed_secs(),
+        ability_available: jump.ability_available,
+        // the server fills this in from its input buffers.
+        last_input: 0,
+    }
+}
+
+// everything except the position, callers decide how to move the transform
+// (snap, blend or predict).
+pub fn apply_player_motion(
+    state: &PlayerState,
+    velocity: &mut Velocity,
+    momentum: &mut Momentum,
+    ground: &mut GroundState,
+    jump: &mut JumpController,
+) {
+    velocity.0 = state.velocity;
+    momentum.0 = state.momentum;
+
+    ground.is_grounded = state.grounded;
+    restore_timer(&mut ground.coyote_timer, state.coyote_elapsed);
+
+    jump.is_jumping = state.is_jumping;
+    jump.jump_time_elapsed = state.jump_time_elapsed;
+    jump.can_wall_jump = state.can_wall_jump;
+    restore_timer(&mut jump.wall_jump_timer, state.wall_jump_elapsed);
+    jump.ability_available = state.ability_available;
+}
+
+// set a timer's elapsed time and recompute `finished()`.
+// set_elapsed alone leaves the finished flag from the last tick.
+pub fn restore_timer(timer: &mut Timer, elapsed_secs: f32) {
+    timer.reset();
+    timer.set_elapsed(Duration::from_secs_f32(elapsed_secs.max(0.0)));
+    timer.tick(Duration::ZERO);
+}
+
+pub fn capture_rope(rope: &Rope, head_slot: usize, tail_slot: usize) -> RopeState {
+    RopeState {
+        head: head_slot as u8,
+        tail: tail_slot as u8,
+        rest_length: rope.constraint.rest_length,
+        max_extension: rope.constraint.max_extension,
+        spring_constant: rope.constraint.spring_constant,
+    }
+}
+
+pub fn apply_rope(state: &RopeState, rope: &mut Rope) {
+    rope.constraint.rest_length = state.rest_length;
+    rope.constraint.max_extension = state.max_extension;
+    rope.constraint.spring_constant = state.spring_constant;
+}
+
+pub fn capture_platform(id: u16, transform: &Transform, platform: &EasedPlatform) -> PlatformState {
+    PlatformState {
+        id,
+        position: transform.translation.truncate(),
+        t: platform.t,
+        forward: platform.forward,
+    }
+}
+
+pub fn apply_platform(
+    state: &PlatformState,
+    transform: &mut Transform,
+    platform: &mut EasedPlatform,
+) {
+    platform.t = state.t;
+    platform.forward = state.forward;
+    transform.translation.x = state.position.x;
+    transform.translation.y = state.position.y;
+}
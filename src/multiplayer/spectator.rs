@@ -0,0 +1,101 @@
+// Spectator side of a net game.
+//
+// a spectator handshakes with SPECTATOR_SLOT and both climbers come in as
+// Player::Net, so snapshots and interpolation work exactly as they do for a
+// partner. all that is left here is keeping the server's deltas going and
+// picking which climber the camera follows.
+use bevy::prelude::*;
+use std::sync::atomic::Ordering;
+
+use super::client::{ClientConnection, ClientNetChannels, UdpClientSocket};
+use super::protocol::{self, Message};
+use crate::app::{FollowedPlayer, GameMode};
+use crate::player::Player;
+
+pub fn spectating(mode: Option<Res<GameMode>>) -> bool {
+    matches!(mode.as_deref(), Some(GameMode::Spectate))
+}
+
+// an Input without frames, only so the server learns our newest snapshot and
+// can keep sending deltas against it.
+pub fn send_spectator_ack_system(
+    channels: Option<Res<ClientNetChannels>>,
+    client: Option<Res<UdpClientSocket>>,
+    connection: Option<Res<ClientConnection>>,
+) {
+    let (Some(channels), Some(client), Some(connection)) = (channels, client, connection) else {
+        return;
+    };
+    let buf = protocol::encode(&Message::Input {
+        token: connection.token,
+        seq: 0,
+        masks: Vec::new(),
+        ack: channels.acked_tick.load(Ordering::Relaxed),
+    });
+    if let Err(e) = client.socket.send_to(&buf, client.server_addr) {
+        eprintln!("[Client] Failed to send spectator ack: {}", e);
+    }
+}
+
+// 1 / 2 follow that climber, tab switches to the other one.
+pub fn switch_followed_player_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    followed: Query<(Entity, &Player), With<FollowedPlayer>>,
+    players: Query<(Entity, &Player)>,
+) {
+    let current = followed.iter().next();
+    let target = if keyboard.just_pressed(KeyCode::Digit1) {
+        0
+    } else if keyboard.just_pressed(KeyCode::Digit2) {
+        1
+    } else if keyboard.just_pressed(KeyCode::Tab) {
+        current.map_or(0, |(_, player)| (player.slot() + 1) % 2)
+    } else {
+        return;
+    };
+
+    let Some((entity, _)) = players.iter().find(|(_, p)| p.slot() == target) else {
+        return;
+    };
+    if let Some((old, _)) = current {
+        commands.entity(old).remove::<FollowedPlayer>();
+    }
+    commands.entity(entity).insert(FollowedPlayer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn followed_slot(app: &mut App) -> Vec<usize> {
+        let world = app.world_mut();
+        world
+            .query_filtered::<&Player, With<FollowedPlayer>>()
+            .iter(world)
+            .map(Player::slot)
+            .collect()
+    }
+
+    #[test]
+    fn tab_and_number_keys_move_the_camera_between_climbers() {
+        let mut app = App::new();
+        app.init_resource::<ButtonInput<KeyCode>>()
+            .add_systems(Update, switch_followed_player_system);
+        app.world_mut().spawn((Player::Net(0), FollowedPlayer));
+        app.world_mut().spawn(Player::Net(1));
+
+        let press = |app: &mut App, key: KeyCode| {
+            let mut keys = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+            keys.reset_all();
+            keys.press(key);
+            app.update();
+            followed_slot(app)
+        };
+        assert_eq!(press(&mut app, KeyCode::Tab), vec![1]);
+        assert_eq!(press(&mut app, KeyCode::Tab), vec![0]);
+        assert_eq!(press(&mut app, KeyCode::Digit2), vec![1]);
+        assert_eq!(press(&mut app, KeyCode::Digit2), vec![1]);
+        assert_eq!(press(&mut app, KeyCode::Digit1), vec![0]);
+    }
+}
@@ -0,0 +1,288 @@
+// Network statistics.
+//
+// both ends count every datagram per peer as it goes through ConditionedSocket
+// (TrafficTable). on the client, update_net_stats_system folds that together
+// with ping round trips, snapshot arrivals, prediction corrections and the
+// clock sync (clock.rs) into NetStats once per STATS_WINDOW, for the netgraph (game_ui::netgraph) and
+// anything else that wants to know how the link is doing. the server logs a
+// line per connection every SERVER_STATS_INTERVAL.
+use bevy::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::client::{ClientNetChannels, UdpClientSocket};
+use super::clock::ClockSync;
+
+pub const STATS_WINDOW: Duration = Duration::from_secs(1);
+pub const SERVER_STATS_INTERVAL: Duration = Duration::from_secs(5);
+// snapshots kept for the graph, newest last.
+pub const GRAPH_SAMPLES: usize = 120;
+// weight of a new sample in the smoothed rtt and prediction error.
+const SMOOTHING: f32 = 0.125;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+}
+
+impl Traffic {
+    pub fn since(&self, earlier: &Traffic) -> Traffic {
+        Traffic {
+            packets_sent: self.packets_sent.saturating_sub(earlier.packets_sent),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            packets_received: self
+                .packets_received
+                .saturating_sub(earlier.packets_received),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+        }
+    }
+}
+
+// traffic per peer. cheap to clone, clones share the counts.
+#[derive(Clone, Default)]
+pub struct TrafficTable(Arc<Mutex<HashMap<SocketAddr, Traffic>>>);
+
+impl TrafficTable {
+    pub fn record_sent(&self, peer: SocketAddr, bytes: usize) {
+        let mut table = self.0.lock().unwrap();
+        let traffic = table.entry(peer).or_default();
+        traffic.packets_sent += 1;
+        traffic.bytes_sent += bytes as u64;
+    }
+
+    pub fn record_received(&self, peer: SocketAddr, bytes: usize) {
+        let mut table = self.0.lock().unwrap();
+        let traffic = table.entry(peer).or_default();
+        traffic.packets_received += 1;
+        traffic.bytes_received += bytes as u64;
+    }
+
+    pub fn get(&self, peer: SocketAddr) -> Traffic {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // forget peers we no longer care about (anyone can send us a datagram).
+    pub fn retain(&self, mut keep: impl FnMut(&SocketAddr) -> bool) {
+        self.0.lock().unwrap().retain(|peer, _| keep(peer));
+    }
+}
+
+// a snapshot datagram the receiver task got, counted whether or not it could
+// be rebuilt.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotReceipt {
+    pub tick: u32,
+    pub bytes: usize,
+}
+
+// what the last window accumulated.
+#[derive(Debug, Default, Clone)]
+struct Window {
+    started: Option<Instant>,
+    traffic: Traffic,
+    // ticks the server sent that should have reached us, and those that did.
+    expected: u32,
+    received: u32,
+    snapshot_bytes: usize,
+}
+
+#[derive(Resource, Debug, Default, Clone)]
+pub struct NetStats {
+    // smoothed round trip, None until the first pong.
+    pub rtt: Option<Duration>,
+    // snapshots that never arrived during the last window, 0.0 to 1.0.
+    pub snapshot_loss: f32,
+    // average snapshot datagram during the last window.
+    pub snapshot_bytes: u32,
+    pub bytes_in_per_sec: u32,
+    pub bytes_out_per_sec: u32,
+    // distance between where we had the local player and where the replayed
+    // server state put it, smoothed.
+    pub prediction_error: f32,
+    // ticks our sim is behind where the clock sync wants it, negative when
+    // it runs too far ahead of the server.
+    pub tick_drift: f32,
+    // snapshot sizes, 0 for one that never arrived.
+    pub graph: VecDeque<u32>,
+
+    newest_tick: Option<u32>,
+    window: Window,
+}
+
+impl NetStats {
+    pub fn on_pong(&mut self, rtt: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(smoothed) => smoothed.mul_f32(1.0 - SMOOTHING) + rtt.mul_f32(SMOOTHING),
+            None => rtt,
+        });
+    }
+
+    pub fn on_snapshot(&mut self, receipt: SnapshotReceipt) {
+        let SnapshotReceipt { tick, bytes } = receipt;
+        self.window.received += 1;
+        self.window.snapshot_bytes += bytes;
+
+        // a late one was already expected when we skipped past it, and its
+        // gap stays in the graph.
+        if let Some(newest) = self.newest_tick
+            && tick <= newest
+        {
+            return;
+        }
+        let skipped = self.newest_tick.map_or(0, |n| tick - n - 1);
+        self.window.expected += skipped + 1;
+        for _ in 0..skipped.min(GRAPH_SAMPLES as u32) {
+            self.push_graph(0);
+        }
+        self.push_graph(bytes as u32);
+        self.newest_tick = Some(tick);
+    }
+
+    pub fn on_correction(&mut self, error: f32) {
+        self.prediction_error += (error - self.prediction_error) * SMOOTHING;
+    }
+
+    // closes the window if it has run for STATS_WINDOW. `traffic` is the
+    // running total with the server.
+    pub fn roll_window(&mut self, traffic: Traffic, now: Instant) {
+        let Some(started) = self.window.started else {
+            self.window.started = Some(now);
+            self.window.traffic = traffic;
+            return;
+        };
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed < STATS_WINDOW {
+            return;
+        }
+
+        let secs = elapsed.as_secs_f64();
+        let delta = traffic.since(&self.window.traffic);
+        self.bytes_in_per_sec = (delta.bytes_received as f64 / secs) as u32;
+        self.bytes_out_per_sec = (delta.bytes_sent as f64 / secs) as u32;
+
+        let Window {
+            expected,
+            received,
+            snapshot_bytes,
+            ..
+        } = self.window;
+        if expected > 0 {
+            self.snapshot_loss = expected.saturating_sub(received) as f32 / expected as f32;
+        }
+        if received > 0 {
+            self.snapshot_bytes = (snapshot_bytes / received as usize) as u32;
+        }
+
+        self.window = Window {
+            started: Some(now),
+            traffic,
+            ..Default::default()
+        };
+    }
+
+    // new session: tick numbers start over.
+    pub fn restart(&mut self) {
+        self.newest_tick = None;
+    }
+
+    fn push_graph(&mut self, bytes: u32) {
+        if self.graph.len() == GRAPH_SAMPLES {
+            self.graph.pop_front();
+        }
+        self.graph.push_back(bytes);
+    }
+}
+
+impl fmt::Display for NetStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rtt {
+            Some(rtt) => writeln!(f, "rtt {:.0} ms", rtt.as_secs_f64() * 1000.0)?,
+            None => writeln!(f, "rtt -")?,
+        }
+        writeln!(f, "loss {:.1}%", self.snapshot_loss * 100.0)?;
+        writeln!(f, "snapshot {} B", self.snapshot_bytes)?;
+        writeln!(
+            f,
+            "in {:.1} kB/s  out {:.1} kB/s",
+            self.bytes_in_per_sec as f32 / 1000.0,
+            self.bytes_out_per_sec as f32 / 1000.0
+        )?;
+        writeln!(f, "prediction error {:.1} px", self.prediction_error)?;
+        write!(f, "tick drift {:+.1}", self.tick_drift)
+    }
+}
+
+// everything the receiver task saw since last frame, plus the window roll.
+pub fn update_net_stats_system(
+    mut stats: ResMut<NetStats>,
+    channels: Res<ClientNetChannels>,
+    client: Res<UdpClientSocket>,
+    clock: Option<Res<ClockSync>>,
+) {
+    while let Ok(receipt) = channels.rx_receipts.try_recv() {
+        stats.on_snapshot(receipt);
+    }
+    if let Some(error) = clock.and_then(|c| c.error()) {
+        stats.tick_drift = error as f32;
+    }
+    let traffic = client.socket.traffic().get(client.server_addr);
+    stats.roll_window(traffic, Instant::now());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(tick: u32) -> SnapshotReceipt {
+        SnapshotReceipt { tick, bytes: 100 }
+    }
+
+    #[test]
+    fn skipped_ticks_count_as_loss_until_the_window_rolls() {
+        let mut stats = NetStats::default();
+        let start = Instant::now();
+        stats.roll_window(Traffic::default(), start);
+
+        for tick in [1, 2, 4, 5, 8, 3] {
+            stats.on_snapshot(receipt(tick));
+        }
+        assert_eq!(stats.snapshot_loss, 0.0);
+
+        let traffic = Traffic {
+            bytes_received: 3000,
+            bytes_sent: 500,
+            ..Default::default()
+        };
+        stats.roll_window(traffic, start + STATS_WINDOW);
+        // 8 ticks sent, 6 got here, one of them late.
+        assert_eq!(stats.snapshot_loss, 0.25);
+        assert_eq!(stats.snapshot_bytes, 100);
+        assert_eq!(stats.bytes_in_per_sec, 3000);
+        assert_eq!(stats.bytes_out_per_sec, 500);
+        assert_eq!(stats.graph, [100, 100, 0, 100, 100, 0, 0, 100]);
+    }
+
+    #[test]
+    fn rtt_and_prediction_error_are_smoothed() {
+        let mut stats = NetStats::default();
+        stats.on_pong(Duration::from_millis(100));
+        assert_eq!(stats.rtt, Some(Duration::from_millis(100)));
+        stats.on_pong(Duration::from_millis(900));
+        let rtt = stats.rtt.unwrap().as_secs_f64();
+        assert!((rtt - 0.2).abs() < 1e-6, "{rtt}");
+
+        stats.on_correction(8.0);
+        assert_eq!(stats.prediction_error, 1.0);
+    }
+}
@@ -0,0 +1,226 @@
+// Datagram transports.
+//
+// everything the multiplayer code sends or receives goes through a Transport:
+// unreliable datagrams between SocketAddrs, the same contract as a UDP socket.
+// ConditionedSocket puts the link conditioner and the traffic counts on top,
+// so every backend gets those.
+//
+// sends never wait, so the ECS can send from any system; receiving is awaited
+// by a task on the NetRuntime (runtime.rs).
+//
+// tokio's UdpSocket is the real one. MemoryTransport delivers over channels
+// inside the process (see MemoryNetwork), so tests, or a server and its clients
+// in one process, run the whole networked path without opening a socket.
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use super::runtime::NetRuntime;
+
+pub type RecvFuture<'a> = Pin<Box<dyn Future<Output = io::Result<(usize, SocketAddr)>> + Send + 'a>>;
+
+pub trait Transport: Send + Sync {
+    // never waits. a datagram that doesn't fit in the send buffer is an error
+    // and gone, like on a congested link.
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<()>;
+    // the next datagram to arrive.
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> RecvFuture<'a>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<()> {
+        self.try_send_to(data, addr).map(|_| ())
+    }
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> RecvFuture<'a> {
+        Box::pin(UdpSocket::recv_from(self, buf))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+// which transport the multiplayer plugins open.
+#[derive(Resource, Clone, Default)]
+pub enum NetTransport {
+    #[default]
+    Udp,
+    Memory(MemoryNetwork),
+}
+
+impl NetTransport {
+    // udp sockets belong to `runtime`'s reactor.
+    pub fn bind(&self, addr: &str, runtime: &NetRuntime) -> io::Result<Arc<dyn Transport>> {
+        match self {
+            NetTransport::Udp => {
+                let socket = std::net::UdpSocket::bind(addr)?;
+                socket.set_nonblocking(true)?;
+                let _runtime = runtime.handle().enter();
+                Ok(Arc::new(UdpSocket::from_std(socket)?))
+            }
+            NetTransport::Memory(network) => {
+                let addr = addr
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                Ok(Arc::new(network.bind(addr)?))
+            }
+        }
+    }
+
+    // hostnames only mean something over udp.
+    pub fn resolve(&self, addr: &str) -> Option<SocketAddr> {
+        match self {
+            NetTransport::Udp => addr.to_socket_addrs().ok()?.next(),
+            NetTransport::Memory(_) => addr.parse().ok(),
+        }
+    }
+}
+
+type Datagram = (SocketAddr, Vec<u8>);
+
+// ports handed out for binds to port 0, like the os does for udp.
+const EPHEMERAL_PORTS: u16 = 49152;
+// datagrams waiting to be read. more than that and new ones are dropped, like
+// a full socket buffer does.
+const INBOX_CAPACITY: usize = 1024;
+
+#[derive(Default)]
+struct Hosts {
+    inboxes: HashMap<SocketAddr, Sender<Datagram>>,
+    next_port: u16,
+}
+
+// a set of MemoryTransports that can reach each other. cheap to clone, clones
+// are the same network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    hosts: Arc<Mutex<Hosts>>,
+}
+
+impl MemoryNetwork {
+    // like UdpSocket::bind. port 0 picks a free port and an unspecified ip
+    // becomes localhost, so the address can be handed to the other side.
+    pub fn bind(&self, mut addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut hosts = self.hosts.lock().unwrap();
+        if addr.ip().is_unspecified() {
+            addr.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        if addr.port() == 0 {
+            loop {
+                hosts.next_port = hosts.next_port.wrapping_add(1).max(EPHEMERAL_PORTS);
+                addr.set_port(hosts.next_port);
+                if !hosts.inboxes.contains_key(&addr) {
+                    break;
+                }
+            }
+        }
+        if hosts.inboxes.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+
+        let (tx, rx) = mpsc::channel(INBOX_CAPACITY);
+        hosts.inboxes.insert(addr, tx);
+        Ok(MemoryTransport {
+            network: self.clone(),
+            addr,
+            inbox: tokio::sync::Mutex::new(rx),
+        })
+    }
+}
+
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    inbox: tokio::sync::Mutex<Receiver<Datagram>>,
+}
+
+impl Transport for MemoryTransport {
+    // nobody bound at `addr`, or their inbox is full, and the datagram is
+    // gone, the same as udp.
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<()> {
+        let hosts = self.network.hosts.lock().unwrap();
+        if let Some(inbox) = hosts.inboxes.get(&addr) {
+            inbox.try_send((self.addr, data.to_vec())).ok();
+        }
+        Ok(())
+    }
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> RecvFuture<'a> {
+        Box::pin(async move {
+            let (from, data) = self
+                .inbox
+                .lock()
+                .await
+                .recv()
+                .await
+                .ok_or(io::ErrorKind::NotConnected)?;
+            // a datagram longer than the buffer is cut off, like udp does.
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok((len, from))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.hosts.lock().unwrap().inboxes.remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datagrams_reach_the_bound_address_with_the_sender_attached() {
+        let runtime = NetRuntime::default();
+        let network = MemoryNetwork::default();
+        let server = network.bind("127.0.0.1:5000".parse().unwrap()).unwrap();
+        let client = network.bind("0.0.0.0:0".parse().unwrap()).unwrap();
+        let client_addr = client.local_addr().unwrap();
+        assert!(client_addr.ip().is_loopback());
+        assert_ne!(client_addr.port(), 0);
+
+        client.send_to(b"hello", server.local_addr().unwrap()).unwrap();
+        let mut buf = [0u8; 3];
+        let received = runtime.handle().block_on(server.recv_from(&mut buf));
+        assert_eq!(received.unwrap(), (3, client_addr));
+        assert_eq!(&buf, b"hel");
+
+        // nobody there, dropped quietly.
+        client.send_to(b"?", "127.0.0.1:1".parse().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn full_inboxes_drop_and_addresses_free_up_when_dropped() {
+        let network = MemoryNetwork::default();
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let socket = network.bind(addr).unwrap();
+        assert_eq!(
+            network.bind(addr).err().map(|e| e.kind()),
+            Some(io::ErrorKind::AddrInUse)
+        );
+
+        let sender = network.bind("0.0.0.0:0".parse().unwrap()).unwrap();
+        for _ in 0..INBOX_CAPACITY + 10 {
+            sender.send_to(b"flood", addr).unwrap();
+        }
+        assert_eq!(socket.inbox.try_lock().unwrap().len(), INBOX_CAPACITY);
+
+        drop(socket);
+        assert!(network.bind(addr).is_ok());
+    }
+}
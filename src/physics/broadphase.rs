@@ -0,0 +1,180 @@
+// Broadphase.
+//
+// a uniform grid over every map Collider, so a collision check only looks at
+// the colliders near whatever is moving instead of the whole mountain. a
+// collider is filed when it appears (map load, spawned platforms), filed again
+// when its transform changes (moving platforms) and dropped when it goes away.
+// the physics step, the enemy checks and client prediction all ask it for
+// candidates and run the exact test on those.
+use bevy::math::bounding::{Aabb2d, BoundingVolume};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+use crate::map::Collider;
+
+// a few player widths; most platforms land in one or two cells.
+const CELL_SIZE: f32 = 128.0;
+// how much further than its velocity says a body can get in one tick (a jump,
+// a rope snap) and still have its colliders looked at.
+pub const SWEEP_MARGIN: f32 = 64.0;
+
+#[derive(Resource, Default)]
+pub struct Broadphase {
+    cells: HashMap<IVec2, Vec<Entity>>,
+    // the cells each collider is filed under.
+    filed: HashMap<Entity, IRect>,
+}
+
+impl Broadphase {
+    // file `entity` under `aabb` (world space), moving it if it was filed.
+    pub fn insert(&mut self, entity: Entity, aabb: Aabb2d) {
+        self.remove(entity);
+        let range = cell_range(&aabb);
+        for cell in cells(range) {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+        self.filed.insert(entity, range);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(range) = self.filed.remove(&entity) else {
+            return;
+        };
+        for cell in cells(range) {
+            if let Some(entities) = self.cells.get_mut(&cell) {
+                entities.retain(|&e| e != entity);
+                if entities.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(Entity) -> bool) {
+        let gone: Vec<Entity> = self.filed.keys().copied().filter(|&e| !keep(e)).collect();
+        for entity in gone {
+            self.remove(entity);
+        }
+    }
+
+    // every collider filed in a cell `area` touches, each once and in entity
+    // order so the caller's results don't depend on the grid.
+    pub fn query(&self, area: &Aabb2d) -> Vec<Entity> {
+        let mut found: Vec<Entity> = cells(cell_range(area))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    pub fn len(&self) -> usize {
+        self.filed.len()
+    }
+}
+
+// what a box at `aabb` can reach moving by `motion`, plus `margin` all round.
+pub fn swept_area(aabb: &Aabb2d, motion: Vec2, margin: f32) -> Aabb2d {
+    aabb.merge(&aabb.translated_by(motion))
+        .grow(Vec2::splat(margin))
+}
+
+fn cell_range(aabb: &Aabb2d) -> IRect {
+    IRect::from_corners(
+        (aabb.min / CELL_SIZE).floor().as_ivec2(),
+        (aabb.max / CELL_SIZE).floor().as_ivec2(),
+    )
+}
+
+fn cells(range: IRect) -> impl Iterator<Item = IVec2> {
+    (range.min.y..=range.max.y)
+        .flat_map(move |y| (range.min.x..=range.max.x).map(move |x| IVec2::new(x, y)))
+}
+
+// runs before anything queries it on FixedUpdate.
+pub fn update_broadphase_system(
+    mut broadphase: ResMut<Broadphase>,
+    changed: Query<(Entity, &Transform, &Collider), Or<(Changed<Transform>, Changed<Collider>)>>,
+    mut removed: RemovedComponents<Collider>,
+) {
+    for entity in removed.read() {
+        broadphase.remove(entity);
+    }
+    for (entity, transform, collider) in &changed {
+        broadphase.insert(
+            entity,
+            collider
+                .aabb
+                .translated_by(transform.translation.truncate()),
+        );
+    }
+}
+
+// removals only show up for a couple of frames, and FixedUpdate doesn't run
+// every frame; whatever was missed is gone by the next round.
+pub fn prune_broadphase_system(
+    mut broadphase: ResMut<Broadphase>,
+    colliders: Query<(), With<Collider>>,
+) {
+    broadphase.retain(|entity| colliders.contains(entity));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(center: Vec2, half: f32) -> Aabb2d {
+        Aabb2d::new(center, Vec2::splat(half))
+    }
+
+    #[test]
+    fn queries_find_nearby_colliders_once_and_follow_moves() {
+        let mut broadphase = Broadphase::default();
+        let (wide, small, far) = (
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Entity::from_raw(3),
+        );
+        // spans ten cells.
+        broadphase.insert(
+            wide,
+            Aabb2d::new(Vec2::new(640.0, 0.0), Vec2::new(640.0, 5.0)),
+        );
+        broadphase.insert(small, square(Vec2::new(300.0, 300.0), 10.0));
+        broadphase.insert(far, square(Vec2::new(300.0, 4000.0), 10.0));
+
+        let near_ground = square(Vec2::new(300.0, 20.0), 300.0);
+        assert_eq!(broadphase.query(&near_ground), [wide, small]);
+        assert_eq!(
+            broadphase.query(&square(Vec2::new(300.0, 2000.0), 10.0)),
+            []
+        );
+
+        // a moving platform leaves its old cells behind.
+        broadphase.insert(small, square(Vec2::new(300.0, 3900.0), 10.0));
+        assert_eq!(broadphase.query(&near_ground), [wide]);
+        assert_eq!(
+            broadphase.query(&square(Vec2::new(300.0, 3950.0), 100.0)),
+            [small, far]
+        );
+
+        broadphase.remove(far);
+        broadphase.retain(|entity| entity != wide);
+        assert_eq!(broadphase.len(), 1);
+        assert!(
+            broadphase
+                .cells
+                .values()
+                .all(|entities| entities == &[small])
+        );
+    }
+
+    #[test]
+    fn swept_areas_cover_the_whole_move() {
+        let area = swept_area(&square(Vec2::ZERO, 10.0), Vec2::new(0.0, -500.0), 5.0);
+        assert_eq!(area.min, Vec2::new(-15.0, -515.0));
+        assert_eq!(area.max, Vec2::new(15.0, 15.0));
+    }
+}
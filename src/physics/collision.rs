@@ -2,8 +2,8 @@ use crate::config::physics::{PLAYER_CONTROL_SPEED_LIMIT, PLAYER_JUMP_FORCE, PLAY
 use crate::enemy::bundle::{Enemy, EnemyCollider, EnemyMovement};
 use crate::physics::MaxHeightReached;
 use crate::player::{self, PlayerCollider};
-use bevy::math::bounding::{Aabb2d, AabbCast2d, BoundingVolume, RayCast2d};
-use bevy::math::{Dir2, Ray2d};
+use bevy::math::Dir2;
+use bevy::math::bounding::{Aabb2d, AabbCast2d, BoundingVolume};
 
 use crate::config::physics::GRAVITY;
 use crate::game_ui::ui::TotalCoin;
@@ -11,8 +11,6 @@ use crate::player::Player;
 use bevy::math::bounding::IntersectsVolume;
 use bevy::{prelude::*, transform};
 
-use crate::config::player::{PLAYER_LENGTH, PLAYER_WIDTH};
-
 #[derive(Event, Debug)]
 pub struct PlayerCollisionEvent {
     pub player: Entity,
@@ -47,61 +45,125 @@ pub fn on_collision(
     }
 }
 
-use crate::components::motion::{GroundState, JumpController, Mass, Momentum, Velocity};
-use crate::map::{Collider, Platform};
+use super::broadphase::{Broadphase, swept_area};
+use super::step::{Body, RopeLink, Solid, StepEvent};
+use crate::map::Collider;
+use std::time::Duration;
 
 const PLATFORM_FRICTION: f32 = 0.88;
 
-// Predict the player's AABB for the next frame
-fn predicted_aabb(
-    transform: &Transform,
-    velocity: &Velocity,
-    player_collider: &PlayerCollider,
-    dt: f32,
-) -> Aabb2d {
-    let current_pos = transform.translation.truncate();
-    let future_pos = current_pos + velocity.0 * dt;
-    let delta = future_pos - current_pos;
-
-    player_collider.aabb.translated_by(current_pos + delta)
+// contacts closer than this count as touching, so float error doesn't
+// let a resting body sink in or snag on the seam between two tiles.
+const CONTACT_EPSILON: f32 = 0.01;
+// a body this far into a one-way platform still lands on it.
+const PLATFORM_SNAP: f32 = 2.0;
+// contacts resolved per body per tick, enough for a corner plus a slide.
+const MAX_SWEEPS: usize = 4;
+
+/// Side of a solid that was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    Top,
+    Bottom,
+    Left,
+    Right,
 }
 
-fn resolve_collision(
-    player_pos: &mut Vec3,
-    velocity: &mut Vec2,
-    momentum: &mut Vec2,
-    ground: &mut GroundState,
-    jump_controller: &mut JumpController,
-    offset: Vec2,
-) {
-    if offset.x.abs() > offset.y.abs() {
-        // Horizontal collision
-        player_pos.x -= offset.x;
-        //
-        if offset.x > 0.0 {
-            // Collided from the left side of a wall → push player to the left
-            player_pos.x += PLAYER_WIDTH / 2.;
-        } else {
-            // Collided from the right side of a wall → push player to the right
-            player_pos.x -= PLAYER_WIDTH / 2.;
+impl Face {
+    pub fn normal(self) -> Vec2 {
+        match self {
+            Face::Top => Vec2::Y,
+            Face::Bottom => Vec2::NEG_Y,
+            Face::Left => Vec2::NEG_X,
+            Face::Right => Vec2::X,
         }
+    }
+
+    fn is_vertical(self) -> bool {
+        matches!(self, Face::Top | Face::Bottom)
+    }
 
-        // Refresh wall jump timer to use wall jump
-        if !ground.is_grounded && jump_controller.can_wall_jump {
-            jump_controller.wall_jump_timer.reset();
+    // `moving` rests against this face of `target`, overlapping it along the
+    // face rather than only at a corner.
+    fn touches(self, moving: &Aabb2d, target: &Aabb2d) -> bool {
+        let (gap, along) = match self {
+            Face::Top => (moving.min.y - target.max.y, overlap(moving, target).x),
+            Face::Bottom => (target.min.y - moving.max.y, overlap(moving, target).x),
+            Face::Left => (target.min.x - moving.max.x, overlap(moving, target).y),
+            Face::Right => (moving.min.x - target.max.x, overlap(moving, target).y),
+        };
+        gap.abs() <= CONTACT_EPSILON && along > CONTACT_EPSILON
+    }
+
+    // where a `collider` centred on `position` rests against this face.
+    fn snap(self, collider: &Aabb2d, target: &Aabb2d, position: &mut Vec2) {
+        match self {
+            Face::Top => position.y = target.max.y - collider.min.y,
+            Face::Bottom => position.y = target.min.y - collider.max.y,
+            Face::Left => position.x = target.min.x - collider.max.x,
+            Face::Right => position.x = target.max.x - collider.min.x,
         }
+    }
+}
 
-        velocity.x = 0.0;
-        momentum.x = 0.0;
-    } else {
-        // Vertical collision
-        player_pos.y -= offset.y;
-        velocity.y = 0.0;
-        momentum.y = 0.0;
+// how far two boxes overlap on each axis, negative for a gap.
+fn overlap(a: &Aabb2d, b: &Aabb2d) -> Vec2 {
+    a.max.min(b.max) - a.min.max(b.min)
+}
+
+/// Swept AABB test: `collider` centred on `from` and moving by `motion`
+/// against `target`. Returns how much of the move is done at the time of
+/// impact (0 to 1) and the face of `target` it hits. Boxes already overlapping
+/// hit at 0 on the face they are least deep in; a box only touching or moving
+/// away doesn't hit.
+pub fn sweep(collider: &Aabb2d, from: Vec2, motion: Vec2, target: &Aabb2d) -> Option<(f32, Face)> {
+    let start = collider.translated_by(from);
+    let depth = overlap(&start, target);
+    if depth.x > CONTACT_EPSILON && depth.y > CONTACT_EPSILON {
+        let faces = [
+            (Face::Top, target.max.y - start.min.y),
+            (Face::Bottom, start.max.y - target.min.y),
+            (Face::Left, start.max.x - target.min.x),
+            (Face::Right, target.max.x - start.min.x),
+        ];
+        let (face, _) = faces.into_iter().min_by(|a, b| a.1.total_cmp(&b.1))?;
+        return Some((0.0, face));
+    }
 
+    let direction = Dir2::new(motion).ok()?;
+    let distance = motion.length();
+    let toi = AabbCast2d::new(*collider, from, direction, distance).aabb_collision_at(*target)?;
+    let contact = collider.translated_by(from + direction * toi);
+    // a corner hit counts as landing.
+    let face = [Face::Top, Face::Bottom, Face::Left, Face::Right]
+        .into_iter()
+        .find(|face| face.touches(&contact, target) && motion.dot(face.normal()) < 0.0)?;
+    Some((toi / distance, face))
+}
+
+fn resolve_collision(body: &mut Body, face: Face) {
+    let Body {
+        velocity,
+        momentum,
+        ground,
+        jump: jump_controller,
+        ..
+    } = body;
+    match face {
+        // Horizontal collision
+        Face::Left | Face::Right => {
+            // Refresh wall jump timer to use wall jump
+            if !ground.is_grounded && jump_controller.can_wall_jump {
+                jump_controller.wall_jump_timer.reset();
+            }
+
+            velocity.x = 0.0;
+            momentum.x = 0.0;
+        }
         // colliding with top
-        if offset.y > 0.0 {
-            player_pos.y += PLAYER_LENGTH / 2.;
+        Face::Top => {
+            velocity.y = 0.0;
+            momentum.y = 0.0;
             ground.is_grounded = true;
             // velocity.x *= PLATFORM_FRICTION;
             momentum.x *= PLATFORM_FRICTION;
@@ -110,143 +172,104 @@ fn resolve_collision(
             jump_controller.can_wall_jump = true;
         }
         // colliding with bottom
-        else {
-            player_pos.y -= PLAYER_LENGTH / 2.;
+        Face::Bottom => {
+            velocity.y = 0.0;
+            momentum.y = 0.0;
         }
     }
 }
 
-/// Main player–platform collision system
-pub fn platform_collider_system(
-    mut events: EventWriter<PlayerCollisionEvent>,
-    time: Res<Time>,
-    mut players: Query<
-        (
-            Entity,
-            &mut Transform,
-            &mut Velocity,
-            &mut Momentum,
-            &PlayerCollider,
-            &mut GroundState,
-            &Mass,
-            &mut JumpController,
-        ),
-        With<Player>,
-    >,
-    colliders: Query<(Entity, &Transform, &Collider), Without<Player>>,
-    spikes: Query<(), With<crate::map::Spike>>,
-    trampolines: Query<(&crate::map::TrampolineBounce,), With<crate::map::TrampolineBounce>>,
-    platforms: Query<(), With<Platform>>,
-    mut game_over: EventWriter<MaxHeightReached>,
+// the first solid `body` hits moving by `motion` from `from`.
+fn first_hit(
+    body: &Body,
+    from: Vec2,
+    motion: Vec2,
+    solids: &[Solid],
+) -> Option<(f32, Face, usize)> {
+    let bottom = body.collider.translated_by(from).min.y;
+    solids
+        .iter()
+        .enumerate()
+        .filter_map(|(i, solid)| {
+            let (toi, face) = sweep(&body.collider, from, motion, &solid.aabb)?;
+            // One-way: only block landing on top (allow jumping through from
+            // below), and not even that while holding down
+            if solid.one_way
+                && (body.drop_through
+                    || !(face == Face::Top
+                        && motion.y <= 0.0
+                        && bottom > solid.aabb.max.y - PLATFORM_SNAP))
+            {
+                return None;
+            }
+            Some((toi, face, i))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Main player–platform collision. `body` moved from `start` to its position
+/// this tick; the move is swept against every solid, stopping at the first
+/// contact and sliding along it, so nothing is fast enough to pass through.
+/// `index` is the body's place in the step.
+pub fn collide_solids(
+    index: usize,
+    body: &mut Body,
+    start: Vec2,
+    solids: &[Solid],
+    events: &mut Vec<StepEvent>,
 ) {
-    let dt = time.delta_secs();
-
-    for (
-        player,
-        mut transform,
-        mut velocity,
-        mut momentum,
-        player_collider,
-        mut ground,
-        mass,
-        mut jump_controller,
-    ) in players.iter_mut()
-    {
-        let mut player_aabb = predicted_aabb(&transform, &velocity, player_collider, dt);
-        ground.is_grounded = false;
-
-        for (game_object, collider_transform, collider) in colliders.iter() {
-            let collider_pos = collider_transform.translation.truncate();
-            let collider_aabb = collider.aabb.translated_by(collider_pos);
-
-            if player_aabb.intersects(&collider_aabb) {
-                let mut player_pos = transform.translation;
-
-                if platforms.get(game_object).is_ok() {
-                    // println!("Player collided with platform entity: {:?}", game_object);
-                    let platform_top = collider_aabb.max.y;
-                    let player_bottom = player_pos.y - (PLAYER_LENGTH / 2.0);
-
-                    // Distance check for "standing on platform"
-                    let vertical_distance = player_bottom - platform_top;
-                    let is_standing_on = vertical_distance.abs() < 2.0;
-
-                    // Only block upward collisions (allow falling through from below)
-                    let is_above = player_bottom >= platform_top - 1.0;
-                    let is_falling = velocity.0.y < 0.0;
-
-                    // Allow collision if: standing on platform OR falling onto it from above
-                    if !(is_standing_on || (is_above && is_falling)) {
-                        continue;
-                    }
-                }
+    body.ground.is_grounded = false;
+    let mut from = start;
+    let mut motion = body.position - start;
+
+    for _ in 0..MAX_SWEEPS {
+        let Some((toi, face, solid_index)) = first_hit(body, from, motion, solids) else {
+            body.position = from + motion;
+            return;
+        };
+        let solid = &solids[solid_index];
+
+        // move up to the contact, then slide along the face with what's left.
+        let mut contact = from + motion * toi;
+        face.snap(&body.collider, &solid.aabb, &mut contact);
+        motion *= 1.0 - toi;
+        if face.is_vertical() {
+            motion.y = 0.0;
+        } else {
+            motion.x = 0.0;
+        }
+        from = contact;
 
-                // (Original collision resolution begins)
-                let player_center = player_aabb.center();
-                let closest = collider_aabb.closest_point(player_center);
-                let offset = player_center - closest;
-
-                // 钉子：任何竖直方向碰撞都会游戏结束
-                if spikes.get(game_object).is_ok() {
-                    // println!("Player collided with spike! offset.y: {}, velocity.y: {}", offset.y, velocity.0.y);
-                    // println!("Spike deadly conditions met! Game over triggered.");
-                    game_over.write(super::MaxHeightReached {
-                        height: transform.translation.y,
-                    });
-                }
+        // 钉子：任何竖直方向碰撞都会游戏结束
+        if solid.spike {
+            events.push(StepEvent::MaxHeightReached { height: contact.y });
+        }
 
-                // 保存碰撞前的速度，用于蹦床弹力计算
-                let velocity_before_collision = velocity.0;
-
-                resolve_collision(
-                    &mut player_pos,
-                    &mut velocity.0,
-                    &mut momentum.0,
-                    &mut ground,
-                    &mut jump_controller,
-                    offset,
-                );
-
-                // 蹦床：玩家从上方落下时给予向上弹力（在碰撞解决后应用）
-                if let Ok((bounce_strength,)) = trampolines.get(game_object) {
-                    // println!("Player collided with trampoline! offset.y: {}, velocity_before: {}, bounce_strength: {}", offset.y, velocity_before_collision.y, bounce_strength.0);
-                    if offset.y > 0.0 && velocity_before_collision.y <= 0.0 {
-                        // println!("Trampoline conditions met! Applying bounce with strength {}.", bounce_strength.0);
-                        velocity.0.y = -velocity_before_collision.y + bounce_strength.0; // 使用碰撞前的速度
-                        momentum.0.y = velocity.0.y * mass.0; // 使用玩家的实际质量重新计算动量
-                    // println!("Applied bounce: velocity.y = {}, momentum.y = {}, mass = {}", velocity.0.y, momentum.0.y, mass.0);
-                    } else {
-                        // println!("Trampoline conditions NOT met.");
-                    }
-                }
+        // 保存碰撞前的速度，用于蹦床弹力计算
+        let velocity_before_collision = body.velocity;
 
-                events.write(PlayerCollisionEvent {
-                    player,
-                    game_object,
-                });
+        resolve_collision(body, face);
 
-                player_aabb = player_collider.aabb.translated_by(player_pos.truncate());
-                transform.translation = player_pos;
-            }
+        // 蹦床：玩家从上方落下时给予向上弹力（在碰撞解决后应用）
+        if let Some(bounce_strength) = solid.trampoline
+            && face == Face::Top
+            && velocity_before_collision.y <= 0.0
+        {
+            body.velocity.y = -velocity_before_collision.y + bounce_strength; // 使用碰撞前的速度
+            body.momentum.y = body.velocity.y * body.mass; // 使用玩家的实际质量重新计算动量
         }
+
+        events.push(StepEvent::Touched {
+            body: index,
+            solid: solid_index,
+        });
     }
-}
 
-pub fn player_collider_system(
-    time: Res<Time>,
-    mut query: Query<(
-        Entity,
-        &mut Velocity,
-        &mut Transform,
-        &mut Momentum,
-        &PlayerCollider,
-        &mut JumpController,
-        &mut GroundState,
-    )>,
-) {
-    let dt = time.delta_secs();
-    let mut players: Vec<_> = query.iter_mut().collect();
+    // still hitting things, stay at the last contact.
+    body.position = from;
+}
 
+pub fn collide_players(players: &mut [Body], dt: f32) {
     for i in 0..players.len() {
         if i + 1 >= players.len() {
             break;
@@ -256,49 +279,31 @@ pub fn player_collider_system(
 
         if let Some(obj1) = one.last_mut() {
             for obj2 in two.iter_mut() {
-                let &mut (
-                    id1,
-                    ref mut vel1,
-                    ref mut trans1,
-                    ref mut mom1,
-                    collider1,
-                    ref mut jump_controller1,
-                    ref mut ground_state1,
-                ) = obj1;
-                let &mut (
-                    id2,
-                    ref mut vel2,
-                    ref mut trans2,
-                    ref mut mom2,
-                    collider2,
-                    ref mut jump_controller2,
-                    ref mut ground_state2,
-                ) = obj2;
-                let &mut (
-                    id1,
-                    ref mut vel1,
-                    ref mut trans1,
-                    ref mut mom1,
-                    collider1,
-                    ref mut jump_controller1,
-                    ref mut ground_state1,
-                ) = obj1;
-                let &mut (
-                    id2,
-                    ref mut vel2,
-                    ref mut trans2,
-                    ref mut mom2,
-                    collider2,
-                    ref mut jump_controller2,
-                    ref mut ground_state2,
-                ) = obj2;
+                let Body {
+                    position: pos1,
+                    velocity: vel1,
+                    momentum: mom1,
+                    collider: collider1,
+                    jump: jump_controller1,
+                    ground: ground_state1,
+                    ..
+                } = &mut *obj1;
+                let Body {
+                    position: pos2,
+                    velocity: vel2,
+                    momentum: mom2,
+                    collider: collider2,
+                    jump: jump_controller2,
+                    ground: ground_state2,
+                    ..
+                } = obj2;
 
                 // Predict future positions
-                let future_pos1 = trans1.translation.truncate() + vel1.0 * dt;
-                let future_pos2 = trans2.translation.truncate() + vel2.0 * dt;
+                let future_pos1 = *pos1 + *vel1 * dt;
+                let future_pos2 = *pos2 + *vel2 * dt;
 
-                let aabb1 = collider1.aabb.translated_by(future_pos1);
-                let aabb2 = collider2.aabb.translated_by(future_pos2);
+                let aabb1 = collider1.translated_by(future_pos1);
+                let aabb2 = collider2.translated_by(future_pos2);
 
                 if aabb1.intersects(&aabb2) {
                     // --- Compute overlap rectangle ---
@@ -309,26 +314,26 @@ pub fn player_collider_system(
                         // 🧭 Resolve horizontally
                         if aabb1.center().x < aabb2.center().x {
                             // push obj1 left, obj2 right
-                            trans1.translation.x -= overlap_x / 2.0;
-                            trans2.translation.x += overlap_x / 2.0;
+                            pos1.x -= overlap_x / 2.0;
+                            pos2.x += overlap_x / 2.0;
                         } else {
-                            trans1.translation.x += overlap_x / 2.0;
-                            trans2.translation.x -= overlap_x / 2.0;
+                            pos1.x += overlap_x / 2.0;
+                            pos2.x -= overlap_x / 2.0;
                         }
 
                         // Basic horizontal momentum resolution
-                        let total_momentum = mom1.0.x + mom2.0.x;
-                        mom1.0.x = total_momentum * 0.5;
-                        mom2.0.x = total_momentum * 0.5;
-                        vel1.0.x = 0.0;
-                        vel2.0.x = 0.0;
+                        let total_momentum = mom1.x + mom2.x;
+                        mom1.x = total_momentum * 0.5;
+                        mom2.x = total_momentum * 0.5;
+                        vel1.x = 0.0;
+                        vel2.x = 0.0;
                         // Resolve vertically
                     } else {
                         // Resolve vertically
                         if aabb1.center().y < aabb2.center().y {
                             // Push obj1 down, obj2 up
-                            trans1.translation.y -= overlap_y / 2.0;
-                            trans2.translation.y += overlap_y / 2.0;
+                            pos1.y -= overlap_y / 2.0;
+                            pos2.y += overlap_y / 2.0;
 
                             // obj2 landed on obj1, reset jumps
                             jump_controller2.can_wall_jump = true;
@@ -337,11 +342,11 @@ pub fn player_collider_system(
                             ground_state2.coyote_timer.reset();
 
                             // Apply friction to top of player
-                            mom2.0.x *= PLATFORM_FRICTION;
+                            mom2.x *= PLATFORM_FRICTION;
                         } else {
                             // Push obj1 up, obj2 down
-                            trans1.translation.y += overlap_y / 2.0;
-                            trans2.translation.y -= overlap_y / 2.0;
+                            pos1.y += overlap_y / 2.0;
+                            pos2.y -= overlap_y / 2.0;
 
                             // obj1 landed on obj2, reset jumps
                             jump_controller1.can_wall_jump = true;
@@ -350,15 +355,15 @@ pub fn player_collider_system(
                             ground_state1.coyote_timer.reset();
 
                             // Apply friction to top of player
-                            mom1.0.x *= PLATFORM_FRICTION;
+                            mom1.x *= PLATFORM_FRICTION;
                         }
 
                         // Basic vertical momentum resolution
-                        let total_momentum = mom1.0.y + mom2.0.y;
-                        mom1.0.y = total_momentum * 0.5;
-                        mom2.0.y = total_momentum * 0.5;
-                        vel1.0.y = 0.0;
-                        vel2.0.y = 0.0;
+                        let total_momentum = mom1.y + mom2.y;
+                        mom1.y = total_momentum * 0.5;
+                        mom2.y = total_momentum * 0.5;
+                        vel1.y = 0.0;
+                        vel2.y = 0.0;
                     }
                 }
             }
@@ -380,45 +385,83 @@ fn check_top(pos1: Vec2, width: Vec2, pos2: Vec2, width2: Vec2) -> bool {
         && (pos1.y - width.y) <= (pos2.y + width2.y);
 }
 
-pub fn update_coyote_timer_system(
-    time: Res<Time>,
-    mut query: Query<&mut GroundState, With<Player>>,
-) {
-    for mut ground_state in &mut query {
-        // If in air tick the coyote timer
-        if !ground_state.is_grounded {
-            ground_state.coyote_timer.tick(time.delta());
-        }
-        // Grounded reset timer
-        else {
-            ground_state.coyote_timer.reset();
-        }
+pub fn tick_timers(body: &mut Body, dt: Duration) {
+    let ground_state = &mut body.ground;
+    // If in air tick the coyote timer
+    if !ground_state.is_grounded {
+        ground_state.coyote_timer.tick(dt);
+    }
+    // Grounded reset timer
+    else {
+        ground_state.coyote_timer.reset();
     }
+
+    body.jump.wall_jump_timer.tick(dt);
 }
 
-pub fn update_wall_jump_timer_system(
-    time: Res<Time>,
-    mut query: Query<&mut JumpController, With<Player>>,
-) {
-    for mut jump_controller in &mut query {
-        jump_controller.wall_jump_timer.tick(time.delta());
+// the moving solid `body` was standing on before it moved this tick.
+fn standing_on<'a>(body: &Body, solids: &'a [Solid]) -> Option<&'a Solid> {
+    if !body.ground.is_grounded {
+        return None;
     }
+    let feet = body.collider.translated_by(body.position);
+    solids.iter().find(|solid| {
+        solid.motion != Vec2::ZERO
+            && Face::Top.touches(&feet, &solid.aabb.translated_by(-solid.motion))
+    })
 }
 
+/// Moving platforms carry whoever stands on them, and whoever hangs from
+/// that player's rope. Jumping off keeps the platform's velocity. Runs before
+/// integrate, so the sweep starts from where the platform put the body.
+pub fn ride_platforms(bodies: &mut [Body], ropes: &[RopeLink], solids: &[Solid], dt: f32) {
+    let mut carried = vec![None; bodies.len()];
+    for (i, body) in bodies.iter_mut().enumerate() {
+        let Some(motion) = standing_on(body, solids).map(|solid| solid.motion) else {
+            continue;
+        };
+        body.position += motion;
+        // a jump force this tick means the body is leaving the platform
+        if body.control_force.y > 0.0 {
+            body.momentum += motion / dt * body.mass;
+        }
+        carried[i] = Some(motion);
+    }
+
+    for rope in ropes {
+        for (rider, partner) in [(rope.head, rope.tail), (rope.tail, rope.head)] {
+            let (Some(Some(motion)), Some(None)) = (carried.get(rider), carried.get(partner))
+            else {
+                continue;
+            };
+            let hanging = !bodies[partner].ground.is_grounded
+                && bodies[rider].position.distance(bodies[partner].position)
+                    > rope.constraint.rest_length;
+            if hanging {
+                bodies[partner].position += *motion;
+            }
+        }
+    }
+}
+
+// enemies are swept along last tick's move too.
 pub fn enemy_player_collision_system(
     mut events: EventWriter<EnemyPlayerCollisionEvent>,
-    enemy_query: Query<(Entity, &Transform, &EnemyCollider), With<Enemy>>,
+    enemy_query: Query<(Entity, &Transform, &EnemyCollider, &EnemyMovement), With<Enemy>>,
     player_query: Query<(Entity, &Transform, &PlayerCollider), With<Player>>,
 ) {
-    for (enemy_entity, enemy_transform, enemy_collider) in enemy_query.iter() {
+    for (enemy_entity, enemy_transform, enemy_collider, movement) in enemy_query.iter() {
         let enemy_pos = enemy_transform.translation.truncate();
         let enemy_aabb = enemy_collider.aabb.translated_by(enemy_pos);
+        let from = enemy_pos - movement.last_move;
 
         for (player_entity, player_transform, player_collider) in player_query.iter() {
             let player_pos = player_transform.translation.truncate();
             let player_aabb = player_collider.aabb.translated_by(player_pos);
 
-            if enemy_aabb.intersects(&player_aabb) {
+            if enemy_aabb.intersects(&player_aabb)
+                || sweep(&enemy_collider.aabb, from, movement.last_move, &player_aabb).is_some()
+            {
                 events.write(EnemyPlayerCollisionEvent {
                     player: player_entity,
                     enemy: enemy_entity,
@@ -430,18 +473,33 @@ pub fn enemy_player_collision_system(
 
 pub fn enemy_platform_collision_system(
     mut events: EventWriter<EnemyPlatformCollisionEvent>,
-    enemy_query: Query<(Entity, &Transform, &EnemyCollider), With<Enemy>>,
+    enemy_query: Query<(Entity, &Transform, &EnemyCollider, &EnemyMovement), With<Enemy>>,
     platform_query: Query<(Entity, &Transform, &Collider), Without<Player>>,
+    broadphase: Res<Broadphase>,
 ) {
-    for (enemy_entity, enemy_transform, enemy_collider) in enemy_query.iter() {
+    for (enemy_entity, enemy_transform, enemy_collider, movement) in enemy_query.iter() {
         let enemy_pos = enemy_transform.translation.truncate();
         let enemy_aabb = enemy_collider.aabb.translated_by(enemy_pos);
-
-        for (platform_entity, platform_transform, platform_collider) in platform_query.iter() {
+        let from = enemy_pos - movement.last_move;
+        let area = swept_area(&enemy_aabb, -movement.last_move, 0.0);
+
+        for (platform_entity, platform_transform, platform_collider) in broadphase
+            .query(&area)
+            .into_iter()
+            .filter_map(|entity| platform_query.get(entity).ok())
+        {
             let platform_pos = platform_transform.translation.truncate();
             let platform_aabb = platform_collider.aabb.translated_by(platform_pos);
 
-            if enemy_aabb.intersects(&platform_aabb) {
+            if enemy_aabb.intersects(&platform_aabb)
+                || sweep(
+                    &enemy_collider.aabb,
+                    from,
+                    movement.last_move,
+                    &platform_aabb,
+                )
+                .is_some()
+            {
                 events.write(EnemyPlatformCollisionEvent {
                     enemy: enemy_entity,
                     platform: platform_entity,
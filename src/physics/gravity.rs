@@ -1,18 +1,13 @@
 // SPDX-License-Identifier: MIT
 // Copyright (c) 2025 Tingxu Chen
 // Author: Tingxu Chen <tic128@pitt.edu>
-// Description: <Gravity system>
-use bevy::prelude::*;
+// Description: <Gravity>
+use super::step::Body;
 use crate::config::physics::GRAVITY;
-use crate::components::motion::{NetForce, Gravity, Mass};
 
-pub fn gravity_system(
-    mut query: Query<(&mut NetForce, &Gravity, &Mass)>,
-) {
-    for (mut net_force, gravity, mass) in &mut query {
-        if gravity.0 {
-            // F = m * g
-            net_force.0 += GRAVITY * mass.0;
-        }
+pub fn apply_gravity(body: &mut Body) {
+    if body.gravity {
+        // F = m * g
+        body.net_force += GRAVITY * body.mass;
     }
 }
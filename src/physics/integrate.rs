@@ -1,72 +1,43 @@
 // SPDX-License-Identifier: MIT
 // Copyright (c) 2025 Tingxu Chen
 // Author: Tingxu Chen <tic128@pitt.edu>
-// Description: <Systems for physics integration>
-use crate::components::motion::{Mass, Momentum, NetForce, Velocity};
+// Description: <Physics integration>
+use super::step::{Body, StepEvent};
 use crate::config::player::{PLAYER_LENGTH, PLAYER_WIDTH};
-use crate::player::Player;
-use bevy::prelude::*;
 
-pub fn clean_force_system(mut query: Query<&mut NetForce>) {
-    for mut net_force in query.iter_mut() {
-        net_force.0 = Vec2::ZERO;
-    }
-}
-
-pub fn integrate_force_system(time: Res<Time>, mut query: Query<(&mut Momentum, &NetForce)>) {
-    let delta_seconds = time.delta_secs();
-    for (mut momentum, net_force) in query.iter_mut() {
-        momentum.0 += net_force.0 * delta_seconds;
-    }
-}
-
-pub fn integrate_momentum_system(mut query: Query<(&mut Velocity, &Momentum, &Mass)>) {
-    for (mut velocity, momentum, mass) in query.iter_mut() {
-        velocity.0 = momentum.0 / mass.0;
-    }
-}
-
-pub fn integrate_velocity_system(
-    time: Res<Time<Fixed>>,
-    mut query: Query<(&mut Transform, &Velocity)>,
-) {
-    let delta_seconds = time.delta_secs();
-    for (mut transform, velocity) in query.iter_mut() {
-        transform.translation.x += velocity.0.x * delta_seconds;
-        transform.translation.y += velocity.0.y * delta_seconds;
-    }
+// net force -> momentum -> velocity -> position.
+pub fn integrate(body: &mut Body, dt: f32) {
+    body.net_force += body.rope_force;
+    body.momentum += body.net_force * dt;
+    body.velocity = body.momentum / body.mass;
+    body.position += body.velocity * dt;
 }
 
 // Force to give a windows boundary
-pub fn boundary(
-    mut query: Query<(&mut Transform, &mut Velocity, &mut Momentum)>,
-    mut max_height_ev: EventWriter<super::MaxHeightReached>,
-) {
+pub fn boundary(body: &mut Body, events: &mut Vec<StepEvent>) {
     let width = 1280.0 - PLAYER_WIDTH; // minus player width
     let height = 64.0 * 64.0 - PLAYER_LENGTH; // minus player height
-    for (mut transform, mut velocity, mut momentum) in query.iter_mut() {
-        if transform.translation.x < PLAYER_WIDTH / 2. {
-            transform.translation.x = PLAYER_WIDTH / 2.;
-            velocity.0.x = 0.0;
-            momentum.0.x = 0.0;
-        }
-        if transform.translation.x > width + (PLAYER_WIDTH / 2.) {
-            transform.translation.x = width + (PLAYER_WIDTH / 2.);
-            velocity.0.x = 0.0;
-            momentum.0.x = 0.0;
-        }
-        if transform.translation.y < PLAYER_LENGTH / 2. {
-            transform.translation.y = PLAYER_LENGTH / 2.;
-            velocity.0.y = 0.0;
-            momentum.0.y = 0.0;
-        }
-        if transform.translation.y > (height) {
-            transform.translation.y = height + (PLAYER_LENGTH / 2.);
-            velocity.0.y = 0.0;
-            momentum.0.y = 0.0;
-            max_height_ev.write(super::MaxHeightReached {
-                height: transform.translation.y,
-            });
-        }
+    if body.position.x < PLAYER_WIDTH / 2. {
+        body.position.x = PLAYER_WIDTH / 2.;
+        body.velocity.x = 0.0;
+        body.momentum.x = 0.0;
+    }
+    if body.position.x > width + (PLAYER_WIDTH / 2.) {
+        body.position.x = width + (PLAYER_WIDTH / 2.);
+        body.velocity.x = 0.0;
+        body.momentum.x = 0.0;
+    }
+    if body.position.y < PLAYER_LENGTH / 2. {
+        body.position.y = PLAYER_LENGTH / 2.;
+        body.velocity.y = 0.0;
+        body.momentum.y = 0.0;
+    }
+    if body.position.y > (height) {
+        body.position.y = height + (PLAYER_LENGTH / 2.);
+        body.velocity.y = 0.0;
+        body.momentum.y = 0.0;
+        events.push(StepEvent::MaxHeightReached {
+            height: body.position.y,
+        });
     }
 }
@@ -4,32 +4,33 @@ use bevy::prelude::*;
 // Copyright (c) 2025 Tingxu Chen
 // Author: Tingxu Chen <tic128@pitt.edu>
 // Description: <Physics system module and plugin>
+use bevy::math::bounding::BoundingVolume;
 use crate::config::MyAppState;
 use crate::physics::collision::{EnemyPlatformCollisionEvent, EnemyPlayerCollisionEvent};
 
+pub mod broadphase;
 pub mod collision;
 pub mod gravity;
 pub mod integrate;
 pub mod rope_force;
+pub mod step;
 
-use self::gravity::gravity_system;
-use self::integrate::boundary;
-use self::integrate::clean_force_system;
-use self::integrate::integrate_force_system;
-use self::integrate::integrate_momentum_system;
-use self::integrate::integrate_velocity_system;
-use self::rope_force::clean_rope_force_system;
-use self::rope_force::rope_force_to_system;
-use self::rope_force::rope_tension_system;
-// use self::rope_force::debug_print_rope_mesh2d;
-// use self::rope_force::debug_print_player_world_pos;
-
+use self::broadphase::{
+    Broadphase, SWEEP_MARGIN, prune_broadphase_system, swept_area, update_broadphase_system,
+};
 use self::collision::PlayerCollisionEvent;
 use self::collision::on_collision;
-use self::collision::platform_collider_system;
-use self::collision::player_collider_system;
-use self::collision::update_coyote_timer_system;
-use self::collision::update_wall_jump_timer_system;
+use self::step::{Body, BodyInput, PhysicsState, RopeLink, Solid, StepEvent};
+use crate::components::motion::{
+    ControlForce, Gravity, GroundState, JumpController, Mass, Momentum, NetForce, RopeForce,
+    Velocity,
+};
+use crate::components::rope::Rope;
+use crate::map::{Collider, EasedPlatform, OneWay, Spike, TrampolineBounce};
+use crate::player::player_control::PlayerInputEvent;
+use crate::player::{Player, PlayerCollider};
+// use self::rope_force::debug_print_rope_mesh2d;
+// use self::rope_force::debug_print_player_world_pos;
 
 #[derive(Event)]
 pub struct MaxHeightReached {
@@ -43,23 +44,14 @@ impl Plugin for PhysicsPlugin {
             .add_event::<EnemyPlatformCollisionEvent>()
             .add_event::<EnemyPlayerCollisionEvent>()
             .add_event::<MaxHeightReached>()
+            .init_resource::<Broadphase>()
+            .add_systems(OnEnter(MyAppState::InGame), prune_broadphase_system)
             .add_systems(
                 FixedUpdate,
                 (
-                    clean_force_system,
-                    gravity_system,
-                    clean_rope_force_system,
-                    rope_tension_system,
-                    rope_force_to_system,
-                    integrate_force_system,
-                    integrate_momentum_system,
-                    integrate_velocity_system,
-                    player_collider_system,
-                    platform_collider_system,
-                    update_coyote_timer_system,
-                    update_wall_jump_timer_system,
+                    update_broadphase_system,
+                    physics_step_system,
                     on_collision,
-                    boundary,
                     // debug_print_rope_mesh2d,
                     // debug_print_player_world_pos,
                 )
@@ -68,3 +60,173 @@ impl Plugin for PhysicsPlugin {
             );
     }
 }
+
+type PlayerBody = (
+    Entity,
+    &'static mut Transform,
+    &'static mut Velocity,
+    &'static mut Momentum,
+    &'static mut NetForce,
+    &'static mut ControlForce,
+    &'static mut RopeForce,
+    &'static mut GroundState,
+    &'static mut JumpController,
+    &'static Mass,
+    &'static Gravity,
+    &'static PlayerCollider,
+);
+
+type SolidItem = (
+    Entity,
+    &'static Transform,
+    &'static Collider,
+    Has<OneWay>,
+    Has<Spike>,
+    Option<&'static TrampolineBounce>,
+    Option<&'static EasedPlatform>,
+);
+
+// step::step on the ECS: copy the players into a PhysicsState, run one tick of
+// the fixed clock and copy them back, turning the step's events into
+// PlayerCollisionEvent and MaxHeightReached.
+pub fn physics_step_system(
+    time: Res<Time<Fixed>>,
+    broadphase: Res<Broadphase>,
+    mut inputs: EventReader<PlayerInputEvent>,
+    mut players: Query<PlayerBody, With<Player>>,
+    ropes: Query<&Rope>,
+    solids: Query<SolidItem, Without<Player>>,
+    mut collisions: EventWriter<PlayerCollisionEvent>,
+    mut max_height: EventWriter<MaxHeightReached>,
+) {
+    let entities: Vec<Entity> = players.iter().map(|(entity, ..)| entity).collect();
+    let index = |entity: Entity| entities.iter().position(|&e| e == entity);
+
+    // keyboard input is collected every frame, so a tick can see several
+    // events for a player: the latest one counts, and so does a release in
+    // any of them.
+    let mut held: Vec<Option<BodyInput>> = vec![None; entities.len()];
+    for event in inputs.read() {
+        let Some(i) = index(event.entity) else {
+            continue;
+        };
+        let released = held[i].is_some_and(|input| input.jump_just_released);
+        held[i] = Some(BodyInput {
+            left: event.left,
+            right: event.right,
+            jump_pressed: event.jump_pressed,
+            jump_just_released: released || event.jump_just_released,
+            down: event.down,
+        });
+    }
+
+    let mut state = PhysicsState {
+        bodies: players
+            .iter()
+            .map(
+                |(
+                    _,
+                    transform,
+                    velocity,
+                    momentum,
+                    net_force,
+                    control_force,
+                    rope_force,
+                    ground,
+                    jump,
+                    mass,
+                    gravity,
+                    collider,
+                )| Body {
+                    position: transform.translation.truncate(),
+                    velocity: velocity.0,
+                    momentum: momentum.0,
+                    mass: mass.0,
+                    gravity: gravity.0,
+                    collider: collider.aabb,
+                    ground: ground.clone(),
+                    jump: jump.clone(),
+                    drop_through: false,
+                    control_force: control_force.0,
+                    rope_force: rope_force.0,
+                    net_force: net_force.0,
+                },
+            )
+            .collect(),
+        ropes: ropes
+            .iter()
+            .filter_map(|rope| {
+                Some(RopeLink {
+                    head: index(rope.attached_entity_head)?,
+                    tail: index(rope.attached_entity_tail)?,
+                    constraint: rope.constraint,
+                })
+            })
+            .collect(),
+        solids: Vec::new(),
+    };
+
+    // only the colliders near somebody's move this tick.
+    let dt = time.delta_secs();
+    let mut solid_entities: Vec<Entity> = state
+        .bodies
+        .iter()
+        .flat_map(|body| {
+            let aabb = body.collider.translated_by(body.position);
+            broadphase.query(&swept_area(&aabb, body.velocity * dt, SWEEP_MARGIN))
+        })
+        .collect();
+    solid_entities.sort_unstable();
+    solid_entities.dedup();
+    solid_entities.retain(|&entity| solids.contains(entity));
+    state.solids = solid_entities
+        .iter()
+        .filter_map(|&entity| solids.get(entity).ok())
+        .map(|(_, transform, collider, one_way, spike, trampoline, moving)| {
+            Solid::new(transform, collider, one_way, spike, trampoline, moving)
+        })
+        .collect();
+
+    let events = step::step(&mut state, &held, time.delta());
+
+    for (&entity, body) in entities.iter().zip(state.bodies) {
+        let Ok((
+            _,
+            mut transform,
+            mut velocity,
+            mut momentum,
+            mut net_force,
+            mut control_force,
+            mut rope_force,
+            mut ground,
+            mut jump,
+            ..,
+        )) = players.get_mut(entity)
+        else {
+            continue;
+        };
+        transform.translation.x = body.position.x;
+        transform.translation.y = body.position.y;
+        velocity.0 = body.velocity;
+        momentum.0 = body.momentum;
+        net_force.0 = body.net_force;
+        control_force.0 = body.control_force;
+        rope_force.0 = body.rope_force;
+        *ground = body.ground;
+        *jump = body.jump;
+    }
+
+    for event in events {
+        match event {
+            StepEvent::Touched { body, solid } => {
+                collisions.write(PlayerCollisionEvent {
+                    player: entities[body],
+                    game_object: solid_entities[solid],
+                });
+            }
+            StepEvent::MaxHeightReached { height } => {
+                max_height.write(MaxHeightReached { height });
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@
 // Author: Tingxu Chen <tic128@pitt.edu>
 // Description: Rope force + rendering system
 
-use crate::components::motion::{NetForce, RopeForce};
+use super::step::{Body, RopeLink};
 use crate::components::rope::{Rope, RopeConstraint};
 use crate::player::Player;
 use bevy::prelude::*; // 用于 query 玩家实体
@@ -24,53 +24,30 @@ pub struct RopeSprite {
     rope_entity: Entity,
 }
 
-/// 每帧先把 RopeForce 清零
-pub fn clean_rope_force_system(mut q_rope_force: Query<&mut RopeForce>) {
-    for mut rope_force in &mut q_rope_force {
-        rope_force.0 = Vec2::ZERO;
-    }
-}
-
 /// 根据 Hooke 定律计算张力，作用在两个端点
-pub fn rope_tension_system(
-    q_transforms: Query<&Transform>,
-    mut q_rope_force: Query<&mut RopeForce>,
-    q_rope: Query<&Rope>,
-) {
-    for rope in &q_rope {
-        let Ok([head_transform, tail_transform]) =
-            q_transforms.get_many([rope.attached_entity_head, rope.attached_entity_tail])
-        else {
-            continue;
-        };
-
-        let direction = (tail_transform.translation - head_transform.translation).truncate();
-        let current_length = direction.length();
+pub fn apply_rope_tension(bodies: &mut [Body], rope: &RopeLink) {
+    if rope.head == rope.tail {
+        return;
+    }
+    let (Some(head), Some(tail)) = (bodies.get(rope.head), bodies.get(rope.tail)) else {
+        return;
+    };
 
-        let force = if current_length > rope.constraint.rest_length {
-            let extension = current_length - rope.constraint.rest_length;
-            let k = rope.constraint.spring_constant;
-            let force_magnitude = k * extension;
-            let force_direction = direction.normalize();
-            force_direction * force_magnitude
-        } else {
-            Vec2::ZERO
-        };
+    let direction = tail.position - head.position;
+    let current_length = direction.length();
 
-        if let Ok([mut head_force, mut tail_force]) =
-            q_rope_force.get_many_mut([rope.attached_entity_head, rope.attached_entity_tail])
-        {
-            head_force.0 += force;
-            tail_force.0 -= force;
-        }
-    }
-}
+    let force = if current_length > rope.constraint.rest_length {
+        let extension = current_length - rope.constraint.rest_length;
+        let k = rope.constraint.spring_constant;
+        let force_magnitude = k * extension;
+        let force_direction = direction.normalize();
+        force_direction * force_magnitude
+    } else {
+        Vec2::ZERO
+    };
 
-/// 把 RopeForce 累加到 NetForce
-pub fn rope_force_to_system(mut query: Query<(&RopeForce, &mut NetForce)>) {
-    for (rope_force, mut net_force) in &mut query {
-        net_force.0 += rope_force.0;
-    }
+    bodies[rope.head].rope_force += force;
+    bodies[rope.tail].rope_force -= force;
 }
 
 // ==================== 绳子可视化部分 ====================
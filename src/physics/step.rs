@@ -0,0 +1,436 @@
+// Headless physics step.
+//
+// one fixed tick of the climbing simulation on plain data: player controls,
+// gravity, rope tension, riding moving platforms, integration, player/player and (swept) player/platform
+// collisions, the coyote and wall-jump timers and the level boundary. nothing
+// in here reads the ECS or a clock, dt is an argument, so the same inputs always
+// give the same result. PhysicsPlugin runs it on FixedUpdate (see
+// physics_step_system), client prediction (multiplayer::prediction) replays it,
+// and training or tests can call it directly.
+use bevy::math::bounding::{Aabb2d, BoundingVolume};
+use bevy::prelude::*;
+use std::time::Duration;
+
+use super::collision::{collide_players, collide_solids, ride_platforms, tick_timers};
+use super::gravity::apply_gravity;
+use super::integrate::{boundary, integrate};
+use super::rope_force::apply_rope_tension;
+use crate::components::motion::{GroundState, JumpController};
+use crate::components::rope::RopeConstraint;
+use crate::config::player::{PLAYER_SIZE, PLAYER_SPAWN_MASS};
+use crate::map::{Collider, EasedPlatform, TrampolineBounce};
+use crate::player::player_control::apply_controls;
+
+// what a player holds during one tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BodyInput {
+    pub left: bool,
+    pub right: bool,
+    pub jump_pressed: bool,
+    pub jump_just_released: bool,
+    // falls through one-way platforms.
+    pub down: bool,
+}
+
+// a climber, the same fields as PlayerBundle.
+#[derive(Debug, Clone)]
+pub struct Body {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub momentum: Vec2,
+    pub mass: f32,
+    pub gravity: bool,
+    // centred on position.
+    pub collider: Aabb2d,
+    pub ground: GroundState,
+    pub jump: JumpController,
+    // holding down this tick, so one-way platforms don't catch it.
+    pub drop_through: bool,
+    // what acted on the body during the last step.
+    pub control_force: Vec2,
+    pub rope_force: Vec2,
+    pub net_force: Vec2,
+}
+
+impl Body {
+    // a freshly spawned player at rest.
+    pub fn at(position: Vec2) -> Self {
+        Self {
+            position,
+            velocity: Vec2::ZERO,
+            momentum: Vec2::ZERO,
+            mass: PLAYER_SPAWN_MASS,
+            gravity: true,
+            collider: Aabb2d::new(Vec2::ZERO, PLAYER_SIZE * 0.5),
+            ground: GroundState::default(),
+            jump: JumpController::default(),
+            drop_through: false,
+            control_force: Vec2::ZERO,
+            rope_force: Vec2::ZERO,
+            net_force: Vec2::ZERO,
+        }
+    }
+}
+
+// a rope between two bodies, by index.
+#[derive(Debug, Clone, Copy)]
+pub struct RopeLink {
+    pub head: usize,
+    pub tail: usize,
+    pub constraint: RopeConstraint,
+}
+
+// anything with a map Collider.
+#[derive(Debug, Clone, Copy)]
+pub struct Solid {
+    // in world space.
+    pub aabb: Aabb2d,
+    // only solid from above (see map::OneWay).
+    pub one_way: bool,
+    pub spike: bool,
+    pub trampoline: Option<f32>,
+    // how far it moved this tick (already in aabb); zero unless it's a
+    // moving platform.
+    pub motion: Vec2,
+}
+
+impl Solid {
+    pub fn new(
+        transform: &Transform,
+        collider: &Collider,
+        one_way: bool,
+        spike: bool,
+        trampoline: Option<&TrampolineBounce>,
+        moving: Option<&EasedPlatform>,
+    ) -> Self {
+        Self {
+            aabb: collider
+                .aabb
+                .translated_by(transform.translation.truncate()),
+            one_way,
+            spike,
+            trampoline: trampoline.map(|bounce| bounce.0),
+            motion: moving.map_or(Vec2::ZERO, |platform| platform.displacement),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PhysicsState {
+    pub bodies: Vec<Body>,
+    pub ropes: Vec<RopeLink>,
+    pub solids: Vec<Solid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepEvent {
+    // a body ran into solids[solid].
+    Touched { body: usize, solid: usize },
+    // a spike or the top of the level; the round is over.
+    MaxHeightReached { height: f32 },
+}
+
+// advance `state` by `dt`. `inputs[i]` drives bodies[i]; a body without one
+// gets no control force this tick.
+pub fn step(
+    state: &mut PhysicsState,
+    inputs: &[Option<BodyInput>],
+    dt: Duration,
+) -> Vec<StepEvent> {
+    let secs = dt.as_secs_f32();
+    let mut events = Vec::new();
+
+    for (i, body) in state.bodies.iter_mut().enumerate() {
+        body.net_force = Vec2::ZERO;
+        body.rope_force = Vec2::ZERO;
+        body.drop_through = false;
+        if let Some(input) = inputs.get(i).copied().flatten() {
+            apply_controls(body, &input, secs);
+        }
+        apply_gravity(body);
+    }
+    for rope in &state.ropes {
+        apply_rope_tension(&mut state.bodies, rope);
+    }
+    ride_platforms(&mut state.bodies, &state.ropes, &state.solids, secs);
+    // solids are swept along the whole move, from where each body started.
+    let starts: Vec<Vec2> = state.bodies.iter().map(|body| body.position).collect();
+    for body in &mut state.bodies {
+        integrate(body, secs);
+    }
+
+    collide_players(&mut state.bodies, secs);
+    for (i, (body, start)) in state.bodies.iter_mut().zip(starts).enumerate() {
+        collide_solids(i, body, start, &state.solids, &mut events);
+    }
+    for body in &mut state.bodies {
+        tick_timers(body, dt);
+        boundary(body, &mut events);
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::player::PLAYER_LENGTH;
+
+    const DT: Duration = Duration::from_micros(15625);
+
+    fn floor() -> Solid {
+        Solid {
+            aabb: Aabb2d::new(Vec2::new(400.0, 100.0), Vec2::new(400.0, 10.0)),
+            one_way: false,
+            spike: false,
+            trampoline: None,
+            motion: Vec2::ZERO,
+        }
+    }
+
+    fn run(state: &mut PhysicsState, input: BodyInput, ticks: usize) -> Vec<StepEvent> {
+        let inputs = vec![Some(input); state.bodies.len()];
+        (0..ticks).flat_map(|_| step(state, &inputs, DT)).collect()
+    }
+
+    #[test]
+    fn the_same_inputs_give_the_same_world() {
+        let state = PhysicsState {
+            bodies: vec![
+                Body::at(Vec2::new(300.0, 200.0)),
+                Body::at(Vec2::new(700.0, 400.0)),
+            ],
+            ropes: vec![RopeLink {
+                head: 0,
+                tail: 1,
+                constraint: RopeConstraint::default(),
+            }],
+            solids: vec![floor()],
+        };
+        let input = BodyInput {
+            right: true,
+            jump_pressed: true,
+            ..Default::default()
+        };
+        let (mut a, mut b) = (state.clone(), state);
+        run(&mut a, input, 90);
+        run(&mut b, input, 90);
+        for (a, b) in a.bodies.iter().zip(&b.bodies) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.momentum, b.momentum);
+        }
+    }
+
+    #[test]
+    fn bodies_land_on_solids_and_the_coyote_timer_runs_once_airborne() {
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(300.0, 200.0))],
+            solids: vec![floor()],
+            ..Default::default()
+        };
+        let events = run(&mut state, BodyInput::default(), 120);
+        let body = &state.bodies[0];
+        assert!(body.ground.is_grounded);
+        assert!((body.position.y - (110.0 + PLAYER_LENGTH / 2.0)).abs() < 2.0);
+        assert!(events.contains(&StepEvent::Touched { body: 0, solid: 0 }));
+        assert_eq!(body.ground.coyote_timer.elapsed(), Duration::ZERO);
+
+        // walk off the edge.
+        state.bodies[0].position.x = 1000.0;
+        run(&mut state, BodyInput::default(), 3);
+        assert!(!state.bodies[0].ground.is_grounded);
+        assert!(state.bodies[0].ground.coyote_timer.elapsed() > Duration::ZERO);
+    }
+
+    #[test]
+    fn platforms_let_bodies_through_from_below_and_ropes_pull() {
+        let mut platform = floor();
+        platform.one_way = true;
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(300.0, 60.0))],
+            solids: vec![platform],
+            ..Default::default()
+        };
+        state.bodies[0].momentum.y = 1200.0 * state.bodies[0].mass;
+        run(&mut state, BodyInput::default(), 10);
+        assert!(state.bodies[0].position.y > 110.0);
+
+        // stretched well past its rest length, both ends get pulled in.
+        let mut state = PhysicsState {
+            bodies: vec![
+                Body::at(Vec2::new(100.0, 2000.0)),
+                Body::at(Vec2::new(1100.0, 2000.0)),
+            ],
+            ropes: vec![RopeLink {
+                head: 0,
+                tail: 1,
+                constraint: RopeConstraint::default(),
+            }],
+            ..Default::default()
+        };
+        run(&mut state, BodyInput::default(), 1);
+        assert!(state.bodies[0].velocity.x > 0.0);
+        assert!(state.bodies[1].velocity.x < 0.0);
+    }
+    #[test]
+    fn fast_bodies_stop_on_thin_platforms_instead_of_tunneling() {
+        // a 21 px platform as in level1, and a fall covering ~160 px a tick.
+        let thin = Solid {
+            aabb: Aabb2d::new(Vec2::new(400.0, 1000.0), Vec2::new(250.0, 10.5)),
+            one_way: true,
+            spike: false,
+            trampoline: None,
+            motion: Vec2::ZERO,
+        };
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(400.0, 1100.0))],
+            solids: vec![thin],
+            ..Default::default()
+        };
+        state.bodies[0].momentum.y = -10_000.0 * state.bodies[0].mass;
+        let events = run(&mut state, BodyInput::default(), 1);
+        let body = &state.bodies[0];
+        assert_eq!(body.position.y, 1010.5 + PLAYER_LENGTH / 2.0);
+        assert_eq!(body.velocity.y, 0.0);
+        assert!(body.ground.is_grounded);
+        assert_eq!(events, [StepEvent::Touched { body: 0, solid: 0 }]);
+    }
+
+    #[test]
+    fn bodies_slide_along_floors_and_over_seams_between_tiles() {
+        let tile = |x: f32| Solid {
+            aabb: Aabb2d::new(Vec2::new(x, 100.0), Vec2::new(100.0, 10.0)),
+            one_way: false,
+            spike: false,
+            trampoline: None,
+            motion: Vec2::ZERO,
+        };
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(300.0, 110.0 + PLAYER_LENGTH / 2.0))],
+            solids: vec![tile(300.0), tile(500.0)],
+            ..Default::default()
+        };
+        let right = BodyInput {
+            right: true,
+            ..Default::default()
+        };
+        run(&mut state, right, 60);
+        let body = &state.bodies[0];
+        assert!(body.position.x > 450.0, "{}", body.position.x);
+        assert_eq!(body.position.y, 110.0 + PLAYER_LENGTH / 2.0);
+        assert!(body.ground.is_grounded);
+    }
+
+    #[test]
+    fn moving_platforms_carry_riders_and_jumps_keep_their_velocity() {
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(300.0, 110.0 + PLAYER_LENGTH / 2.0))],
+            solids: vec![floor()],
+            ..Default::default()
+        };
+        state.bodies[0].ground.is_grounded = true;
+        state.solids[0].motion = Vec2::new(2.0, 1.0);
+        let mut tick = |state: &mut PhysicsState, input| {
+            state.solids[0].aabb = state.solids[0].aabb.translated_by(state.solids[0].motion);
+            run(state, input, 1);
+        };
+
+        for _ in 0..30 {
+            tick(&mut state, BodyInput::default());
+        }
+        let body = &state.bodies[0];
+        assert!(body.ground.is_grounded);
+        let on_top = Vec2::new(360.0, 140.0 + PLAYER_LENGTH / 2.0);
+        assert!(body.position.distance(on_top) < 0.1, "{}", body.position);
+
+        let jump = BodyInput {
+            jump_pressed: true,
+            ..Default::default()
+        };
+        tick(&mut state, jump);
+        let body = &state.bodies[0];
+        assert!(!body.ground.is_grounded);
+        assert!((body.velocity.x - 2.0 / DT.as_secs_f32()).abs() < 0.1);
+    }
+
+    #[test]
+    fn solid_platforms_are_ceilings_and_one_way_ones_let_bodies_drop_through() {
+        // jumping into a solid platform from below bumps the head.
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(300.0, 60.0))],
+            solids: vec![floor()],
+            ..Default::default()
+        };
+        state.bodies[0].momentum.y = 1200.0 * state.bodies[0].mass;
+        let events = run(&mut state, BodyInput::default(), 10);
+        assert!(state.bodies[0].position.y <= 90.0 - PLAYER_LENGTH / 2.0 + 0.01);
+        assert!(events.contains(&StepEvent::Touched { body: 0, solid: 0 }));
+
+        // standing on a one-way platform, holding down drops through it.
+        let mut platform = floor();
+        platform.one_way = true;
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(Vec2::new(300.0, 110.0 + PLAYER_LENGTH / 2.0))],
+            solids: vec![platform],
+            ..Default::default()
+        };
+        run(&mut state, BodyInput::default(), 10);
+        assert!(state.bodies[0].ground.is_grounded);
+        let down = BodyInput {
+            down: true,
+            ..Default::default()
+        };
+        run(&mut state, down, 30);
+        assert!(!state.bodies[0].ground.is_grounded);
+        assert!(state.bodies[0].position.y < 90.0);
+    }
+
+    #[test]
+    fn bodies_holding_down_land_on_the_platform_they_spawn() {
+        use crate::map::OneWay;
+        use crate::player::Player;
+        use crate::player::bundle::PlayerControls;
+        use crate::player::player_control::{SpawnedPlatform, platform_spawn_system};
+
+        // down in mid-air spawns the rescue platform.
+        let start = Vec2::new(300.0, 500.0);
+        let mut app = App::new();
+        let mut keys = ButtonInput::<KeyCode>::default();
+        keys.press(KeyCode::KeyS);
+        app.insert_resource(keys)
+            .add_systems(Update, platform_spawn_system);
+        app.world_mut().spawn((
+            Player::Local(0),
+            Transform::from_translation(start.extend(0.0)),
+            JumpController::default(),
+            GroundState::default(),
+            PlayerControls {
+                up: KeyCode::KeyW,
+                down: KeyCode::KeyS,
+                left: KeyCode::KeyA,
+                right: KeyCode::KeyD,
+            },
+        ));
+        app.update();
+        let world = app.world_mut();
+        let (transform, collider, one_way) = world
+            .query_filtered::<(&Transform, &Collider, Has<OneWay>), With<SpawnedPlatform>>()
+            .single(world)
+            .unwrap();
+        let solid = Solid::new(transform, collider, one_way, false, None, None);
+
+        // still holding down, it catches the fall.
+        let mut state = PhysicsState {
+            bodies: vec![Body::at(start)],
+            solids: vec![solid],
+            ..Default::default()
+        };
+        let down = BodyInput {
+            down: true,
+            ..Default::default()
+        };
+        run(&mut state, down, 60);
+        let body = &state.bodies[0];
+        assert!(body.ground.is_grounded);
+        assert_eq!(body.position.y, solid.aabb.max.y + PLAYER_LENGTH / 2.0);
+    }
+}
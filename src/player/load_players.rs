@@ -30,6 +30,15 @@ pub enum Player {
     Npc(usize),
 }
 
+impl Player {
+    // player number regardless of who is driving it.
+    pub fn slot(&self) -> usize {
+        match self {
+            Player::Local(id) | Player::Net(id) | Player::Npc(id) => *id,
+        }
+    }
+}
+
 pub fn spawn_players(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
@@ -122,9 +131,12 @@ pub fn spawn_players(
             camera_follow_player = local_player_number;
             // insert net player marker for all players that arent LocalPlayer
             // insert localPlayer marker component for this player
-            commands
-                .entity(*player_list[local_player_number])
-                .insert((wasd_controls.unwrap(), Player::Local(local_player_number)));
+            // headless clients (multiplayer::harness) have no key bindings.
+            let mut local = commands.entity(*player_list[local_player_number]);
+            local.insert(Player::Local(local_player_number));
+            if let Some(controls) = wasd_controls {
+                local.insert(controls);
+            }
             player_list
                 .iter()
                 .enumerate()
@@ -133,6 +145,11 @@ pub fn spawn_players(
                     commands.entity(**entity).insert(Player::Net(i));
                 });
         }
+        GameMode::Spectate => {
+            player_list.iter().enumerate().for_each(|(i, entity)| {
+                commands.entity(**entity).insert(Player::Net(i));
+            });
+        }
         GameMode::Simulated => {
             player_list.iter().enumerate().for_each(|(i, entity)| {
                 commands.entity(**entity).insert(Player::Local(i));
@@ -7,7 +7,7 @@ use crate::config::MyAppState;
 
 use self::player_control::{
     PlayerInputEvent, despawn_platform_system, platform_spawn_system,
-    player_input_collection_system, player_movement_input_system,
+    player_input_collection_system,
 };
 
 use crate::player::load_players::reset_player;
@@ -19,18 +19,12 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
+        // read by the physics step, see physics::physics_step_system.
         app.add_event::<PlayerInputEvent>();
 
-        #[cfg(feature = "server")]
-        {
-            app.add_systems(Startup, spawn_players);
-        }
-        #[cfg(feature = "client")]
-        {
-            app.add_systems(OnEnter(MyAppState::InGame), spawn_players);
-        }
+        // players are state scoped, so every round spawns them again.
+        app.add_systems(OnEnter(MyAppState::InGame), spawn_players);
 
-        app.add_systems(FixedUpdate, (player_movement_input_system).chain());
         app.add_systems(
             Update,
             (platform_spawn_system, despawn_platform_system).run_if(in_state(MyAppState::InGame)),
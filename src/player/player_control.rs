@@ -3,10 +3,11 @@
 // Author: Tingxu Chen <tic128@pitt.edu>
 // Description: <Systems for player control>
 
-use crate::components::motion::{ControlForce, GroundState, JumpController, NetForce, Velocity};
+use crate::components::motion::{GroundState, JumpController};
 use crate::config::physics::{PLAYER_CONTROL_SPEED_LIMIT, PLAYER_JUMP_FORCE, PLAYER_MOVE_FORCE};
 use crate::map::Collider;
 use crate::map::Platform;
+use crate::physics::step::{Body, BodyInput};
 use crate::player::Player;
 use bevy::math::VectorSpace;
 use bevy::math::bounding::Aabb2d;
@@ -20,38 +21,26 @@ pub struct PlayerInputEvent {
     pub right: bool,
     pub jump_pressed: bool,
     pub jump_just_released: bool,
+    pub down: bool,
 }
 
-pub fn player_movement_input_system(
-    time: Res<Time>,
-    mut reader: EventReader<PlayerInputEvent>,
-    mut query: Query<(
-        &mut Velocity,
-        &mut ControlForce,
-        &mut NetForce,
-        &mut JumpController,
-        &mut GroundState,
-    )>,
-) {
-    for event in reader.read() {
-        if let Ok((velocity, mut control_force, mut net_force, mut jump_controller, ground_state)) =
-            query.get_mut(event.entity)
-        {
-            control_force.0.y = 0.0;
+/// one tick of a player's controls, added to the net force. physics::step
+/// calls this for every body with an input.
+pub fn apply_controls(body: &mut Body, input: &BodyInput, dt: f32) {
+    body.control_force.y = 0.0;
+    body.drop_through = input.down;
 
-            apply_horizontal_movement(&velocity, &mut control_force, event);
+    apply_horizontal_movement(body.velocity, &mut body.control_force, input);
 
-            apply_jump(
-                &time,
-                &mut control_force,
-                &mut jump_controller,
-                &ground_state,
-                event,
-            );
+    apply_jump(
+        dt,
+        &mut body.control_force,
+        &mut body.jump,
+        &body.ground,
+        input,
+    );
 
-            net_force.0 += control_force.0;
-        }
-    }
+    body.net_force += body.control_force;
 }
 
 /// Collects keyboard input every `Update` frame and emits `PlayerInputEvent`s.
@@ -72,45 +61,42 @@ pub fn player_input_collection_system(
             right: keyboard_input.pressed(player_controls.right),
             jump_pressed: keyboard_input.pressed(player_controls.up),
             jump_just_released: keyboard_input.just_released(player_controls.up),
+            down: keyboard_input.pressed(player_controls.down),
         });
     }
 }
 
-fn apply_horizontal_movement(
-    velocity: &Velocity,
-    control_force: &mut ControlForce,
-    event: &PlayerInputEvent,
-) {
-    control_force.0.x = 0.0;
+fn apply_horizontal_movement(velocity: Vec2, control_force: &mut Vec2, event: &BodyInput) {
+    control_force.x = 0.0;
 
     let resistance = PLAYER_MOVE_FORCE / PLAYER_CONTROL_SPEED_LIMIT;
-    let resistance_force = resistance * velocity.0.x.abs();
+    let resistance_force = resistance * velocity.x.abs();
 
     if event.left {
-        if velocity.0.x > -PLAYER_CONTROL_SPEED_LIMIT {
-            control_force.0.x = -PLAYER_MOVE_FORCE;
-            if velocity.0.x < 0.0 {
-                control_force.0.x += resistance_force;
+        if velocity.x > -PLAYER_CONTROL_SPEED_LIMIT {
+            control_force.x = -PLAYER_MOVE_FORCE;
+            if velocity.x < 0.0 {
+                control_force.x += resistance_force;
             }
         }
     }
 
     if event.right {
-        if velocity.0.x < PLAYER_CONTROL_SPEED_LIMIT {
-            control_force.0.x = PLAYER_MOVE_FORCE;
-            if velocity.0.x > 0.0 {
-                control_force.0.x -= resistance_force;
+        if velocity.x < PLAYER_CONTROL_SPEED_LIMIT {
+            control_force.x = PLAYER_MOVE_FORCE;
+            if velocity.x > 0.0 {
+                control_force.x -= resistance_force;
             }
         }
     }
 }
 
 fn apply_jump(
-    time: &Time,
-    control_force: &mut ControlForce,
+    dt: f32,
+    control_force: &mut Vec2,
     jump_controller: &mut JumpController,
     ground_state: &GroundState,
-    event: &PlayerInputEvent,
+    event: &BodyInput,
 ) {
     let can_ground_jump = ground_state.is_grounded || !ground_state.coyote_timer.finished();
     let can_wall_jump = !ground_state.is_grounded
@@ -121,11 +107,11 @@ fn apply_jump(
     if event.jump_pressed && !jump_controller.is_jumping {
         // Check grounded jump first
         if can_ground_jump {
-            control_force.0.y = PLAYER_JUMP_FORCE;
+            control_force.y = PLAYER_JUMP_FORCE;
             jump_controller.is_jumping = true;
             jump_controller.jump_time_elapsed = 0.0;
         } else if can_wall_jump {
-            control_force.0.y = PLAYER_JUMP_FORCE;
+            control_force.y = PLAYER_JUMP_FORCE;
 
             // Consume wall jump
             jump_controller.can_wall_jump = false;
@@ -140,10 +126,10 @@ fn apply_jump(
         && event.jump_pressed
         && jump_controller.jump_time_elapsed < jump_controller.max_jump_duration
     {
-        jump_controller.jump_time_elapsed += time.delta_secs();
+        jump_controller.jump_time_elapsed += dt;
 
         // Apply smaller force while holding
-        control_force.0.y += PLAYER_JUMP_FORCE * jump_controller.jump_multiplier;
+        control_force.y += PLAYER_JUMP_FORCE * jump_controller.jump_multiplier;
     }
     // End the jump either by letting go or time running out
     if jump_controller.is_jumping && event.jump_just_released
@@ -210,6 +196,8 @@ pub fn platform_spawn_system(
                 },
                 Transform::from_translation(platform_pos.extend(0.0)),
                 collider,
+                // solid, not OneWay: down spawns it, and holding down must
+                // not drop straight through it again.
                 Platform,
                 SpawnedPlatform,
                 DespawnTimer::default(),
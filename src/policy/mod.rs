@@ -16,8 +16,8 @@ use self::action::make_qlearning_system;
 use self::debug::make_gizmos;
 use self::qtable::QTable;
 
-const QTABLE_PATH: &str = "assets/qtable.csv";
-const QTABLE_P2: &str = "assets/qtableSec.csv";
+pub const QTABLE_PATH: &str = "assets/qtable.csv";
+pub const QTABLE_P2: &str = "assets/qtableSec.csv";
 pub struct PolicyPlugin;
 impl Plugin for PolicyPlugin {
     fn build(&self, app: &mut App) {